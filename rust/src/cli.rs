@@ -1,12 +1,47 @@
+use std::{path::PathBuf, time::Duration};
+
 use argh::FromArgs;
 
+use crate::config::{parse_duration, ByteSize, Pattern, SignalLadder, Threshold};
+use crate::log::{Filter, Format, Target};
+use crate::memory::MemorySource;
+use crate::metrics;
+
 #[derive(FromArgs)]
 /// Lightweight process killer daemon for out-of-memory scenarios
 pub struct CommandLineArgs {
-    /// toggles on verbose output
+    /// runs the given command instead of the daemon
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+
+    /// path to the configuration file, whose values are overridden by command-line flags (default: /etc/bustd.toml, if it exists)
+    #[argh(option, short = 'c')]
+    pub config: Option<PathBuf>,
+
+    /// prints the effective configuration and exits
+    #[argh(switch)]
+    pub print_config: bool,
+
+    /// logs at the debug level at least, same as --log-level debug
     #[argh(switch, short = 'V')]
     pub verbose: bool,
 
+    /// log level among error, warn, info, debug and trace, optionally followed by per-module levels, e.g. info,kill=debug (default: info)
+    #[argh(option)]
+    pub log_level: Option<Filter>,
+
+    /// format of the log output, text or json (default: text)
+    #[argh(option)]
+    pub log_format: Option<Format>,
+
+    /// where to log to: console, file, journald or syslog (default: console with --no-daemon, file otherwise)
+    #[argh(option)]
+    pub log_target: Option<Target>,
+
+    /// file to log to when logging to a file (default: /var/log/bustd.out as root, /tmp/bustd.out otherwise)
+    #[argh(option)]
+    pub log_file: Option<PathBuf>,
+
     /// when set, the process will not be daemonized
     #[argh(switch, short = 'n')]
     pub no_daemon: bool,
@@ -15,9 +50,157 @@ pub struct CommandLineArgs {
     #[argh(switch, short = 'g')]
     pub kill_pgroup: bool,
 
-    /// sets the PSI value on which, if surpassed, a process will be killed
-    #[argh(option, short = 'p', long = "psi", default = "25.0")]
-    pub cutoff_psi: f32, // TODO: responsitivity multiplier?
+    /// choose and log victims without ever killing them
+    #[argh(switch)]
+    pub dry_run: bool,
+
+    /// minimum time between two kills, or two would-be kills in dry-run mode (default: 0s)
+    #[argh(option, from_str_fn(parse_duration))]
+    pub kill_cooldown: Option<Duration>,
+
+    /// command run through `sh -c` before a victim is killed, which is waited on for up to 250ms
+    #[argh(option)]
+    pub pre_kill: Option<String>,
+
+    /// command run through `sh -c` after a victim is killed, in the background
+    #[argh(option)]
+    pub post_kill: Option<String>,
+
+    /// time after which hook commands are killed (default: 5s)
+    #[argh(option, from_str_fn(parse_duration))]
+    pub hook_timeout: Option<Duration>,
+
+    /// signals sent to victims in turn, with how long to wait after each, e.g. "SIGTERM 5s SIGINT 2s SIGKILL" (default: "SIGTERM 500ms SIGKILL")
+    #[argh(option)]
+    pub signal_ladder: Option<SignalLadder>,
+
+    /// how long to wait for a victim to exit once sent SIGKILL (default: 10s)
+    #[argh(option, from_str_fn(parse_duration))]
+    pub exit_timeout: Option<Duration>,
+
+    /// sends a desktop notification to the owner of each killed process
+    #[argh(switch)]
+    pub notify: bool,
+
+    /// the `some avg10` PSI value above which the owner of the next victim is warned, if notifications are on (default: unset)
+    #[argh(option)]
+    pub notify_warn_psi: Option<f32>,
+
+    /// minimum time between two warning notifications (default: 1m)
+    #[argh(option, from_str_fn(parse_duration))]
+    pub notify_warn_interval: Option<Duration>,
+
+    /// serves Prometheus metrics over HTTP on the given host:port or unix:/path/to/socket
+    #[argh(option)]
+    pub metrics_listen: Option<metrics::Address>,
+
+    /// listen on the control socket
+    #[argh(switch)]
+    pub control: bool,
+
+    /// path of the control socket (default: /run/bustd/control.sock as root, $XDG_RUNTIME_DIR/bustd/control.sock otherwise)
+    #[argh(option)]
+    pub control_socket: Option<PathBuf>,
+
+    /// group whose members may use the control socket, besides the daemon's user, as long as they look for it at the same path
+    #[argh(option)]
+    pub control_group: Option<String>,
+
+    /// sets the PSI value on which, if surpassed, a process will be killed (default: 25.0)
+    #[argh(option, short = 'p', long = "psi")]
+    pub cutoff_psi: Option<f32>, // TODO: responsitivity multiplier?
+
+    /// sets the `full avg10` PSI value on which, if surpassed, a process will be killed (default: unset)
+    #[argh(option, long = "psi-full")]
+    pub cutoff_psi_full: Option<f32>,
+
+    /// sets the `some avg60` PSI value on which, if surpassed, a process will be killed (default: unset)
+    #[argh(option, long = "psi-avg60")]
+    pub cutoff_psi_avg60: Option<f32>,
+
+    /// sets the `full avg10` PSI value on which, if surpassed, victims are sent SIGKILL right away, skipping the signal ladder (default: unset)
+    #[argh(option)]
+    pub sigkill_psi_full: Option<f32>,
+
+    /// sets the stall percentage, measured over the last --psi-window-length, on which, if surpassed, a process will be killed (default: unset)
+    #[argh(option, long = "psi-window")]
+    pub cutoff_psi_window: Option<f32>,
+
+    /// length of the window used by --psi-window, e.g. 500ms (default: 500ms)
+    #[argh(option, from_str_fn(parse_duration))]
+    pub psi_window_length: Option<Duration>,
+
+    /// where procfs is mounted, e.g. /host/proc inside of a container (default: /proc)
+    #[argh(option)]
+    pub proc_root: Option<PathBuf>,
+
+    /// where available RAM is read from: sysinfo, meminfo (MemAvailable) or custom (MemFree + Cached + SReclaimable - Shmem) (default: sysinfo)
+    #[argh(option)]
+    pub memory_source: Option<MemorySource>,
+
+    /// available RAM, as a percentage (e.g. 15%) or size (e.g. 512M), under which PSI starts being checked (default: 15%)
+    #[argh(option)]
+    pub near_terminal: Option<Threshold>,
+
+    /// available RAM considered terminal when calculating the adaptive sleep time (default: 10%)
+    #[argh(option)]
+    pub ram_terminal: Option<Threshold>,
+
+    /// available swap considered terminal when calculating the adaptive sleep time (default: 10%)
+    #[argh(option)]
+    pub swap_terminal: Option<Threshold>,
+
+    /// maximum expected RAM fill rate per second, e.g. 6G (default: 6000000K)
+    #[argh(option)]
+    pub ram_fill_rate: Option<ByteSize>,
+
+    /// maximum expected swap fill rate per second, e.g. 800M (default: 800000K)
+    #[argh(option)]
+    pub swap_fill_rate: Option<ByteSize>,
+
+    /// minimum time to sleep between memory checks, e.g. 100ms (default: 100ms)
+    #[argh(option, from_str_fn(parse_duration))]
+    pub min_sleep: Option<Duration>,
+
+    /// maximum time to sleep between memory checks, e.g. 1s (default: 1s)
+    #[argh(option, from_str_fn(parse_duration))]
+    pub max_sleep: Option<Duration>,
+
+    /// always use adaptive sleep instead of waiting for kernel PSI triggers
+    #[argh(switch)]
+    pub no_psi_trigger: bool,
+
+    /// stall time within the PSI trigger window that wakes the daemon up, e.g. 150ms (default: 150ms)
+    #[argh(option, from_str_fn(parse_duration))]
+    pub psi_trigger_stall: Option<Duration>,
+
+    /// time window of the PSI trigger, between 500ms and 10s (default: 1s)
+    #[argh(option, from_str_fn(parse_duration))]
+    pub psi_trigger_window: Option<Duration>,
+
+    /// longest time to wait for the PSI trigger before checking memory anyway (default: 10s)
+    #[argh(option, from_str_fn(parse_duration))]
+    pub psi_trigger_timeout: Option<Duration>,
+
+    /// processes whose name, executable path or command line match this regular expression are more likely to be killed
+    #[argh(option)]
+    pub prefer: Option<Pattern>,
+
+    /// processes whose name, executable path or command line match this regular expression are less likely to be killed
+    #[argh(option)]
+    pub avoid: Option<Pattern>,
+
+    /// how much is added to the oom_score of processes matching --prefer, up to 1000 (default: 300)
+    #[argh(option)]
+    pub prefer_bias: Option<u16>,
+
+    /// how much is subtracted from the oom_score of processes matching --avoid, up to 1000 (default: 300)
+    #[argh(option)]
+    pub avoid_bias: Option<u16>,
+
+    /// the file per-process rules are read from
+    #[argh(option)]
+    pub rules_file: Option<PathBuf>,
 
     #[cfg(feature = "glob-ignore")]
     /// all processes whose names match any of the supplied vertical bar-separated glob patterns will never be chosen to be killed
@@ -30,6 +213,45 @@ pub struct CommandLineArgs {
     pub ignored: Option<Vec<glob::Pattern>>,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Status(StatusArgs),
+    Rank(RankArgs),
+    Explain(ExplainArgs),
+}
+
+#[derive(FromArgs)]
+/// Shows the state of the running daemon
+#[argh(subcommand, name = "status")]
+pub struct StatusArgs {
+    /// how many of the latest kills to show (default: 5)
+    #[argh(option, default = "5")]
+    pub kills: usize,
+}
+
+#[derive(FromArgs)]
+/// Lists the processes that would be killed first, in order
+#[argh(subcommand, name = "rank")]
+pub struct RankArgs {
+    /// how many eligible processes to list, along with the excluded ones ranking among them (default: 10)
+    #[argh(option, default = "10")]
+    pub top: usize,
+
+    /// prints the list in JSON
+    #[argh(switch)]
+    pub json: bool,
+}
+
+#[derive(FromArgs)]
+/// Shows why a process would or wouldn't be killed
+#[argh(subcommand, name = "explain")]
+pub struct ExplainArgs {
+    /// the PID of the process
+    #[argh(positional)]
+    pub pid: u32,
+}
+
 #[cfg(feature = "glob-ignore")]
 fn parse_unkillables(arg: &str) -> Result<Vec<glob::Pattern>, String> {
     let unkillables: Result<Vec<_>, _> = arg.split('|').map(glob::Pattern::new).collect();
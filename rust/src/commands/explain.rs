@@ -0,0 +1,353 @@
+//! `bustd explain`, which shows how a process fares against the rules victims are chosen by
+
+use std::fmt::{self, Write};
+
+use crate::cli::ExplainArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::kill::{self, Candidate, Exclusion};
+use crate::utils;
+
+pub fn run(args: &ExplainArgs, config: &Config) -> Result<()> {
+    if args.pid == 1 {
+        println!("PID 1 is init, which is never considered.");
+        return Ok(());
+    }
+
+    let (mut proc_buf, mut buf) = ([0_u8; 128], [0_u8; 256]);
+    let candidates = kill::rank_candidates(&mut proc_buf, &mut buf, config)?;
+    let target = candidates
+        .iter()
+        .find(|candidate| candidate.process.pid == args.pid)
+        .ok_or(Error::ProcessNotFound("explain"))?;
+
+    // Every rule is checked, rather than stopping at the first one that excludes the process
+    let checks: Vec<_> = Exclusion::ALL
+        .iter()
+        .map(|&check| {
+            let applies = check.applies(
+                &target.process,
+                target.vm_rss_kib,
+                target.oom_score_adj,
+                target.rule.as_ref(),
+                &mut buf,
+                config,
+            );
+            (check, applies)
+        })
+        .collect();
+    let eligible: Vec<_> = candidates
+        .iter()
+        .filter(|candidate| candidate.exclusion.is_none())
+        .collect();
+    let user = utils::get_user_name(target.uid).unwrap_or_else(|| target.uid.to_string());
+
+    let mut out = String::new();
+    // Writing to a String can't fail
+    let _ = write_explanation(&mut out, target, &user, &checks, &eligible);
+    print!("{}", out);
+
+    Ok(())
+}
+
+/// Describes how the process fared against the check
+fn describe_check(candidate: &Candidate, check: Exclusion, applies: bool) -> String {
+    let rule_line = candidate.rule.as_ref().map(|rule| rule.line);
+
+    match (check, applies) {
+        #[cfg(feature = "glob-ignore")]
+        (Exclusion::Unkillable, true) => {
+            format!("\"{}\" matches an unkillables pattern", candidate.comm)
+        }
+        #[cfg(feature = "glob-ignore")]
+        (Exclusion::Unkillable, false) => {
+            format!("\"{}\" matches no unkillables pattern", candidate.comm)
+        }
+        (Exclusion::Rule, true) => format!(
+            "the rule at line {} of the rules file says never to kill it",
+            rule_line.unwrap_or_default()
+        ),
+        (Exclusion::Rule, false) => match rule_line {
+            Some(line) => format!(
+                "the rule at line {} of the rules file does not protect it",
+                line
+            ),
+            None => "matches no rule".into(),
+        },
+        (Exclusion::KernelThread, true) => {
+            "no resident memory, as is the case of kernel threads".into()
+        }
+        (Exclusion::KernelThread, false) => format!("{} KiB resident", candidate.vm_rss_kib),
+        (Exclusion::OomDisabled, true) => {
+            "oom_score_adj is -1000, which the kernel's OOM killer respects as well".into()
+        }
+        (Exclusion::OomDisabled, false) => {
+            format!("oom_score_adj is {}, not -1000", candidate.oom_score_adj)
+        }
+    }
+}
+
+/// Compares the process to the one that would be killed next
+fn compare(out: &mut impl Write, candidate: &Candidate, top: &Candidate) -> fmt::Result {
+    let (pid, comm) = (top.process.pid, &top.comm);
+
+    if candidate.process.pid == pid {
+        writeln!(out, "It is the top candidate, and would be killed next.")
+    } else if candidate.rank() > top.rank() {
+        writeln!(
+            out,
+            "Were it not excluded, it would be killed before the top candidate, PID {} ({}).",
+            pid, comm
+        )
+    } else if top.rank().over_rss_max && !candidate.rank().over_rss_max {
+        writeln!(
+            out,
+            "The top candidate is PID {} ({}), which is over the RSS ceiling set by its rule.",
+            pid, comm
+        )
+    } else if candidate.score() < top.score() {
+        writeln!(
+            out,
+            "The top candidate is PID {} ({}), whose score of {} beats {}.",
+            pid,
+            comm,
+            top.score(),
+            candidate.score()
+        )
+    } else {
+        writeln!(
+            out,
+            "The top candidate is PID {} ({}), with the same score of {}: \
+             ties go to the largest RSS, {} KiB against {} KiB.",
+            pid,
+            comm,
+            top.score(),
+            top.vm_rss_kib,
+            candidate.vm_rss_kib
+        )
+    }
+}
+
+/// Writes how the process fared against every rule, along with where it ranks among
+/// the `eligible` processes, which are ordered as victims would be chosen
+fn write_explanation(
+    out: &mut impl Write,
+    candidate: &Candidate,
+    user: &str,
+    checks: &[(Exclusion, bool)],
+    eligible: &[&Candidate],
+) -> fmt::Result {
+    write!(
+        out,
+        "PID {} ({}), owned by {}, with an oom_score of {}",
+        candidate.process.pid, candidate.comm, user, candidate.process.oom_score
+    )?;
+    if candidate.bias != 0 {
+        // The bias is the rule's own, plus `prefer_bias` or minus `avoid_bias` if the patterns match
+        let rule_bias = candidate.rule.as_ref().map_or(0, |rule| rule.bias);
+        let pattern_bias = candidate.bias - rule_bias;
+        if rule_bias != 0 {
+            write!(
+                out,
+                ", {:+} from the bias of the rule at line {}",
+                rule_bias,
+                candidate.rule.as_ref().map_or(0, |rule| rule.line)
+            )?;
+        }
+        if pattern_bias != 0 {
+            write!(
+                out,
+                "{} {:+} from prefer_bias and avoid_bias, as it matches the prefer or avoid patterns",
+                if rule_bias != 0 { " and" } else { "," },
+                pattern_bias
+            )?;
+        }
+        write!(out, ", for a score of {}", candidate.score())?;
+    }
+    writeln!(out)?;
+    for &(check, applies) in checks {
+        writeln!(
+            out,
+            "  [{}] {}: {}",
+            if applies { "excluded" } else { "passed" },
+            check.as_str(),
+            describe_check(candidate, check, applies)
+        )?;
+    }
+    if let Some(rss_max) = candidate.rule.as_ref().and_then(|rule| rule.rss_max) {
+        if candidate.rank().over_rss_max {
+            writeln!(
+                out,
+                "Over the RSS ceiling of {} set by its rule, so it comes before processes that aren't.",
+                rss_max
+            )?;
+        }
+    }
+
+    let rank = eligible
+        .iter()
+        .position(|other| other.process.pid == candidate.process.pid);
+    match (candidate.exclusion, rank) {
+        (Some(exclusion), _) => writeln!(out, "Never killed: {}.", exclusion)?,
+        (None, Some(rank)) => writeln!(
+            out,
+            "Eligible, ranking {} of {} eligible processes.",
+            rank + 1,
+            eligible.len()
+        )?,
+        // Can't happen, since eligible processes are all ranked
+        (None, None) => {}
+    }
+
+    match eligible.first() {
+        Some(top) => compare(out, candidate, top),
+        None => writeln!(out, "No process is eligible, so none would be killed."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_explanation;
+    use crate::config::Rule;
+    use crate::kill::{Candidate, Exclusion};
+    use crate::process::Process;
+
+    fn candidate(pid: u32, comm: &str, oom_score: i16, vm_rss_kib: i64) -> Candidate {
+        Candidate {
+            process: Process { pid, oom_score },
+            comm: comm.into(),
+            uid: 1000,
+            oom_score_adj: 0,
+            vm_rss_kib,
+            vm_swap_kib: None,
+            bias: 0,
+            rule: None,
+            exclusion: None,
+        }
+    }
+
+    /// Explains without going through the checks, which depend on enabled features
+    fn explain(candidate: &Candidate, eligible: &[&Candidate]) -> String {
+        let mut out = String::new();
+        write_explanation(&mut out, candidate, "alice", &[], eligible).unwrap();
+
+        out.trim_end().into()
+    }
+
+    #[test]
+    fn should_explain_rankings() {
+        let firefox = candidate(400, "firefox", 600, 8192);
+        let thunderbird = candidate(500, "thunderbird", 600, 4096);
+        let bash = candidate(600, "bash", 100, 1024);
+        let eligible = [&firefox, &thunderbird, &bash];
+
+        assert_eq!(
+            explain(&firefox, &eligible),
+            "PID 400 (firefox), owned by alice, with an oom_score of 600\n\
+             Eligible, ranking 1 of 3 eligible processes.\n\
+             It is the top candidate, and would be killed next."
+        );
+        assert_eq!(
+            explain(&thunderbird, &eligible),
+            "PID 500 (thunderbird), owned by alice, with an oom_score of 600\n\
+             Eligible, ranking 2 of 3 eligible processes.\n\
+             The top candidate is PID 400 (firefox), with the same score of 600: \
+             ties go to the largest RSS, 8192 KiB against 4096 KiB."
+        );
+        assert!(explain(&bash, &eligible)
+            .ends_with("The top candidate is PID 400 (firefox), whose score of 600 beats 100."));
+
+        let chromium = Candidate {
+            bias: -300,
+            ..candidate(700, "chromium", 800, 8192)
+        };
+        assert_eq!(
+            explain(&chromium, &eligible),
+            "PID 700 (chromium), owned by alice, with an oom_score of 800, \
+             -300 from prefer_bias and avoid_bias, as it matches the prefer or avoid patterns, \
+             for a score of 500\n\
+             The top candidate is PID 400 (firefox), whose score of 600 beats 500."
+        );
+
+        let sshd = Candidate {
+            oom_score_adj: -1000,
+            exclusion: Some(Exclusion::OomDisabled),
+            ..candidate(300, "sshd", 900, 4096)
+        };
+        assert_eq!(
+            explain(&sshd, &eligible),
+            "PID 300 (sshd), owned by alice, with an oom_score of 900\n\
+             Never killed: oom_score_adj is -1000.\n\
+             Were it not excluded, it would be killed before the top candidate, PID 400 (firefox)."
+        );
+    }
+
+    #[test]
+    fn should_explain_rss_ceilings() {
+        let rules = Rule::from_toml("[[rule]]\ncmdline = \"postgres\"\nrss_max = \"1M\"").unwrap();
+        let postgres = Candidate {
+            rule: rules.into_iter().next(),
+            ..candidate(700, "postgres", 100, 2048)
+        };
+        let firefox = candidate(400, "firefox", 600, 8192);
+        let eligible = [&postgres, &firefox];
+
+        assert_eq!(
+            explain(&postgres, &eligible),
+            "PID 700 (postgres), owned by alice, with an oom_score of 100\n\
+             Over the RSS ceiling of 1M set by its rule, so it comes before processes that aren't.\n\
+             Eligible, ranking 1 of 2 eligible processes.\n\
+             It is the top candidate, and would be killed next."
+        );
+        assert!(explain(&firefox, &eligible).ends_with(
+            "The top candidate is PID 700 (postgres), which is over the RSS ceiling set by its rule."
+        ));
+    }
+
+    #[test]
+    fn should_explain_where_biases_come_from() {
+        let rules = Rule::from_toml("[[rule]]\ncmdline = \"java\"\nbias = 200").unwrap();
+        let java = Candidate {
+            bias: 200,
+            rule: rules.into_iter().next(),
+            ..candidate(800, "java", 300, 4096)
+        };
+        assert!(explain(&java, &[&java]).starts_with(
+            "PID 800 (java), owned by alice, with an oom_score of 300, \
+             +200 from the bias of the rule at line 1, for a score of 500\n"
+        ));
+
+        // Rule and pattern biases add up
+        let java = Candidate { bias: 100, ..java };
+        assert!(explain(&java, &[&java]).starts_with(
+            "PID 800 (java), owned by alice, with an oom_score of 300, \
+             +200 from the bias of the rule at line 1 \
+             and -100 from prefer_bias and avoid_bias, as it matches the prefer or avoid patterns, \
+             for a score of 400\n"
+        ));
+    }
+
+    #[test]
+    fn should_describe_every_check() {
+        let sshd = Candidate {
+            oom_score_adj: -1000,
+            exclusion: Some(Exclusion::OomDisabled),
+            ..candidate(300, "sshd", 900, 4096)
+        };
+        let mut out = String::new();
+        let checks = [
+            (Exclusion::KernelThread, false),
+            (Exclusion::OomDisabled, true),
+        ];
+        write_explanation(&mut out, &sshd, "root", &checks, &[]).unwrap();
+
+        assert_eq!(
+            out,
+            "PID 300 (sshd), owned by root, with an oom_score of 900\n  \
+             [passed] kernel_thread: 4096 KiB resident\n  \
+             [excluded] oom_score_adj: oom_score_adj is -1000, which the kernel's OOM killer respects as well\n\
+             Never killed: oom_score_adj is -1000.\n\
+             No process is eligible, so none would be killed.\n"
+        );
+    }
+}
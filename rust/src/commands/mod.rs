@@ -0,0 +1,21 @@
+//! Subcommands, which run instead of the daemon
+
+mod explain;
+mod rank;
+mod status;
+
+use crate::cli::Command;
+use crate::config::Config;
+use crate::error::Result;
+use crate::log::{self, Filter, Level};
+
+pub fn run(command: &Command, config: &Config) -> Result<()> {
+    // Subcommands print what they found themselves, so only problems are logged
+    log::set_filter(Filter::new(Level::Warn));
+
+    match command {
+        Command::Status(args) => status::run(args, config),
+        Command::Rank(args) => rank::run(args, config),
+        Command::Explain(args) => explain::run(args, config),
+    }
+}
@@ -0,0 +1,192 @@
+//! `bustd rank`, which lists who would be killed next
+
+use std::fmt::{self, Write};
+
+use crate::cli::RankArgs;
+use crate::config::Config;
+use crate::error::Result;
+use crate::json::JsonObject;
+use crate::kill::{self, Candidate};
+use crate::utils;
+
+/// A line of the ranking
+struct Row<'a> {
+    /// Unset for excluded processes, which are never killed
+    rank: Option<usize>,
+    candidate: &'a Candidate,
+    user: String,
+}
+
+pub fn run(args: &RankArgs, config: &Config) -> Result<()> {
+    let (mut proc_buf, mut buf) = ([0_u8; 128], [0_u8; 256]);
+    let candidates = kill::rank_candidates(&mut proc_buf, &mut buf, config)?;
+    let rows = rows(&candidates, args.top, |uid| {
+        utils::get_user_name(uid).unwrap_or_else(|| uid.to_string())
+    });
+
+    let mut out = String::new();
+    // Writing to a String can't fail
+    let _ = if args.json {
+        write_json(&mut out, &rows)
+    } else {
+        write_table(&mut out, &rows)
+    };
+    println!("{}", out.trim_end());
+
+    Ok(())
+}
+
+/// Numbers the first `top` eligible candidates, keeping the excluded ones
+/// that rank among them unnumbered
+fn rows(candidates: &[Candidate], top: usize, user_name: impl Fn(u32) -> String) -> Vec<Row<'_>> {
+    let mut rows = Vec::new();
+    let mut rank = 0;
+
+    for candidate in candidates {
+        if rank == top {
+            break;
+        }
+        if candidate.exclusion.is_none() {
+            rank += 1;
+        }
+
+        rows.push(Row {
+            rank: candidate.exclusion.is_none().then_some(rank),
+            user: user_name(candidate.uid),
+            candidate,
+        });
+    }
+
+    rows
+}
+
+fn write_table(out: &mut impl Write, rows: &[Row]) -> fmt::Result {
+    writeln!(
+        out,
+        "{:>4} {:>7} {:<16} {:<12} {:>9} {:>6} {:>5} {:>10} {:>10}  EXCLUDED",
+        "RANK", "PID", "COMM", "USER", "OOM_SCORE", "ADJ", "BIAS", "RSS_KIB", "SWAP_KIB"
+    )?;
+
+    for Row {
+        rank,
+        candidate,
+        user,
+    } in rows
+    {
+        let rank = rank.map_or_else(|| "-".into(), |rank| rank.to_string());
+        let swap = candidate
+            .vm_swap_kib
+            .map_or_else(|| "-".into(), |swap| swap.to_string());
+        let exclusion = candidate
+            .exclusion
+            .map_or_else(String::new, |exclusion| exclusion.to_string());
+
+        let line = format!(
+            "{:>4} {:>7} {:<16} {:<12} {:>9} {:>6} {:>5} {:>10} {:>10}  {}",
+            rank,
+            candidate.process.pid,
+            candidate.comm,
+            user,
+            candidate.process.oom_score,
+            candidate.oom_score_adj,
+            candidate.bias,
+            candidate.vm_rss_kib,
+            swap,
+            exclusion,
+        );
+        // Eligible processes have nothing in the last column
+        writeln!(out, "{}", line.trim_end())?;
+    }
+
+    Ok(())
+}
+
+fn write_json(out: &mut String, rows: &[Row]) -> fmt::Result {
+    let mut object = JsonObject::new(out)?;
+    let mut array = object.array("candidates")?;
+
+    for Row {
+        rank,
+        candidate,
+        user,
+    } in rows
+    {
+        let mut row = array.object()?;
+        if let Some(rank) = rank {
+            row.number("rank", *rank as i128)?;
+        }
+        row.number("pid", candidate.process.pid)?;
+        row.string("comm", &candidate.comm)?;
+        row.string("user", user)?;
+        row.number("uid", candidate.uid)?;
+        row.number("oom_score", candidate.process.oom_score)?;
+        row.number("oom_score_adj", candidate.oom_score_adj)?;
+        row.number("bias", candidate.bias)?;
+        row.number("rss_kib", candidate.vm_rss_kib)?;
+        if let Some(swap) = candidate.vm_swap_kib {
+            row.number("swap_kib", swap)?;
+        }
+        if let Some(exclusion) = candidate.exclusion {
+            row.string("excluded", exclusion.as_str())?;
+        }
+        row.finish()?;
+    }
+
+    array.finish()?;
+    object.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rows, write_json, write_table};
+    use crate::kill::{Candidate, Exclusion};
+    use crate::process::Process;
+
+    fn candidate(pid: u32, comm: &str, oom_score: i16, exclusion: Option<Exclusion>) -> Candidate {
+        Candidate {
+            process: Process { pid, oom_score },
+            comm: comm.into(),
+            uid: 1000,
+            oom_score_adj: 0,
+            vm_rss_kib: 4096,
+            vm_swap_kib: (pid != 300).then_some(0),
+            bias: 0,
+            rule: None,
+            exclusion,
+        }
+    }
+
+    #[test]
+    fn should_rank_candidates() {
+        let candidates = [
+            candidate(300, "sshd", 900, Some(Exclusion::OomDisabled)),
+            candidate(400, "firefox", 600, None),
+            Candidate {
+                bias: -300,
+                ..candidate(500, "thunderbird", 800, None)
+            },
+            candidate(600, "bash", 100, None),
+            candidate(700, "init", 50, Some(Exclusion::OomDisabled)),
+        ];
+        let rows = rows(&candidates, 2, |uid| format!("user{}", uid));
+
+        let mut table = String::new();
+        write_table(&mut table, &rows).unwrap();
+        // Excluded processes don't count towards `top`
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            table,
+            "RANK     PID COMM             USER         OOM_SCORE    ADJ  BIAS    RSS_KIB   SWAP_KIB  EXCLUDED\n   \
+                -     300 sshd             user1000           900      0     0       4096          -  oom_score_adj is -1000\n   \
+                1     400 firefox          user1000           600      0     0       4096          0\n   \
+                2     500 thunderbird      user1000           800      0  -300       4096          0\n"
+        );
+
+        let mut json = String::new();
+        write_json(&mut json, &rows[..2]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"candidates":[{"pid":300,"comm":"sshd","user":"user1000","uid":1000,"oom_score":900,"oom_score_adj":0,"bias":0,"rss_kib":4096,"excluded":"oom_score_adj"},{"rank":1,"pid":400,"comm":"firefox","user":"user1000","uid":1000,"oom_score":600,"oom_score_adj":0,"bias":0,"rss_kib":4096,"swap_kib":0}]}"#
+        );
+    }
+}
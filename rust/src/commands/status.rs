@@ -0,0 +1,297 @@
+//! `bustd status`, which shows what the running daemon is up to
+
+use std::fmt::{self, Write};
+use std::path::PathBuf;
+
+use crate::cli::StatusArgs;
+use crate::config::{signal_name, Config};
+use crate::control::{self, Request};
+use crate::daemon;
+use crate::error::{Error, Result};
+use crate::json::Value;
+
+/// The configuration keys that decide when processes get killed, shown if set
+const THRESHOLDS: [&str; 7] = [
+    "near_terminal",
+    "cutoff_psi",
+    "cutoff_psi_full",
+    "cutoff_psi_avg60",
+    "cutoff_psi_window",
+    "ram_terminal",
+    "swap_terminal",
+];
+
+pub fn run(args: &StatusArgs, config: &Config) -> Result<()> {
+    // Only daemonized instances write a pidfile, so the sockets are tried either way
+    let pid = daemon::running_pid()?;
+
+    let status =
+        request_status(&config.control_socket_candidates()).map_err(|err| match (err, pid) {
+            (Error::Io { reason }, Some(pid)) => Error::Control {
+                reason: format!(
+                    "bustd is running as PID {}, but could not be reached: {}",
+                    pid, reason
+                ),
+            },
+            (Error::Io { reason }, None) => Error::Control {
+                reason: format!(
+                    "bustd does not seem to be running with `control` enabled: {}",
+                    reason
+                ),
+            },
+            (err, _) => err,
+        })?;
+
+    let mut out = String::new();
+    // Writing to a String can't fail
+    let _ = write_status(&mut out, &status, args.kills);
+    print!("{}", out);
+
+    Ok(())
+}
+
+/// Asks the daemon listening on the first of the sockets that can be connected to for its status
+fn request_status(sockets: &[PathBuf]) -> Result<Value> {
+    let mut failures = Vec::new();
+    for socket in sockets {
+        match control::send(socket, Request::Status) {
+            Err(Error::Io { reason }) => failures.push(format!(
+                "could not connect to {}: {}",
+                socket.display(),
+                reason
+            )),
+            response => return response,
+        }
+    }
+
+    Err(Error::Io {
+        reason: failures.join(", "),
+    })
+}
+
+fn number(value: &Value, key: &str) -> f64 {
+    value.get(key).and_then(Value::as_f64).unwrap_or(0.0)
+}
+
+fn flag(value: &Value, key: &str) -> bool {
+    value.get(key).and_then(Value::as_bool).unwrap_or(false)
+}
+
+fn text<'a>(value: &'a Value, key: &str) -> &'a str {
+    value.get(key).and_then(Value::as_str).unwrap_or("?")
+}
+
+/// Displays milliseconds as e.g. `2h 3m 12s`
+fn human_duration(millis: f64) -> String {
+    let secs = (millis / 1000.0) as u64;
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    match (hours, minutes) {
+        (0, 0) => format!("{}s", secs),
+        (0, _) => format!("{}m {}s", minutes, secs),
+        _ => format!("{}h {}m {}s", hours, minutes, secs),
+    }
+}
+
+fn write_memory(out: &mut impl Write, label: &str, memory: &Value, kind: &str) -> fmt::Result {
+    writeln!(
+        out,
+        "{:<12} {} MB available of {} MB ({}%)",
+        label,
+        number(memory, &format!("available_{}_mb", kind)),
+        number(memory, &format!("total_{}_mb", kind)),
+        number(memory, &format!("available_{}_percent", kind)),
+    )
+}
+
+fn write_pressure(out: &mut impl Write, label: &str, line: &Value) -> fmt::Result {
+    writeln!(
+        out,
+        "{:<12} avg10 {:.2}, avg60 {:.2}, avg300 {:.2}",
+        label,
+        number(line, "avg10"),
+        number(line, "avg60"),
+        number(line, "avg300"),
+    )
+}
+
+/// Writes the daemon's answer to a `status` request, along with its `kills` latest kills
+fn write_status(out: &mut impl Write, status: &Value, kills: usize) -> fmt::Result {
+    writeln!(out, "{:<12} {}", "PID:", number(status, "pid"))?;
+    writeln!(
+        out,
+        "{:<12} {}",
+        "Uptime:",
+        human_duration(number(status, "uptime_ms"))
+    )?;
+
+    let state = if flag(status, "paused") {
+        format!(
+            "paused, resuming in {}",
+            human_duration(number(status, "resumes_in_ms"))
+        )
+    } else if flag(status, "in_cooldown") {
+        "cooling down after a kill".to_string()
+    } else {
+        "active".to_string()
+    };
+    let dry_run = if flag(status, "dry_run") {
+        " (dry run)"
+    } else {
+        ""
+    };
+    writeln!(out, "{:<12} {}{}", "State:", state, dry_run)?;
+
+    let null = Value::Null;
+    let memory = status.get("memory").unwrap_or(&null);
+    write_memory(out, "RAM:", memory, "ram")?;
+    write_memory(out, "Swap:", memory, "swap")?;
+    writeln!(
+        out,
+        "{:<12} {}",
+        "Memory:",
+        match text(status, "status") {
+            "near_terminal" => "near terminal, checking PSI",
+            "okay" => "okay",
+            other => other,
+        }
+    )?;
+    if let Some(psi) = status.get("psi") {
+        write_pressure(out, "PSI some:", psi.get("some").unwrap_or(&null))?;
+        write_pressure(out, "PSI full:", psi.get("full").unwrap_or(&null))?;
+    }
+
+    let config = status.get("config").unwrap_or(&null);
+    let thresholds: Vec<_> = THRESHOLDS
+        .iter()
+        .filter_map(|key| Some(format!("{} = {}", key, config.get(key)?.as_str()?)))
+        .collect();
+    writeln!(out, "{:<12} {}", "Thresholds:", thresholds.join(", "))?;
+
+    let all_kills = status.get("kills").and_then(Value::as_array).unwrap_or(&[]);
+    let latest = &all_kills[all_kills.len().saturating_sub(kills)..];
+    if latest.is_empty() {
+        return writeln!(out, "{:<12} none", "Kills:");
+    }
+    writeln!(
+        out,
+        "{:<12} {} latest, most recent last",
+        "Kills:",
+        latest.len()
+    )?;
+    for kill in latest {
+        write!(
+            out,
+            "  {}  {} (PID {}), oom_score {}, rss {} KiB: ",
+            text(kill, "timestamp"),
+            text(kill, "comm"),
+            number(kill, "pid"),
+            number(kill, "oom_score"),
+            number(kill, "rss_kib"),
+        )?;
+        if flag(kill, "dry_run") {
+            writeln!(out, "would have been killed")?;
+        } else {
+            writeln!(
+                out,
+                "{} (last signal: {})",
+                text(kill, "result"),
+                signal_name(number(kill, "signal") as i32)
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{human_duration, request_status, write_status};
+    use crate::config::Config;
+    use crate::control::{self, Server};
+    use crate::json;
+
+    #[test]
+    fn should_reach_daemons_running_as_other_users() {
+        // Clients look where a root daemon listens first, whoever they run as
+        let candidates = Config::default().control_socket_candidates();
+        assert_eq!(candidates[0].to_str(), Some("/run/bustd/control.sock"));
+        assert_eq!(candidates.len(), 2);
+
+        let dir = std::env::temp_dir().join(format!("bustd-sockets-{}", std::process::id()));
+        let (system, user) = (dir.join("system.sock"), dir.join("user.sock"));
+        let sockets = [system.clone(), user.clone()];
+        assert!(request_status(&sockets).is_err());
+
+        // As seen by a user other than the one running the daemon
+        for socket in [&system, &user] {
+            let server = Server::bind(socket, None).unwrap();
+            let daemon = std::thread::spawn(move || {
+                let mut connection = loop {
+                    match server.accept().unwrap() {
+                        Some(connection) => break connection,
+                        None => std::thread::sleep(Duration::from_millis(1)),
+                    }
+                };
+                while connection.read_request().unwrap().is_none() {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                connection
+                    .respond(&control::ok_response(|object| object.number("pid", 42)))
+                    .unwrap();
+            });
+
+            let status = request_status(&sockets).unwrap();
+            assert_eq!(status.get("pid").and_then(json::Value::as_f64), Some(42.0));
+            daemon.join().unwrap();
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn should_format_durations() {
+        assert_eq!(human_duration(999.0), "0s");
+        assert_eq!(human_duration(61_000.0), "1m 1s");
+        assert_eq!(human_duration(7_384_000.0), "2h 3m 4s");
+    }
+
+    #[test]
+    fn should_write_status() {
+        let status = json::parse(
+            r#"{"ok":true,"pid":42,"uptime_ms":61000,"paused":true,"resumes_in_ms":5000,
+                "in_cooldown":false,"dry_run":false,
+                "memory":{"total_ram_mb":8000,"available_ram_mb":400,"available_ram_percent":5,
+                          "total_swap_mb":0,"available_swap_mb":0,"available_swap_percent":0},
+                "status":"near_terminal",
+                "psi":{"some":{"avg10":30.5,"avg60":12,"avg300":3,"total":1},
+                       "full":{"avg10":1,"avg60":0,"avg300":0,"total":1}},
+                "config":{"near_terminal":"\"15%\"","cutoff_psi":"25.0","dry_run":"false"},
+                "kills":[
+                    {"timestamp":"2021-09-04T21:30:12.345Z","pid":7,"comm":"old","oom_score":1,"rss_kib":2,"dry_run":true},
+                    {"timestamp":"2021-09-04T21:31:12.345Z","pid":1234,"comm":"firefox","oom_score":800,
+                     "rss_kib":4096,"result":"killed","signal":15}]}"#,
+        )
+        .unwrap();
+
+        let mut out = String::new();
+        write_status(&mut out, &status, 1).unwrap();
+        assert_eq!(
+            out,
+            "PID:         42\n\
+             Uptime:      1m 1s\n\
+             State:       paused, resuming in 5s\n\
+             RAM:         400 MB available of 8000 MB (5%)\n\
+             Swap:        0 MB available of 0 MB (0%)\n\
+             Memory:      near terminal, checking PSI\n\
+             PSI some:    avg10 30.50, avg60 12.00, avg300 3.00\n\
+             PSI full:    avg10 1.00, avg60 0.00, avg300 0.00\n\
+             Thresholds:  near_terminal = \"15%\", cutoff_psi = 25.0\n\
+             Kills:       1 latest, most recent last\n\
+             \x20 2021-09-04T21:31:12.345Z  firefox (PID 1234), oom_score 800, rss 4096 KiB: \
+             killed (last signal: SIGTERM)\n"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use libc::{SIGABRT, SIGALRM, SIGHUP, SIGINT, SIGKILL, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2};
+
+use super::{format_duration, parse_duration};
+
+/// The signals victims may be sent, by name
+pub const SIGNALS: [(&str, i32); 9] = [
+    ("HUP", SIGHUP),
+    ("INT", SIGINT),
+    ("QUIT", SIGQUIT),
+    ("ABRT", SIGABRT),
+    ("KILL", SIGKILL),
+    ("USR1", SIGUSR1),
+    ("USR2", SIGUSR2),
+    ("ALRM", SIGALRM),
+    ("TERM", SIGTERM),
+];
+
+/// How long victims get to exit before being sent SIGKILL by default
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+/// Parses signal names such as `SIGINT` or `INT`
+pub fn parse_signal(signal: &str) -> Result<i32, String> {
+    let name = signal.strip_prefix("SIG").unwrap_or(signal);
+
+    SIGNALS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|&(_, number)| number)
+        .ok_or_else(|| format!("unknown signal \"{}\"", signal))
+}
+
+/// The name of the signal, e.g. `SIGTERM`, or its number if it isn't one of [`SIGNALS`]
+pub fn signal_name(signal: i32) -> String {
+    match SIGNALS.iter().find(|&&(_, number)| number == signal) {
+        Some((name, _)) => format!("SIG{}", name),
+        None => signal.to_string(),
+    }
+}
+
+/// The signals a victim is sent in turn, each followed by how long it gets to exit before
+/// the next one, always ending with SIGKILL, e.g. `SIGTERM 5s SIGINT 2s SIGKILL`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignalLadder {
+    /// The signals sent before SIGKILL, along with how long to wait after each
+    rungs: Vec<(i32, Duration)>,
+}
+
+impl SignalLadder {
+    /// Sends the signal, then SIGKILL once the grace period is over
+    pub fn single(signal: i32, grace_period: Duration) -> Self {
+        let rungs = match signal {
+            SIGKILL => Vec::new(),
+            signal => vec![(signal, grace_period)],
+        };
+
+        Self { rungs }
+    }
+
+    /// Sends SIGKILL right away
+    pub fn immediate() -> Self {
+        Self { rungs: Vec::new() }
+    }
+
+    pub fn rungs(&self) -> &[(i32, Duration)] {
+        &self.rungs
+    }
+
+    pub fn first_signal(&self) -> i32 {
+        self.rungs.first().map_or(SIGKILL, |&(signal, _)| signal)
+    }
+}
+
+/// SIGTERM, then SIGKILL after half a second
+impl Default for SignalLadder {
+    fn default() -> Self {
+        Self::single(SIGTERM, DEFAULT_GRACE_PERIOD)
+    }
+}
+
+impl FromStr for SignalLadder {
+    type Err = String;
+
+    fn from_str(ladder: &str) -> Result<Self, Self::Err> {
+        let mut tokens = ladder.split_ascii_whitespace();
+        let mut rungs = Vec::new();
+
+        loop {
+            let signal = match tokens.next() {
+                Some(signal) => parse_signal(signal)?,
+                None => return Err("the signal ladder must end with SIGKILL".into()),
+            };
+            if signal == SIGKILL {
+                break;
+            }
+
+            let wait = tokens.next().ok_or_else(|| {
+                format!("expected how long to wait after {}", signal_name(signal))
+            })?;
+            rungs.push((signal, parse_duration(wait)?));
+        }
+
+        match tokens.next() {
+            Some(_) => Err("nothing can come after SIGKILL in the signal ladder".into()),
+            None => Ok(Self { rungs }),
+        }
+    }
+}
+
+impl fmt::Display for SignalLadder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &(signal, wait) in &self.rungs {
+            write!(f, "{} {} ", signal_name(signal), format_duration(wait))?;
+        }
+
+        f.write_str("SIGKILL")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use libc::{SIGINT, SIGKILL, SIGTERM};
+
+    use super::SignalLadder;
+
+    #[test]
+    fn should_parse_signal_ladders() {
+        let ladder: SignalLadder = "SIGTERM 5s INT 2s SIGKILL".parse().unwrap();
+        assert_eq!(
+            ladder.rungs(),
+            [
+                (SIGTERM, Duration::from_secs(5)),
+                (SIGINT, Duration::from_secs(2))
+            ]
+        );
+        assert_eq!(ladder.to_string(), "SIGTERM 5s SIGINT 2s SIGKILL");
+        assert_eq!(SignalLadder::default().to_string(), "SIGTERM 500ms SIGKILL");
+        assert_eq!("SIGKILL".parse(), Ok(SignalLadder::immediate()));
+        assert_eq!(SignalLadder::immediate().first_signal(), SIGKILL);
+        assert_eq!(SignalLadder::single(SIGKILL, Duration::ZERO).rungs(), []);
+
+        assert!("SIGTERM 5s".parse::<SignalLadder>().is_err());
+        assert!("SIGTERM SIGKILL".parse::<SignalLadder>().is_err());
+        assert!("SIGKILL SIGTERM 5s".parse::<SignalLadder>().is_err());
+        assert!("SIGFOO 1s SIGKILL".parse::<SignalLadder>().is_err());
+        assert!("".parse::<SignalLadder>().is_err());
+    }
+}
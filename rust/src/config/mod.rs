@@ -0,0 +1,954 @@
+//! bustd's runtime configuration, read from `/etc/bustd.toml` (or the file given
+//! through `--config`) and then overridden by whichever command-line flags were supplied.
+
+mod ladder;
+mod parser;
+mod pattern;
+mod rules;
+mod units;
+
+use std::{
+    convert::TryFrom,
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub use ladder::{signal_name, SignalLadder, SIGNALS};
+pub use pattern::Pattern;
+pub use rules::Rule;
+pub use units::{format_duration, parse_duration, ByteSize, Threshold};
+
+use self::parser::{Table, Value};
+use crate::{
+    cli::CommandLineArgs,
+    error::{Error, Result},
+    log::{Filter, Format, Level, Target},
+    memory::MemorySource,
+    metrics,
+    process::Facts,
+    utils,
+};
+
+/// Read if it exists and no other configuration file was given
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/bustd.toml";
+
+/// The largest `prefer_bias` and `avoid_bias` allowed, as large as the range of oom_score itself
+const MAX_BIAS: u16 = 1000;
+
+/// The longest `proc_root` allowed, so that paths within it always fit in the daemon's buffers
+const MAX_PROC_ROOT_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Shorthand for a default log level of at least `debug`
+    pub verbose: bool,
+    /// The default log level, along with per-module levels, e.g. `info,kill=debug`
+    pub log_level: Filter,
+    /// Whether events are logged as text or as JSON lines
+    pub log_format: Format,
+    /// Where to log to. When unset, the console is used with `no_daemon`, and `log_file` otherwise.
+    pub log_target: Option<Target>,
+    /// The file to log to when `log_target` is `file`. When unset, `/var/log/bustd.out` is used
+    /// as root, and `/tmp/bustd.out` otherwise.
+    pub log_file: Option<PathBuf>,
+    /// When set, the process will not be daemonized
+    pub no_daemon: bool,
+    /// When set, the victim's entire process group will be killed
+    pub kill_pgroup: bool,
+    /// When set, victims are chosen and logged but never killed
+    pub dry_run: bool,
+    /// Minimum time between two kills, none by default. Applies to would-be kills in dry-run
+    /// mode as well, so that its log mirrors what would really happen.
+    pub kill_cooldown: Duration,
+    /// Command run through `sh -c` right before a victim is killed
+    pub pre_kill: Option<String>,
+    /// Command run through `sh -c` right after a victim is killed
+    pub post_kill: Option<String>,
+    /// Time after which hook commands are killed
+    pub hook_timeout: Duration,
+    /// The signals victims are sent, unless their rule says otherwise
+    pub signal_ladder: SignalLadder,
+    /// How long to wait for a victim to exit once sent SIGKILL
+    pub exit_timeout: Duration,
+    /// When set, the owner of each killed process gets a desktop notification
+    pub notify: bool,
+    /// The `some avg10` PSI value above which the owner of the would-be victim is warned
+    pub notify_warn_psi: Option<f32>,
+    /// Minimum time between two warnings
+    pub notify_warn_interval: Duration,
+    /// Where Prometheus metrics are served, if anywhere
+    pub metrics_listen: Option<metrics::Address>,
+    /// Whether to listen on the control socket, off unless asked for
+    pub control: bool,
+    /// Where the control socket is. When unset, `/run/bustd/control.sock` is used as root,
+    /// `$XDG_RUNTIME_DIR/bustd/control.sock` otherwise, or `/tmp/bustd-control.sock`
+    /// if `XDG_RUNTIME_DIR` isn't set.
+    pub control_socket: Option<PathBuf>,
+    /// A group whose members may use the control socket, besides the daemon's own user.
+    /// Only useful if they look for it where the daemon listens, which clients do by default
+    /// for a daemon running as root, trying `/run/bustd/control.sock` first.
+    pub control_group: Option<String>,
+    /// The `some avg10` PSI value on which, if surpassed, a process will be killed
+    pub cutoff_psi: f32,
+    /// The `full avg10` PSI value on which, if surpassed, a process will be killed
+    pub cutoff_psi_full: Option<f32>,
+    /// The `some avg60` PSI value on which, if surpassed, a process will be killed
+    pub cutoff_psi_avg60: Option<f32>,
+    /// The `full avg10` PSI value on which, if surpassed, victims are sent SIGKILL right away
+    /// rather than going through their signal ladder
+    pub sigkill_psi_full: Option<f32>,
+    /// The stall percentage, measured over the last `psi_window_length` from the `some total`
+    /// counter, on which, if surpassed, a process will be killed
+    pub cutoff_psi_window: Option<f32>,
+    /// Length of the window used for `cutoff_psi_window`
+    pub psi_window_length: Duration,
+    /// Processes whose names match any of these patterns will never be chosen to be killed
+    #[cfg(feature = "glob-ignore")]
+    pub unkillables: Vec<glob::Pattern>,
+    /// Processes whose name, executable path or command line match this pattern
+    /// have `prefer_bias` added to their oom_score when choosing a victim
+    pub prefer: Option<Pattern>,
+    /// Processes whose name, executable path or command line match this pattern
+    /// have `avoid_bias` subtracted from their oom_score when choosing a victim
+    pub avoid: Option<Pattern>,
+    pub prefer_bias: u16,
+    pub avoid_bias: u16,
+    /// The file per-process rules are read from, if any
+    pub rules_file: Option<PathBuf>,
+    /// The rules read from `rules_file`, in order
+    pub rules: Vec<Rule>,
+    /// Where procfs is mounted, e.g. `/host/proc` when running in a container
+    pub proc_root: PathBuf,
+    /// Where the amount of available RAM is read from
+    pub memory_source: MemorySource,
+    /// Available RAM under which memory is considered to be near terminal,
+    /// at which point PSI starts being checked
+    pub near_terminal: Threshold,
+    /// Available RAM used as the "empty" mark when calculating the adaptive sleep time
+    pub ram_terminal: Threshold,
+    /// Available swap used as the "empty" mark when calculating the adaptive sleep time
+    pub swap_terminal: Threshold,
+    /// Maximum expected RAM fill rate, per second
+    pub ram_fill_rate: ByteSize,
+    /// Maximum expected swap fill rate, per second
+    pub swap_fill_rate: ByteSize,
+    /// Lower bound of the adaptive sleep time
+    pub min_sleep: Duration,
+    /// Upper bound of the adaptive sleep time
+    pub max_sleep: Duration,
+    /// Whether to wait for kernel PSI triggers instead of polling while memory isn't
+    /// near terminal. Adaptive sleep is used as a fallback if triggers are unavailable.
+    pub psi_trigger: bool,
+    /// Total stall time, within `psi_trigger_window`, that fires the PSI trigger
+    pub psi_trigger_stall: Duration,
+    /// Time window in which stalls are accounted for by the PSI trigger
+    pub psi_trigger_window: Duration,
+    /// Longest time to wait for the PSI trigger before checking memory anyway
+    pub psi_trigger_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            log_level: Filter::new(Level::Info),
+            log_format: Format::Text,
+            log_target: None,
+            log_file: None,
+            no_daemon: false,
+            kill_pgroup: false,
+            dry_run: false,
+            kill_cooldown: Duration::ZERO,
+            pre_kill: None,
+            post_kill: None,
+            hook_timeout: Duration::from_secs(5),
+            signal_ladder: SignalLadder::default(),
+            exit_timeout: Duration::from_secs(10),
+            notify: false,
+            notify_warn_psi: None,
+            notify_warn_interval: Duration::from_secs(60),
+            metrics_listen: None,
+            control: false,
+            control_socket: None,
+            control_group: None,
+            cutoff_psi: 25.0,
+            cutoff_psi_full: None,
+            cutoff_psi_avg60: None,
+            sigkill_psi_full: None,
+            cutoff_psi_window: None,
+            psi_window_length: Duration::from_millis(500),
+            #[cfg(feature = "glob-ignore")]
+            unkillables: Vec::new(),
+            prefer: None,
+            avoid: None,
+            // As earlyoom does
+            prefer_bias: 300,
+            avoid_bias: 300,
+            rules_file: None,
+            rules: Vec::new(),
+            proc_root: PathBuf::from("/proc"),
+            memory_source: MemorySource::SysInfo,
+            near_terminal: Threshold::Percent(15.0),
+            ram_terminal: Threshold::Percent(10.0),
+            swap_terminal: Threshold::Percent(10.0),
+            // Maximum expected memory fill rate as seen
+            // with `stress -m 4 --vm-bytes 4G` (6000 KiB/ms)
+            ram_fill_rate: ByteSize(6000 * 1024 * 1000),
+            // Maximum expected swap fill rate as seen
+            // with membomb on zRAM (800 KiB/ms)
+            swap_fill_rate: ByteSize(800 * 1024 * 1000),
+            min_sleep: Duration::from_millis(100),
+            max_sleep: Duration::from_millis(1000),
+            psi_trigger: true,
+            psi_trigger_stall: Duration::from_millis(150),
+            psi_trigger_window: Duration::from_secs(1),
+            psi_trigger_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+fn config_error(reason: impl Into<String>) -> Error {
+    Error::Config {
+        reason: reason.into(),
+    }
+}
+
+/// Builds an error about the value of `key`, defined at `line` of the configuration file
+fn invalid_value(key: &str, line: usize, reason: impl fmt::Display) -> Error {
+    config_error(format!(
+        "line {}: invalid value for `{}`: {}",
+        line, key, reason
+    ))
+}
+
+fn expect_bool(key: &str, line: usize, value: &Value) -> Result<bool> {
+    match value {
+        Value::Boolean(boolean) => Ok(*boolean),
+        other => Err(invalid_value(
+            key,
+            line,
+            format!("expected a boolean, found {}", other.type_name()),
+        )),
+    }
+}
+
+fn expect_float(key: &str, line: usize, value: &Value) -> Result<f32> {
+    match value {
+        Value::Float(float) => Ok(*float as f32),
+        Value::Integer(integer) => Ok(*integer as f32),
+        other => Err(invalid_value(
+            key,
+            line,
+            format!("expected a number, found {}", other.type_name()),
+        )),
+    }
+}
+
+fn expect_str<'a>(key: &str, line: usize, value: &'a Value) -> Result<&'a str> {
+    match value {
+        Value::String(string) => Ok(string),
+        other => Err(invalid_value(
+            key,
+            line,
+            format!("expected a string, found {}", other.type_name()),
+        )),
+    }
+}
+
+fn expect_integer<T: TryFrom<i64>>(key: &str, line: usize, value: &Value) -> Result<T> {
+    match value {
+        Value::Integer(integer) => T::try_from(*integer)
+            .map_err(|_| invalid_value(key, line, format!("{} is out of range", integer))),
+        other => Err(invalid_value(
+            key,
+            line,
+            format!("expected an integer, found {}", other.type_name()),
+        )),
+    }
+}
+
+/// Parses a string value with the given parser, such as [`parse_duration`] or [`str::parse`]
+fn expect_parsed<T>(
+    key: &str,
+    line: usize,
+    value: &Value,
+    parse: impl Fn(&str) -> std::result::Result<T, String>,
+) -> Result<T> {
+    let string = expect_str(key, line, value)?;
+    parse(string).map_err(|err| invalid_value(key, line, err))
+}
+
+#[cfg(feature = "glob-ignore")]
+fn expect_patterns(key: &str, line: usize, value: &Value) -> Result<Vec<glob::Pattern>> {
+    let values = match value {
+        Value::Array(values) => values,
+        other => {
+            return Err(invalid_value(
+                key,
+                line,
+                format!("expected an array, found {}", other.type_name()),
+            ))
+        }
+    };
+
+    values
+        .iter()
+        .map(|value| {
+            expect_parsed(key, line, value, |pattern| {
+                glob::Pattern::new(pattern).map_err(|err| err.to_string())
+            })
+        })
+        .collect()
+}
+
+/// Where the control socket is by default when running as root
+const SYSTEM_CONTROL_SOCKET: &str = "/run/bustd/control.sock";
+
+/// Where the control socket is by default when not running as root
+fn user_control_socket() -> PathBuf {
+    // Only the user can write to their runtime directory, unlike /tmp
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("bustd/control.sock"),
+        _ => PathBuf::from("/tmp/bustd-control.sock"),
+    }
+}
+
+impl Config {
+    /// Loads the configuration file (if any) and applies the command-line overrides on top of it
+    pub fn load(args: &CommandLineArgs) -> Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_args(args);
+        config.validate()?;
+        if let Some(path) = &config.rules_file {
+            config.rules = Rule::from_file(path)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Where events are actually logged to
+    pub fn log_target(&self) -> Target {
+        match self.log_target {
+            Some(target) => target,
+            None if self.no_daemon => Target::Console,
+            None => Target::File,
+        }
+    }
+
+    /// The log filter, with `verbose` taken into account
+    pub fn log_filter(&self) -> Filter {
+        let mut filter = self.log_level;
+        if self.verbose {
+            filter.default = filter.default.max(Level::Debug);
+        }
+        filter
+    }
+
+    /// Returns the first rule the process matches, if any
+    pub fn matching_rule(&self, facts: &Facts) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.matches(facts))
+    }
+
+    /// Where the log file actually is
+    pub fn log_file(&self) -> PathBuf {
+        match &self.log_file {
+            Some(path) => path.clone(),
+            None if utils::running_as_sudo() => PathBuf::from("/var/log/bustd.out"),
+            None => PathBuf::from("/tmp/bustd.out"),
+        }
+    }
+
+    /// Where the daemon listens on the control socket
+    pub fn control_socket(&self) -> PathBuf {
+        match &self.control_socket {
+            Some(path) => path.clone(),
+            None if utils::running_as_sudo() => PathBuf::from(SYSTEM_CONTROL_SOCKET),
+            None => user_control_socket(),
+        }
+    }
+
+    /// Where clients look for the control socket, in order. Unless `control_socket` is set,
+    /// that's where a daemon running as root listens, then where one running as the client's
+    /// own user does, whoever the client runs as.
+    pub fn control_socket_candidates(&self) -> Vec<PathBuf> {
+        match &self.control_socket {
+            Some(path) => vec![path.clone()],
+            None => vec![PathBuf::from(SYSTEM_CONTROL_SOCKET), user_control_socket()],
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| config_error(format!("could not read {}: {}", path.display(), err)))?;
+
+        Self::from_toml(&contents).map_err(|err| match err {
+            Error::Config { reason } => config_error(format!("{}: {}", path.display(), reason)),
+            err => err,
+        })
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        let document = parser::parse(contents).map_err(config_error)?;
+
+        if let Some(section) = document.sections.first() {
+            return Err(config_error(format!(
+                "line {}: unexpected table `{}`",
+                section.line, section.name
+            )));
+        }
+
+        let mut config = Self::default();
+        config.apply_table(&document.root)?;
+        Ok(config)
+    }
+
+    fn apply_table(&mut self, table: &Table) -> Result<()> {
+        for entry in &table.entries {
+            let (key, line, value) = (entry.key.as_str(), entry.line, &entry.value);
+
+            match key {
+                "verbose" => self.verbose = expect_bool(key, line, value)?,
+                "log_level" => self.log_level = expect_parsed(key, line, value, str::parse)?,
+                "log_format" => self.log_format = expect_parsed(key, line, value, str::parse)?,
+                "log_target" => {
+                    self.log_target = Some(expect_parsed(key, line, value, str::parse)?)
+                }
+                "log_file" => self.log_file = Some(expect_str(key, line, value)?.into()),
+                "no_daemon" => self.no_daemon = expect_bool(key, line, value)?,
+                "kill_pgroup" => self.kill_pgroup = expect_bool(key, line, value)?,
+                "dry_run" => self.dry_run = expect_bool(key, line, value)?,
+                "kill_cooldown" => {
+                    self.kill_cooldown = expect_parsed(key, line, value, parse_duration)?
+                }
+                "pre_kill" => self.pre_kill = Some(expect_str(key, line, value)?.into()),
+                "post_kill" => self.post_kill = Some(expect_str(key, line, value)?.into()),
+                "hook_timeout" => {
+                    self.hook_timeout = expect_parsed(key, line, value, parse_duration)?
+                }
+                "signal_ladder" => {
+                    self.signal_ladder = expect_parsed(key, line, value, str::parse)?
+                }
+                "exit_timeout" => {
+                    self.exit_timeout = expect_parsed(key, line, value, parse_duration)?
+                }
+                "notify" => self.notify = expect_bool(key, line, value)?,
+                "notify_warn_psi" => self.notify_warn_psi = Some(expect_float(key, line, value)?),
+                "notify_warn_interval" => {
+                    self.notify_warn_interval = expect_parsed(key, line, value, parse_duration)?
+                }
+                "metrics_listen" => {
+                    self.metrics_listen = Some(expect_parsed(key, line, value, str::parse)?)
+                }
+                "control" => self.control = expect_bool(key, line, value)?,
+                "control_socket" => {
+                    self.control_socket = Some(expect_str(key, line, value)?.into())
+                }
+                "control_group" => self.control_group = Some(expect_str(key, line, value)?.into()),
+                "cutoff_psi" => self.cutoff_psi = expect_float(key, line, value)?,
+                "cutoff_psi_full" => self.cutoff_psi_full = Some(expect_float(key, line, value)?),
+                "cutoff_psi_avg60" => self.cutoff_psi_avg60 = Some(expect_float(key, line, value)?),
+                "sigkill_psi_full" => self.sigkill_psi_full = Some(expect_float(key, line, value)?),
+                "cutoff_psi_window" => {
+                    self.cutoff_psi_window = Some(expect_float(key, line, value)?)
+                }
+                "psi_window_length" => {
+                    self.psi_window_length = expect_parsed(key, line, value, parse_duration)?
+                }
+                #[cfg(feature = "glob-ignore")]
+                "unkillables" => self.unkillables = expect_patterns(key, line, value)?,
+                "prefer" => self.prefer = Some(expect_parsed(key, line, value, str::parse)?),
+                "avoid" => self.avoid = Some(expect_parsed(key, line, value, str::parse)?),
+                "prefer_bias" => self.prefer_bias = expect_integer(key, line, value)?,
+                "avoid_bias" => self.avoid_bias = expect_integer(key, line, value)?,
+                "rules_file" => self.rules_file = Some(expect_str(key, line, value)?.into()),
+                "proc_root" => self.proc_root = expect_str(key, line, value)?.into(),
+                "memory_source" => {
+                    self.memory_source = expect_parsed(key, line, value, str::parse)?
+                }
+                "near_terminal" => {
+                    self.near_terminal = expect_parsed(key, line, value, str::parse)?
+                }
+                "ram_terminal" => self.ram_terminal = expect_parsed(key, line, value, str::parse)?,
+                "swap_terminal" => {
+                    self.swap_terminal = expect_parsed(key, line, value, str::parse)?
+                }
+                "ram_fill_rate" => {
+                    self.ram_fill_rate = expect_parsed(key, line, value, str::parse)?
+                }
+                "swap_fill_rate" => {
+                    self.swap_fill_rate = expect_parsed(key, line, value, str::parse)?
+                }
+                "min_sleep" => self.min_sleep = expect_parsed(key, line, value, parse_duration)?,
+                "max_sleep" => self.max_sleep = expect_parsed(key, line, value, parse_duration)?,
+                "psi_trigger" => self.psi_trigger = expect_bool(key, line, value)?,
+                "psi_trigger_stall" => {
+                    self.psi_trigger_stall = expect_parsed(key, line, value, parse_duration)?
+                }
+                "psi_trigger_window" => {
+                    self.psi_trigger_window = expect_parsed(key, line, value, parse_duration)?
+                }
+                "psi_trigger_timeout" => {
+                    self.psi_trigger_timeout = expect_parsed(key, line, value, parse_duration)?
+                }
+                _ => {
+                    return Err(config_error(format!(
+                        "line {}: unknown configuration key `{}`",
+                        line, key
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Command-line flags take precedence over the configuration file
+    fn apply_args(&mut self, args: &CommandLineArgs) {
+        // Switches can only turn options on
+        self.verbose |= args.verbose;
+        self.no_daemon |= args.no_daemon;
+        if let Some(filter) = args.log_level {
+            self.log_level = filter;
+        }
+        if let Some(format) = args.log_format {
+            self.log_format = format;
+        }
+        if args.log_target.is_some() {
+            self.log_target = args.log_target;
+        }
+        if args.log_file.is_some() {
+            self.log_file = args.log_file.clone();
+        }
+        self.kill_pgroup |= args.kill_pgroup;
+        self.dry_run |= args.dry_run;
+        self.notify |= args.notify;
+
+        if let Some(cooldown) = args.kill_cooldown {
+            self.kill_cooldown = cooldown;
+        }
+        if args.pre_kill.is_some() {
+            self.pre_kill = args.pre_kill.clone();
+        }
+        if args.post_kill.is_some() {
+            self.post_kill = args.post_kill.clone();
+        }
+        if let Some(timeout) = args.hook_timeout {
+            self.hook_timeout = timeout;
+        }
+        if let Some(ladder) = &args.signal_ladder {
+            self.signal_ladder = ladder.clone();
+        }
+        if let Some(timeout) = args.exit_timeout {
+            self.exit_timeout = timeout;
+        }
+        if args.notify_warn_psi.is_some() {
+            self.notify_warn_psi = args.notify_warn_psi;
+        }
+        if let Some(interval) = args.notify_warn_interval {
+            self.notify_warn_interval = interval;
+        }
+        if args.metrics_listen.is_some() {
+            self.metrics_listen = args.metrics_listen.clone();
+        }
+        self.control |= args.control;
+        if args.control_socket.is_some() {
+            self.control_socket = args.control_socket.clone();
+        }
+        if args.control_group.is_some() {
+            self.control_group = args.control_group.clone();
+        }
+        if let Some(cutoff_psi) = args.cutoff_psi {
+            self.cutoff_psi = cutoff_psi;
+        }
+        if args.cutoff_psi_full.is_some() {
+            self.cutoff_psi_full = args.cutoff_psi_full;
+        }
+        if args.cutoff_psi_avg60.is_some() {
+            self.cutoff_psi_avg60 = args.cutoff_psi_avg60;
+        }
+        if args.sigkill_psi_full.is_some() {
+            self.sigkill_psi_full = args.sigkill_psi_full;
+        }
+        if args.cutoff_psi_window.is_some() {
+            self.cutoff_psi_window = args.cutoff_psi_window;
+        }
+        if let Some(length) = args.psi_window_length {
+            self.psi_window_length = length;
+        }
+        #[cfg(feature = "glob-ignore")]
+        if let Some(unkillables) = &args.ignored {
+            self.unkillables = unkillables.clone();
+        }
+        if args.prefer.is_some() {
+            self.prefer = args.prefer.clone();
+        }
+        if args.avoid.is_some() {
+            self.avoid = args.avoid.clone();
+        }
+        if let Some(bias) = args.prefer_bias {
+            self.prefer_bias = bias;
+        }
+        if let Some(bias) = args.avoid_bias {
+            self.avoid_bias = bias;
+        }
+        if args.rules_file.is_some() {
+            self.rules_file = args.rules_file.clone();
+        }
+        if let Some(proc_root) = &args.proc_root {
+            self.proc_root = proc_root.clone();
+        }
+        if let Some(memory_source) = args.memory_source {
+            self.memory_source = memory_source;
+        }
+        if let Some(near_terminal) = args.near_terminal {
+            self.near_terminal = near_terminal;
+        }
+        if let Some(ram_terminal) = args.ram_terminal {
+            self.ram_terminal = ram_terminal;
+        }
+        if let Some(swap_terminal) = args.swap_terminal {
+            self.swap_terminal = swap_terminal;
+        }
+        if let Some(ram_fill_rate) = args.ram_fill_rate {
+            self.ram_fill_rate = ram_fill_rate;
+        }
+        if let Some(swap_fill_rate) = args.swap_fill_rate {
+            self.swap_fill_rate = swap_fill_rate;
+        }
+        if let Some(min_sleep) = args.min_sleep {
+            self.min_sleep = min_sleep;
+        }
+        if let Some(max_sleep) = args.max_sleep {
+            self.max_sleep = max_sleep;
+        }
+        self.psi_trigger &= !args.no_psi_trigger;
+        if let Some(stall) = args.psi_trigger_stall {
+            self.psi_trigger_stall = stall;
+        }
+        if let Some(window) = args.psi_trigger_window {
+            self.psi_trigger_window = window;
+        }
+        if let Some(timeout) = args.psi_trigger_timeout {
+            self.psi_trigger_timeout = timeout;
+        }
+    }
+
+    /// Every configuration key along with its value, formatted as in the configuration file,
+    /// or `None` if the option is unset
+    pub fn entries(&self) -> Vec<(&'static str, Option<String>)> {
+        let quoted = |value: &dyn fmt::Display| Some(format!("\"{}\"", value));
+        let float = |value: Option<f32>| value.map(|value| format!("{:?}", value));
+
+        vec![
+            ("verbose", Some(self.verbose.to_string())),
+            ("log_level", quoted(&self.log_level)),
+            ("log_format", quoted(&self.log_format)),
+            (
+                "log_target",
+                self.log_target.and_then(|target| quoted(&target)),
+            ),
+            (
+                "log_file",
+                self.log_file.as_ref().map(|path| format!("{:?}", path)),
+            ),
+            ("no_daemon", Some(self.no_daemon.to_string())),
+            ("kill_pgroup", Some(self.kill_pgroup.to_string())),
+            ("dry_run", Some(self.dry_run.to_string())),
+            (
+                "kill_cooldown",
+                quoted(&format_duration(self.kill_cooldown)),
+            ),
+            (
+                "pre_kill",
+                self.pre_kill
+                    .as_ref()
+                    .map(|command| format!("{:?}", command)),
+            ),
+            (
+                "post_kill",
+                self.post_kill
+                    .as_ref()
+                    .map(|command| format!("{:?}", command)),
+            ),
+            ("hook_timeout", quoted(&format_duration(self.hook_timeout))),
+            ("signal_ladder", quoted(&self.signal_ladder)),
+            ("exit_timeout", quoted(&format_duration(self.exit_timeout))),
+            ("notify", Some(self.notify.to_string())),
+            ("notify_warn_psi", float(self.notify_warn_psi)),
+            (
+                "notify_warn_interval",
+                quoted(&format_duration(self.notify_warn_interval)),
+            ),
+            (
+                "metrics_listen",
+                self.metrics_listen
+                    .as_ref()
+                    .and_then(|address| quoted(address)),
+            ),
+            ("control", Some(self.control.to_string())),
+            (
+                "control_socket",
+                self.control_socket
+                    .as_ref()
+                    .map(|path| format!("{:?}", path)),
+            ),
+            (
+                "control_group",
+                self.control_group
+                    .as_ref()
+                    .map(|group| format!("{:?}", group)),
+            ),
+            ("cutoff_psi", float(Some(self.cutoff_psi))),
+            ("cutoff_psi_full", float(self.cutoff_psi_full)),
+            ("cutoff_psi_avg60", float(self.cutoff_psi_avg60)),
+            ("sigkill_psi_full", float(self.sigkill_psi_full)),
+            ("cutoff_psi_window", float(self.cutoff_psi_window)),
+            (
+                "psi_window_length",
+                quoted(&format_duration(self.psi_window_length)),
+            ),
+            #[cfg(feature = "glob-ignore")]
+            ("unkillables", {
+                let patterns: Vec<_> = self
+                    .unkillables
+                    .iter()
+                    .map(|pattern| format!("{:?}", pattern.as_str()))
+                    .collect();
+                Some(format!("[{}]", patterns.join(", ")))
+            }),
+            (
+                "prefer",
+                self.prefer
+                    .as_ref()
+                    .map(|pattern| format!("{:?}", pattern.to_string())),
+            ),
+            (
+                "avoid",
+                self.avoid
+                    .as_ref()
+                    .map(|pattern| format!("{:?}", pattern.to_string())),
+            ),
+            ("prefer_bias", Some(self.prefer_bias.to_string())),
+            ("avoid_bias", Some(self.avoid_bias.to_string())),
+            (
+                "rules_file",
+                self.rules_file.as_ref().map(|path| format!("{:?}", path)),
+            ),
+            ("proc_root", Some(format!("{:?}", self.proc_root))),
+            ("memory_source", quoted(&self.memory_source)),
+            ("near_terminal", quoted(&self.near_terminal)),
+            ("ram_terminal", quoted(&self.ram_terminal)),
+            ("swap_terminal", quoted(&self.swap_terminal)),
+            ("ram_fill_rate", quoted(&self.ram_fill_rate)),
+            ("swap_fill_rate", quoted(&self.swap_fill_rate)),
+            ("min_sleep", quoted(&format_duration(self.min_sleep))),
+            ("max_sleep", quoted(&format_duration(self.max_sleep))),
+            ("psi_trigger", Some(self.psi_trigger.to_string())),
+            (
+                "psi_trigger_stall",
+                quoted(&format_duration(self.psi_trigger_stall)),
+            ),
+            (
+                "psi_trigger_window",
+                quoted(&format_duration(self.psi_trigger_window)),
+            ),
+            (
+                "psi_trigger_timeout",
+                quoted(&format_duration(self.psi_trigger_timeout)),
+            ),
+        ]
+    }
+
+    /// Lists the keys whose values differ from the ones in `new`, as `(key, old value, new value)`
+    pub fn changes(&self, new: &Config) -> Vec<(&'static str, String, String)> {
+        let or_unset = |value: Option<String>| value.unwrap_or_else(|| "unset".into());
+
+        self.entries()
+            .into_iter()
+            .zip(new.entries())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((key, old), (_, new))| (key, or_unset(old), or_unset(new)))
+            .collect()
+    }
+
+    /// Checks for values that are well-formed but make no sense together
+    pub fn validate(&self) -> Result<()> {
+        let cutoffs = [
+            ("cutoff_psi", Some(self.cutoff_psi)),
+            ("cutoff_psi_full", self.cutoff_psi_full),
+            ("cutoff_psi_avg60", self.cutoff_psi_avg60),
+            ("sigkill_psi_full", self.sigkill_psi_full),
+            ("cutoff_psi_window", self.cutoff_psi_window),
+            ("notify_warn_psi", self.notify_warn_psi),
+        ];
+        for (key, cutoff) in cutoffs {
+            if matches!(cutoff, Some(cutoff) if !(0.0..=100.0).contains(&cutoff)) {
+                return Err(config_error(format!("`{}` must be between 0 and 100", key)));
+            }
+        }
+        if self.prefer_bias > MAX_BIAS || self.avoid_bias > MAX_BIAS {
+            return Err(config_error(format!(
+                "`prefer_bias` and `avoid_bias` must be at most {}",
+                MAX_BIAS
+            )));
+        }
+        let proc_root_len = self.proc_root.as_os_str().len();
+        if !self.proc_root.is_absolute() || proc_root_len > MAX_PROC_ROOT_LEN {
+            return Err(config_error(format!(
+                "`proc_root` must be an absolute path of at most {} bytes",
+                MAX_PROC_ROOT_LEN
+            )));
+        }
+        if self.psi_window_length.is_zero() {
+            return Err(config_error(
+                "`psi_window_length` must be greater than zero",
+            ));
+        }
+        if self.ram_fill_rate.bytes() == 0 || self.swap_fill_rate.bytes() == 0 {
+            return Err(config_error("fill rates must be greater than zero"));
+        }
+        if self.min_sleep.is_zero() {
+            return Err(config_error("`min_sleep` must be greater than zero"));
+        }
+        // Limits imposed by the kernel on PSI triggers
+        let window_range = Duration::from_millis(500)..=Duration::from_secs(10);
+        if !window_range.contains(&self.psi_trigger_window) {
+            return Err(config_error(
+                "`psi_trigger_window` must be between 500ms and 10s",
+            ));
+        }
+        if self.psi_trigger_stall.is_zero() || self.psi_trigger_stall > self.psi_trigger_window {
+            return Err(config_error(
+                "`psi_trigger_stall` must be greater than zero and not greater than `psi_trigger_window`",
+            ));
+        }
+        if self.psi_trigger_timeout.is_zero() {
+            return Err(config_error(
+                "`psi_trigger_timeout` must be greater than zero",
+            ));
+        }
+        if self.min_sleep > self.max_sleep {
+            return Err(config_error(
+                "`min_sleep` must not be greater than `max_sleep`",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Displays the configuration in the same format the configuration file is read from
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in self.entries() {
+            match value {
+                Some(value) => writeln!(f, "{} = {}", key, value)?,
+                None => writeln!(f, "# {} is unset", key)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ByteSize, Config, Format, MemorySource, Target, Threshold};
+
+    #[test]
+    fn should_read_human_units() {
+        let config = Config::from_toml(
+            r#"
+            cutoff_psi = 10
+            near_terminal = "512M"
+            ram_terminal = "5%"
+            min_sleep = "250ms"
+            max_sleep = "2s"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.cutoff_psi, 10.0);
+        assert_eq!(config.near_terminal, Threshold::Size(ByteSize(512 << 20)));
+        assert_eq!(config.ram_terminal, Threshold::Percent(5.0));
+        assert_eq!(config.min_sleep, Duration::from_millis(250));
+        assert_eq!(config.max_sleep, Duration::from_secs(2));
+        assert_eq!(config.swap_terminal, Config::default().swap_terminal);
+    }
+
+    #[test]
+    fn should_reject_unknown_keys_and_wrong_types() {
+        assert!(Config::from_toml("cutof_psi = 10").is_err());
+        assert!(Config::from_toml("verbose = \"yes\"").is_err());
+        assert!(Config::from_toml("min_sleep = 100").is_err());
+        assert!(Config::from_toml("[section]").is_err());
+        assert!(Config::from_toml("prefer = \"(firefox\"").is_err());
+        assert!(Config::from_toml("avoid_bias = -1").is_err());
+        assert!(Config::from_toml("signal_ladder = \"SIGTERM 5s\"").is_err());
+    }
+
+    #[test]
+    fn printed_configuration_should_be_readable() {
+        let config = Config {
+            cutoff_psi: 12.5,
+            cutoff_psi_full: Some(5.0),
+            near_terminal: Threshold::Size(ByteSize(1 << 30)),
+            max_sleep: Duration::from_millis(1500),
+            dry_run: true,
+            kill_cooldown: Duration::from_secs(30),
+            notify: true,
+            notify_warn_psi: Some(15.0),
+            pre_kill: Some("logger \"about to kill $BUSTD_COMM\"".into()),
+            memory_source: MemorySource::Custom,
+            log_level: "warn,kill=trace".parse().unwrap(),
+            log_format: Format::Json,
+            log_target: Some(Target::Syslog),
+            log_file: Some("/var/log/bustd/bustd.log".into()),
+            metrics_listen: Some("unix:/run/bustd/metrics.sock".parse().unwrap()),
+            control: false,
+            control_socket: Some("/run/bustd.sock".into()),
+            control_group: Some("wheel".into()),
+            prefer: Some(r#"^(firefox|chrom(e|ium))$|\.test"#.parse().unwrap()),
+            avoid_bias: 500,
+            rules_file: Some("/etc/bustd/rules.toml".into()),
+            signal_ladder: "SIGTERM 5s SIGINT 1.5s SIGKILL".parse().unwrap(),
+            sigkill_psi_full: Some(60.0),
+            ..Config::default()
+        };
+
+        assert_eq!(Config::from_toml(&config.to_string()).unwrap(), config);
+        assert_eq!(
+            Config::from_toml(&Config::default().to_string()).unwrap(),
+            Config::default()
+        );
+    }
+
+    #[test]
+    fn should_list_changed_keys() {
+        let old = Config::default();
+        let new = Config {
+            cutoff_psi: 10.0,
+            min_sleep: Duration::from_millis(50),
+            ..Config::default()
+        };
+
+        assert_eq!(
+            old.changes(&new),
+            [
+                ("cutoff_psi", "25.0".into(), "10.0".into()),
+                ("min_sleep", "\"100ms\"".into(), "\"50ms\"".into()),
+            ]
+        );
+        assert!(new.changes(&new).is_empty());
+    }
+}
@@ -0,0 +1,432 @@
+//! A parser for the subset of TOML used by bustd's configuration file:
+//! `key = value` pairs, `[table]` and `[[array-of-tables]]` headers, and
+//! strings, integers, floats, booleans and (possibly multi-line) arrays as values.
+
+use std::{iter::Peekable, str::Chars};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    /// The line in which this entry starts, for error reporting
+    pub line: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Table {
+    pub entries: Vec<Entry>,
+}
+
+impl Table {
+    fn insert(&mut self, entry: Entry) -> Result<(), String> {
+        if self.entries.iter().any(|other| other.key == entry.key) {
+            return Err(format!(
+                "line {}: key `{}` was defined more than once",
+                entry.line, entry.key
+            ));
+        }
+
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+/// A table introduced by a `[name]` or `[[name]]` header
+#[derive(Debug)]
+pub struct Section {
+    pub name: String,
+    /// True if this section was declared with `[[name]]`
+    pub is_array: bool,
+    pub line: usize,
+    pub table: Table,
+}
+
+#[derive(Debug, Default)]
+pub struct Document {
+    /// The entries that come before any section header
+    pub root: Table,
+    pub sections: Vec<Section>,
+}
+
+impl Document {
+    fn current_table(&mut self) -> &mut Table {
+        match self.sections.last_mut() {
+            Some(section) => &mut section.table,
+            None => &mut self.root,
+        }
+    }
+}
+
+/// Removes a trailing comment from the given line, if any
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (idx, ch) in line.char_indices() {
+        match (quote, ch) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), ch) if ch == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, '#') => return &line[..idx],
+            _ => {}
+        }
+        escaped = false;
+    }
+
+    line
+}
+
+/// Returns how many more arrays were opened than closed in the given value,
+/// ignoring brackets inside strings
+fn bracket_depth(value: &str) -> i32 {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut depth = 0;
+
+    for ch in value.chars() {
+        match (quote, ch) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), ch) if ch == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            _ => {}
+        }
+        escaped = false;
+    }
+
+    depth
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-')
+}
+
+pub fn parse(input: &str) -> Result<Document, String> {
+    let mut document = Document::default();
+    let mut lines = input.lines().enumerate();
+
+    while let Some((idx, line)) = lines.next() {
+        let line_no = idx + 1;
+        let line = strip_comment(line).trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let (name, is_array) = match header.strip_prefix('[') {
+                Some(rest) => (rest.strip_suffix("]]"), true),
+                None => (header.strip_suffix(']'), false),
+            };
+            let name = name
+                .map(str::trim)
+                .filter(|name| is_valid_key(name))
+                .ok_or_else(|| format!("line {}: malformed table header", line_no))?;
+
+            let redefined = document
+                .sections
+                .iter()
+                .any(|section| section.name == name && !(is_array && section.is_array));
+            if redefined {
+                return Err(format!(
+                    "line {}: table `{}` was defined more than once",
+                    line_no, name
+                ));
+            }
+
+            document.sections.push(Section {
+                name: name.into(),
+                is_array,
+                line: line_no,
+                table: Table::default(),
+            });
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `key = value`", line_no))?;
+        let key = key.trim();
+        if !is_valid_key(key) {
+            return Err(format!("line {}: invalid key `{}`", line_no, key));
+        }
+
+        // Arrays may span several lines
+        let mut value = value.trim().to_string();
+        while bracket_depth(&value) > 0 {
+            let (_, next_line) = lines
+                .next()
+                .ok_or_else(|| format!("line {}: unterminated array", line_no))?;
+            value.push(' ');
+            value.push_str(strip_comment(next_line).trim());
+        }
+
+        let value = ValueParser::new(&value)
+            .parse_complete()
+            .map_err(|err| format!("line {}: {}", line_no, err))?;
+
+        document.current_table().insert(Entry {
+            key: key.into(),
+            value,
+            line: line_no,
+        })?;
+    }
+
+    Ok(document)
+}
+
+struct ValueParser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> ValueParser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            chars: input.chars().peekable(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some(ch) if ch.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn parse_complete(mut self) -> Result<Value, String> {
+        let value = self.parse_value()?;
+        self.skip_whitespace();
+
+        match self.chars.next() {
+            None => Ok(value),
+            Some(ch) => Err(format!("unexpected `{}` after value", ch)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+
+        match self.chars.peek() {
+            Some('"') => self.parse_basic_string().map(Value::String),
+            Some('\'') => self.parse_literal_string().map(Value::String),
+            Some('[') => self.parse_array(),
+            Some(_) => self.parse_scalar(),
+            None => Err("missing value".into()),
+        }
+    }
+
+    fn parse_basic_string(&mut self) -> Result<String, String> {
+        // Eat the opening quote
+        self.chars.next();
+        let mut string = String::new();
+
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match self.chars.next() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some(ch) => return Err(format!("unknown escape sequence `\\{}`", ch)),
+                    None => break,
+                },
+                Some(ch) => string.push(ch),
+                None => break,
+            }
+        }
+
+        Err("unterminated string".into())
+    }
+
+    fn parse_literal_string(&mut self) -> Result<String, String> {
+        // Eat the opening quote
+        self.chars.next();
+        let mut string = String::new();
+
+        for ch in &mut self.chars {
+            if ch == '\'' {
+                return Ok(string);
+            }
+            string.push(ch);
+        }
+
+        Err("unterminated string".into())
+    }
+
+    fn parse_array(&mut self) -> Result<Value, String> {
+        // Eat the opening bracket
+        self.chars.next();
+        let mut values = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() == Some(&']') {
+                self.chars.next();
+                return Ok(Value::Array(values));
+            }
+
+            values.push(self.parse_value()?);
+
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err("expected `,` or `]` in array".into()),
+            }
+        }
+    }
+
+    fn parse_scalar(&mut self) -> Result<Value, String> {
+        let mut scalar = String::new();
+        while let Some(&ch) = self.chars.peek() {
+            if ch.is_whitespace() || ch == ',' || ch == ']' {
+                break;
+            }
+            scalar.push(ch);
+            self.chars.next();
+        }
+
+        if scalar == "true" || scalar == "false" {
+            return Ok(Value::Boolean(scalar == "true"));
+        }
+
+        // TOML allows underscores between digits
+        let number = scalar.replace('_', "");
+        if let Ok(integer) = number.parse() {
+            return Ok(Value::Integer(integer));
+        }
+        match number.parse::<f64>() {
+            Ok(float) if float.is_finite() => Ok(Value::Float(float)),
+            _ => Err(format!("invalid value `{}`", scalar)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Value};
+
+    #[test]
+    fn should_parse_key_value_pairs() {
+        let document = parse(
+            r#"
+            # A comment
+            name = "bustd # not a comment" # a comment
+            literal = 'C:\path'
+            count = 1_000
+            ratio = 2.5
+            enabled = true
+            "#,
+        )
+        .unwrap();
+
+        let values: Vec<_> = document
+            .root
+            .entries
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.value.clone()))
+            .collect();
+
+        assert_eq!(
+            values,
+            [
+                ("name", Value::String("bustd # not a comment".into())),
+                ("literal", Value::String("C:\\path".into())),
+                ("count", Value::Integer(1000)),
+                ("ratio", Value::Float(2.5)),
+                ("enabled", Value::Boolean(true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_parse_multi_line_arrays() {
+        let document = parse(
+            r#"
+            names = [
+                "firefox", # browsers
+                "chromium",
+            ]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            document.root.entries[0].value,
+            Value::Array(vec![
+                Value::String("firefox".into()),
+                Value::String("chromium".into())
+            ])
+        );
+    }
+
+    #[test]
+    fn should_parse_sections() {
+        let document = parse(
+            r#"
+            top = 1
+            [[rule]]
+            a = 1
+            [[rule]]
+            a = 2
+            [other]
+            b = 3
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(document.root.entries.len(), 1);
+        let sections: Vec<_> = document
+            .sections
+            .iter()
+            .map(|section| (section.name.as_str(), section.is_array))
+            .collect();
+        assert_eq!(sections, [("rule", true), ("rule", true), ("other", false)]);
+        assert_eq!(
+            document.sections[1].table.entries[0].value,
+            Value::Integer(2)
+        );
+    }
+
+    #[test]
+    fn should_reject_malformed_documents() {
+        assert!(parse("key").is_err());
+        assert!(parse("key = ").is_err());
+        assert!(parse("key = \"unterminated").is_err());
+        assert!(parse("key = [1, 2").is_err());
+        assert!(parse("a = 1\na = 2").is_err());
+        assert!(parse("[table]\n[table]").is_err());
+        assert!(parse("key = 1 2").is_err());
+    }
+}
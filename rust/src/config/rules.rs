@@ -0,0 +1,344 @@
+//! Per-process policies, read from the rules file given through `rules_file`.
+//! Each `[[rule]]` table matches processes on a few conditions, all of which must hold,
+//! and decides how they're treated when memory runs out. The first matching rule applies.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use libc::SIGTERM;
+
+use super::{
+    config_error, expect_bool, expect_integer, expect_parsed, expect_str, invalid_value,
+    ladder::{parse_signal, DEFAULT_GRACE_PERIOD},
+    parse_duration,
+    parser::{self, Table, Value},
+    ByteSize, Pattern, SignalLadder,
+};
+use crate::{
+    error::{Error, Result},
+    process::Facts,
+    utils,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    /// The line of the rules file this rule starts at
+    pub line: usize,
+    /// The path of the process' executable
+    pub exe: Option<String>,
+    /// Matched against the process' command line, its arguments separated by spaces
+    pub cmdline: Option<Pattern>,
+    /// Matched against the process' name
+    #[cfg(feature = "glob-ignore")]
+    pub comm: Option<glob::Pattern>,
+    /// The ID of the user owning the process, which may be given by name
+    pub uid: Option<u32>,
+    /// The name of the process' parent
+    pub parent_comm: Option<String>,
+    /// A cgroup the process belongs to, directly or not, e.g. `/system.slice`
+    pub cgroup: Option<PathBuf>,
+    /// When set, matching processes are never chosen
+    pub never_kill: bool,
+    /// Added to the oom_score of matching processes, along with the bias from `prefer` and `avoid`
+    pub bias: i16,
+    /// Whether to kill the process' whole group, overriding `kill_pgroup`
+    pub kill_pgroup: Option<bool>,
+    /// The signals the process is sent, overriding `signal_ladder`.
+    /// `signal` and `grace_period` are shorthands for a ladder with a single signal before SIGKILL.
+    pub signal_ladder: Option<SignalLadder>,
+    /// Matching processes whose RSS goes over this are chosen before any other
+    pub rss_max: Option<ByteSize>,
+}
+
+impl Rule {
+    fn new(line: usize) -> Self {
+        Self {
+            line,
+            exe: None,
+            cmdline: None,
+            #[cfg(feature = "glob-ignore")]
+            comm: None,
+            uid: None,
+            parent_comm: None,
+            cgroup: None,
+            never_kill: false,
+            bias: 0,
+            kill_pgroup: None,
+            signal_ladder: None,
+            rss_max: None,
+        }
+    }
+
+    fn from_table(line: usize, table: &Table) -> Result<Self> {
+        let mut rule = Self::new(line);
+        let mut has_action = false;
+        let (mut signal, mut grace_period) = (None, None);
+
+        for entry in &table.entries {
+            let (key, line, value) = (entry.key.as_str(), entry.line, &entry.value);
+
+            match key {
+                "exe" => rule.exe = Some(expect_str(key, line, value)?.into()),
+                "cmdline" => rule.cmdline = Some(expect_parsed(key, line, value, str::parse)?),
+                #[cfg(feature = "glob-ignore")]
+                "comm" => {
+                    rule.comm = Some(expect_parsed(key, line, value, |pattern| {
+                        glob::Pattern::new(pattern).map_err(|err| err.to_string())
+                    })?)
+                }
+                #[cfg(not(feature = "glob-ignore"))]
+                "comm" => {
+                    return Err(invalid_value(
+                        key,
+                        line,
+                        "matching names requires bustd to be built with the `glob-ignore` feature",
+                    ))
+                }
+                "user" => {
+                    rule.uid = Some(match value {
+                        Value::String(name) => utils::get_user_id(name)
+                            .map_err(|_| invalid_value(key, line, "no such user"))?,
+                        other => expect_integer(key, line, other)?,
+                    })
+                }
+                "parent_comm" => rule.parent_comm = Some(expect_str(key, line, value)?.into()),
+                "cgroup" => {
+                    let cgroup = PathBuf::from(expect_str(key, line, value)?);
+                    if !cgroup.is_absolute() {
+                        return Err(invalid_value(
+                            key,
+                            line,
+                            "expected an absolute path, such as \"/system.slice\"",
+                        ));
+                    }
+                    rule.cgroup = Some(cgroup);
+                }
+                "signal" => {
+                    has_action = true;
+                    signal = Some(expect_parsed(key, line, value, parse_signal)?);
+                }
+                "grace_period" => {
+                    has_action = true;
+                    grace_period = Some(expect_parsed(key, line, value, parse_duration)?);
+                }
+                _ => {
+                    has_action = true;
+                    rule.apply_action(key, line, value)?;
+                }
+            }
+        }
+
+        if signal.is_some() || grace_period.is_some() {
+            if rule.signal_ladder.is_some() {
+                return Err(config_error(format!(
+                    "line {}: rule has both a `signal_ladder` and a `signal` or `grace_period`",
+                    rule.line
+                )));
+            }
+            rule.signal_ladder = Some(SignalLadder::single(
+                signal.unwrap_or(SIGTERM),
+                grace_period.unwrap_or(DEFAULT_GRACE_PERIOD),
+            ));
+        }
+
+        if !has_action {
+            return Err(config_error(format!(
+                "line {}: rule has no action, such as `never_kill` or `signal`",
+                rule.line
+            )));
+        }
+
+        Ok(rule)
+    }
+
+    fn apply_action(&mut self, key: &str, line: usize, value: &Value) -> Result<()> {
+        match key {
+            "never_kill" => self.never_kill = expect_bool(key, line, value)?,
+            "bias" => {
+                self.bias = expect_integer(key, line, value)?;
+                if !(-1000..=1000).contains(&self.bias) {
+                    return Err(invalid_value(key, line, "must be between -1000 and 1000"));
+                }
+            }
+            "kill_pgroup" => self.kill_pgroup = Some(expect_bool(key, line, value)?),
+            "signal_ladder" => {
+                self.signal_ladder = Some(expect_parsed(key, line, value, str::parse)?)
+            }
+            "rss_max" => self.rss_max = Some(expect_parsed(key, line, value, str::parse)?),
+            _ => {
+                return Err(config_error(format!(
+                    "line {}: unknown rule key `{}`",
+                    line, key
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the rules file
+    pub fn from_file(path: &Path) -> Result<Vec<Self>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| config_error(format!("could not read {}: {}", path.display(), err)))?;
+
+        Self::from_toml(&contents).map_err(|err| match err {
+            Error::Config { reason } => config_error(format!("{}: {}", path.display(), reason)),
+            err => err,
+        })
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Vec<Self>> {
+        let document = parser::parse(contents).map_err(config_error)?;
+
+        if let Some(entry) = document.root.entries.first() {
+            return Err(config_error(format!(
+                "line {}: `{}` must be within a [[rule]] table",
+                entry.line, entry.key
+            )));
+        }
+
+        document
+            .sections
+            .iter()
+            .map(|section| {
+                if section.name != "rule" || !section.is_array {
+                    return Err(config_error(format!(
+                        "line {}: unexpected table `{}`, rules are written as [[rule]]",
+                        section.line, section.name
+                    )));
+                }
+                Self::from_table(section.line, &section.table)
+            })
+            .collect()
+    }
+
+    /// Returns true if the process meets every condition of this rule
+    pub fn matches(&self, facts: &Facts) -> bool {
+        #[cfg(feature = "glob-ignore")]
+        if let Some(comm) = &self.comm {
+            if !facts.comm().is_some_and(|name| comm.matches(name)) {
+                return false;
+            }
+        }
+
+        self.uid.is_none_or(|uid| facts.uid() == Some(uid))
+            && self
+                .exe
+                .as_deref()
+                .is_none_or(|exe| facts.exe() == Some(exe))
+            && self
+                .parent_comm
+                .as_deref()
+                .is_none_or(|comm| facts.parent_comm() == Some(comm))
+            && self.cgroup.as_ref().is_none_or(|cgroup| {
+                facts
+                    .cgroup()
+                    .is_some_and(|path| Path::new(path).starts_with(cgroup))
+            })
+            && self.cmdline.as_ref().is_none_or(|pattern| {
+                facts
+                    .cmdline()
+                    .is_some_and(|cmdline| pattern.is_match(cmdline))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use libc::{SIGINT, SIGTERM};
+
+    use super::Rule;
+    use crate::config::{ByteSize, SignalLadder};
+    use crate::process::{Facts, Process};
+
+    #[test]
+    fn should_read_rules() {
+        let rules = Rule::from_toml(
+            r#"
+            [[rule]]
+            exe = "/usr/sbin/sshd"
+            never_kill = true
+
+            # Give build servers a chance to save their state
+            [[rule]]
+            cmdline = "java .*-jar build-server\\.jar"
+            user = 1000
+            signal = "SIGINT"
+            grace_period = "10s"
+            kill_pgroup = true
+
+            [[rule]]
+            cgroup = "/user.slice"
+            bias = -200
+            rss_max = "4G"
+
+            [[rule]]
+            exe = "/usr/bin/postgres"
+            signal_ladder = "SIGTERM 5s SIGINT 2s SIGKILL"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(rules.len(), 4);
+        assert_eq!(rules[0].line, 2);
+        assert_eq!(rules[0].exe.as_deref(), Some("/usr/sbin/sshd"));
+        assert!(rules[0].never_kill);
+        assert_eq!(rules[1].uid, Some(1000));
+        assert_eq!(
+            rules[1].signal_ladder,
+            Some(SignalLadder::single(SIGINT, Duration::from_secs(10)))
+        );
+        assert_eq!(rules[1].kill_pgroup, Some(true));
+        assert_eq!(rules[2].bias, -200);
+        assert_eq!(rules[2].rss_max, Some(ByteSize(4 << 30)));
+        assert_eq!(
+            rules[3].signal_ladder.as_ref().map(|ladder| ladder.rungs()),
+            Some(
+                &[
+                    (SIGTERM, Duration::from_secs(5)),
+                    (SIGINT, Duration::from_secs(2))
+                ][..]
+            )
+        );
+    }
+
+    #[test]
+    fn should_reject_malformed_rules() {
+        // No action
+        assert!(Rule::from_toml("[[rule]]\nexe = \"/usr/bin/sleep\"").is_err());
+        // Not within a rule
+        assert!(Rule::from_toml("never_kill = true").is_err());
+        assert!(Rule::from_toml("[rule]\nnever_kill = true").is_err());
+        assert!(Rule::from_toml("[[rule]]\nsignal = \"SIGFOO\"").is_err());
+        assert!(Rule::from_toml("[[rule]]\nbias = 1001").is_err());
+        assert!(Rule::from_toml("[[rule]]\ncgroup = \"user.slice\"\nbias = 10").is_err());
+        assert!(Rule::from_toml("[[rule]]\nuser = \"no-such-user-here\"\nbias = 10").is_err());
+        assert!(Rule::from_toml("[[rule]]\nnever_kil = true").is_err());
+        assert!(Rule::from_toml("[[rule]]\nsignal_ladder = \"SIGTERM 1s\"").is_err());
+        assert!(
+            Rule::from_toml("[[rule]]\nsignal = \"INT\"\nsignal_ladder = \"SIGKILL\"").is_err()
+        );
+    }
+
+    #[test]
+    fn should_match_processes() {
+        let mut buf = [0_u8; 128];
+        let process = Process::this(&mut buf).unwrap();
+        let facts = Facts::new(&process, "/proc".as_ref());
+        let exe = std::env::current_exe().unwrap();
+
+        let rules = Rule::from_toml(&format!(
+            "[[rule]]\nexe = {:?}\ncgroup = \"/\"\nnever_kill = true\n\
+             [[rule]]\nexe = \"/usr/bin/nothing-like-this\"\nnever_kill = true",
+            exe
+        ))
+        .unwrap();
+
+        assert!(rules[0].matches(&facts));
+        assert!(!rules[1].matches(&facts));
+    }
+}
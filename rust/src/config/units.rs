@@ -0,0 +1,234 @@
+//! Human-friendly units used by the configuration file and the command-line flags,
+//! such as `512M`, `10%` and `250ms`.
+
+use std::{fmt, str::FromStr, time::Duration};
+
+const KIB: u64 = 1024;
+const SIZE_SUFFIXES: [(char, u64); 4] = [
+    ('T', KIB * KIB * KIB * KIB),
+    ('G', KIB * KIB * KIB),
+    ('M', KIB * KIB),
+    ('K', KIB),
+];
+
+/// Splits strings such as `250ms` or `1.5G` into their numeric part and their unit
+fn split_unit(input: &str) -> (&str, &str) {
+    let unit_start = input
+        .find(|ch: char| !(ch.is_ascii_digit() || ch == '.'))
+        .unwrap_or(input.len());
+
+    let (number, unit) = input.split_at(unit_start);
+    (number, unit.trim())
+}
+
+fn parse_number(number: &str, input: &str) -> Result<f64, String> {
+    match number.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(format!("\"{}\" does not start with a valid number", input)),
+    }
+}
+
+/// An amount of memory, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    pub fn bytes(self) -> u64 {
+        self.0
+    }
+
+    pub fn kib(self) -> f64 {
+        self.0 as f64 / KIB as f64
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    /// Parses sizes such as `4096`, `512M`, `1.5GiB` or `100KB`.
+    /// Suffixes are always interpreted as powers of 1024.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        let (number, unit) = split_unit(input);
+        let number = parse_number(number, input)?;
+
+        let unit = unit.to_ascii_uppercase();
+        let unit = unit
+            .strip_suffix("IB")
+            .or_else(|| unit.strip_suffix('B'))
+            .unwrap_or(&unit);
+
+        let multiplier = match unit {
+            "" => 1,
+            unit => SIZE_SUFFIXES
+                .iter()
+                .find(|(suffix, _)| unit.len() == 1 && unit.starts_with(*suffix))
+                .map(|(_, multiplier)| *multiplier)
+                .ok_or_else(|| format!("\"{}\" has an unknown size unit", input))?,
+        };
+
+        Ok(Self((number * multiplier as f64) as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    /// Displays the size with the largest unit that represents it exactly
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.0;
+        for (suffix, multiplier) in SIZE_SUFFIXES {
+            if bytes != 0 && bytes % multiplier == 0 {
+                return write!(f, "{}{}", bytes / multiplier, suffix);
+            }
+        }
+
+        write!(f, "{}", bytes)
+    }
+}
+
+/// A memory threshold, either relative to the total amount of memory
+/// (e.g. `10%`) or absolute (e.g. `512M`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    Percent(f32),
+    Size(ByteSize),
+}
+
+impl Threshold {
+    /// The value of this threshold in megabytes, given the total amount of
+    /// memory (in megabytes) it's relative to
+    pub fn in_mb(self, total_mb: u64) -> f64 {
+        match self {
+            Threshold::Percent(percent) => total_mb as f64 * percent as f64 / 100.0,
+            Threshold::Size(size) => size.bytes() as f64 / 1000.0 / 1000.0,
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+
+        match input.strip_suffix('%') {
+            Some(percent) => {
+                let percent = parse_number(percent.trim(), input)?;
+                if !(0.0..=100.0).contains(&percent) {
+                    return Err(format!(
+                        "\"{}\" is not a percentage between 0 and 100",
+                        input
+                    ));
+                }
+                Ok(Threshold::Percent(percent as f32))
+            }
+            None => input.parse().map(Threshold::Size),
+        }
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Threshold::Percent(percent) => write!(f, "{}%", percent),
+            Threshold::Size(size) => write!(f, "{}", size),
+        }
+    }
+}
+
+/// Parses durations such as `250ms`, `1.5s`, `2m` or `1h`
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let (number, unit) = split_unit(input);
+    let number = parse_number(number, input)?;
+
+    let secs = match unit {
+        "us" => number / 1_000_000.0,
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 60.0 * 60.0,
+        "" => {
+            return Err(format!(
+                "\"{}\" is missing a unit (us, ms, s, m or h)",
+                input
+            ))
+        }
+        _ => return Err(format!("\"{}\" has an unknown duration unit", input)),
+    };
+
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// Displays the duration with the largest unit that represents it exactly,
+/// in a format accepted by [`parse_duration`]
+pub fn format_duration(duration: Duration) -> String {
+    let micros = duration.as_micros();
+    let units: [(&str, u128); 4] = [
+        ("h", 60 * 60 * 1_000_000),
+        ("m", 60 * 1_000_000),
+        ("s", 1_000_000),
+        ("ms", 1000),
+    ];
+
+    if micros == 0 {
+        return "0s".into();
+    }
+
+    for (suffix, multiplier) in units {
+        if micros % multiplier == 0 {
+            return format!("{}{}", micros / multiplier, suffix);
+        }
+    }
+
+    format!("{}us", micros)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format_duration, parse_duration, ByteSize, Threshold};
+
+    #[test]
+    fn should_parse_sizes() {
+        assert_eq!("4096".parse(), Ok(ByteSize(4096)));
+        assert_eq!("512M".parse(), Ok(ByteSize(512 * 1024 * 1024)));
+        assert_eq!("512MiB".parse(), Ok(ByteSize(512 * 1024 * 1024)));
+        assert_eq!("100kb".parse(), Ok(ByteSize(100 * 1024)));
+        assert_eq!("1.5G".parse(), Ok(ByteSize(3 * 512 * 1024 * 1024)));
+        assert!("12Q".parse::<ByteSize>().is_err());
+        assert!("M".parse::<ByteSize>().is_err());
+    }
+
+    #[test]
+    fn should_display_sizes_with_the_largest_exact_unit() {
+        assert_eq!(ByteSize(512 * 1024 * 1024).to_string(), "512M");
+        assert_eq!(ByteSize(1536 * 1024).to_string(), "1536K");
+        assert_eq!(ByteSize(1000).to_string(), "1000");
+        assert_eq!(ByteSize(0).to_string(), "0");
+    }
+
+    #[test]
+    fn should_parse_thresholds() {
+        assert_eq!("10%".parse(), Ok(Threshold::Percent(10.0)));
+        assert_eq!("512M".parse(), Ok(Threshold::Size(ByteSize(512 << 20))));
+        assert!("101%".parse::<Threshold>().is_err());
+
+        assert_eq!(Threshold::Percent(10.0).in_mb(2000), 200.0);
+        assert_eq!(Threshold::Size(ByteSize(5_000_000)).in_mb(2000), 5.0);
+    }
+
+    #[test]
+    fn should_parse_and_format_durations() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_duration("250").is_err());
+        assert!(parse_duration("3 days").is_err());
+
+        assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+        assert_eq!(format_duration(Duration::from_secs(120)), "2m");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1500ms");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+    }
+}
@@ -0,0 +1,409 @@
+//! A Unix socket through which a running daemon can be queried and controlled.
+//!
+//! Clients send a single JSON object on one line, e.g. `{"command":"pause","duration":"10m"}`,
+//! and get a single JSON object back, with `ok` set to whether the command succeeded and
+//! `error` set to why it didn't. The commands are `status`, `pause`, `resume`, `evaluate`
+//! and `reload`.
+//!
+//! Only the daemon's user can connect, unless `control_group` is set, in which case
+//! that group can as well.
+//!
+//! Clients are served from the monitor loop without ever blocking it: their sockets are
+//! non-blocking, and requests are answered once they've fully arrived.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use memchr::memchr;
+
+use crate::config::{format_duration, parse_duration};
+use crate::error::{Error, Result};
+use crate::json::{self, JsonObject};
+use crate::utils;
+
+/// How long clients get to send their request, after which they're dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How many clients may be waited on at once, others being left in the listen backlog
+pub const MAX_CLIENTS: usize = 8;
+
+/// Requests are short, so anything longer is rejected
+const MAX_REQUEST_LEN: usize = 1024;
+
+/// How long clients wait for the daemon to answer, which may involve killing a process
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// Reports the daemon's readings, configuration and recent kills
+    Status,
+    /// Stops killing processes for the given amount of time
+    Pause(Duration),
+    Resume,
+    /// Checks memory right away, killing a process if needed
+    Evaluate,
+    /// Reloads the configuration file, as on SIGHUP
+    Reload,
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(request: &str) -> std::result::Result<Self, Self::Err> {
+        let request = json::parse(request)?;
+        let command = request
+            .get("command")
+            .and_then(json::Value::as_str)
+            .ok_or("expected an object with a `command`")?;
+
+        match command {
+            "status" => Ok(Request::Status),
+            "pause" => {
+                // Pausing for good would be too easy to forget about
+                let duration = request
+                    .get("duration")
+                    .and_then(json::Value::as_str)
+                    .ok_or("`pause` needs a `duration`, e.g. \"10m\"")?;
+                parse_duration(duration).map(Request::Pause)
+            }
+            "resume" => Ok(Request::Resume),
+            "evaluate" => Ok(Request::Evaluate),
+            "reload" => Ok(Request::Reload),
+            _ => Err(format!(
+                "unknown command \"{}\", expected status, pause, resume, evaluate or reload",
+                command
+            )),
+        }
+    }
+}
+
+/// Requests are sent as they're parsed, as a JSON object
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut object = JsonObject::new(f)?;
+        match self {
+            Request::Status => object.string("command", "status")?,
+            Request::Pause(duration) => {
+                object.string("command", "pause")?;
+                object.string("duration", format_duration(*duration))?;
+            }
+            Request::Resume => object.string("command", "resume")?,
+            Request::Evaluate => object.string("command", "evaluate")?,
+            Request::Reload => object.string("command", "reload")?,
+        }
+        object.finish()
+    }
+}
+
+/// Sends a request to the daemon listening on `path`, returning its response
+/// if the request succeeded
+pub fn send(path: &Path, request: Request) -> Result<json::Value> {
+    let control_error = |reason: String| Error::Control { reason };
+
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    stream.set_write_timeout(Some(RESPONSE_TIMEOUT))?;
+    writeln!(stream, "{}", request)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response = json::parse(&line)
+        .map_err(|reason| control_error(format!("unexpected response: {}", reason)))?;
+
+    match response.get("ok").and_then(json::Value::as_bool) {
+        Some(true) => Ok(response),
+        _ => Err(control_error(
+            response
+                .get("error")
+                .and_then(json::Value::as_str)
+                .unwrap_or("the request failed")
+                .into(),
+        )),
+    }
+}
+
+fn write_response(
+    out: &mut String,
+    ok: bool,
+    fill: impl FnOnce(&mut JsonObject<String>) -> fmt::Result,
+) -> fmt::Result {
+    let mut object = JsonObject::new(out)?;
+    object.boolean("ok", ok)?;
+    fill(&mut object)?;
+    object.finish()
+}
+
+/// A successful response, whose fields are filled in by `fill`
+pub fn ok_response(fill: impl FnOnce(&mut JsonObject<String>) -> fmt::Result) -> String {
+    let mut response = String::new();
+    // Writing to a String can't fail
+    let _ = write_response(&mut response, true, fill);
+    response
+}
+
+pub fn error_response(reason: &str) -> String {
+    let mut response = String::new();
+    let _ = write_response(&mut response, false, |object| {
+        object.string("error", reason)
+    });
+    response
+}
+
+/// A client that connected to the control socket, whose request may not have arrived yet
+pub struct Connection {
+    stream: UnixStream,
+    /// What was read of the request so far
+    request: Vec<u8>,
+    accepted: Instant,
+}
+
+impl Connection {
+    /// Reads whatever the client sent without blocking, returning its request, or why it's
+    /// invalid, once a whole line has arrived. Fails with `UnexpectedEof` if the client
+    /// went away without sending anything.
+    pub fn read_request(&mut self) -> io::Result<Option<std::result::Result<Request, String>>> {
+        let mut buf = [0_u8; 256];
+
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) if self.request.is_empty() => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(0) => return Ok(Some(Err("requests must end with a newline".into()))),
+                Ok(read) => self.request.extend_from_slice(&buf[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+
+            if let Some(end) = memchr(b'\n', &self.request) {
+                return Ok(Some(match std::str::from_utf8(&self.request[..end]) {
+                    Ok(line) => line.parse(),
+                    Err(_) => Err("requests must be valid UTF-8".into()),
+                }));
+            }
+            if self.request.len() > MAX_REQUEST_LEN {
+                return Ok(Some(
+                    Err("requests must fit on a single, short line".into()),
+                ));
+            }
+        }
+    }
+
+    /// When the client will have had its time to send its request
+    pub fn deadline(&self) -> Instant {
+        self.accepted + CLIENT_TIMEOUT
+    }
+
+    /// Returns true once the client has had its time to send its request
+    pub fn timed_out(&self) -> bool {
+        Instant::now() >= self.deadline()
+    }
+
+    /// Sends the response without blocking, which fails if the client doesn't make room for it
+    pub fn respond(mut self, response: &str) -> io::Result<()> {
+        let mut message = Vec::with_capacity(response.len() + 1);
+        message.extend_from_slice(response.as_bytes());
+        message.push(b'\n');
+
+        self.stream.write_all(&message)
+    }
+}
+
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+/// The listening control socket, removed once dropped
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Server {
+    /// Binds the socket, only letting the daemon's user and `group`, if given, connect to it
+    pub fn bind(path: &Path, group: Option<&str>) -> Result<Self> {
+        let gid = group.map(utils::get_group_id).transpose()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let listener = utils::bind_unix_socket(path)?;
+        // Removes the socket if anything below fails
+        let server = Self {
+            listener,
+            path: path.into(),
+        };
+
+        let mode = if gid.is_some() { 0o660 } else { 0o600 };
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        if let Some(gid) = gid {
+            std::os::unix::fs::chown(path, None, Some(gid))?;
+        }
+
+        Ok(server)
+    }
+
+    /// Accepts the next pending connection, if any
+    pub fn accept(&self) -> io::Result<Option<Connection>> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        stream.set_nonblocking(true)?;
+
+        Ok(Some(Connection {
+            stream,
+            request: Vec::new(),
+            accepted: Instant::now(),
+        }))
+    }
+}
+
+impl AsRawFd for Server {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    use super::{error_response, ok_response, send, Request, Server};
+
+    #[test]
+    fn should_parse_requests() {
+        assert_eq!(r#"{"command":"status"}"#.parse(), Ok(Request::Status));
+        assert_eq!(
+            r#"{"command": "pause", "duration": "10m"}"#.parse(),
+            Ok(Request::Pause(Duration::from_secs(600)))
+        );
+        assert!(r#"{"command":"pause"}"#.parse::<Request>().is_err());
+        assert!(r#"{"command":"pause","duration":"soon"}"#.parse::<Request>().is_err());
+        assert!(r#"{"command":"kill"}"#.parse::<Request>().is_err());
+        assert!(r#"["status"]"#.parse::<Request>().is_err());
+
+        let pause = Request::Pause(Duration::from_millis(1500));
+        assert_eq!(pause.to_string().parse(), Ok(pause));
+    }
+
+    #[test]
+    fn should_answer_clients() {
+        let path = std::env::temp_dir().join(format!("bustd-control-{}", std::process::id()));
+        let server = Server::bind(&path, None).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"{\"command\":\"resume\"}\n").unwrap();
+
+        let mut connection = server.accept().unwrap().unwrap();
+        assert_eq!(
+            connection.read_request().unwrap(),
+            Some(Ok(Request::Resume))
+        );
+        let response = ok_response(|object| object.number("paused_ms", 0));
+        connection.respond(&response).unwrap();
+        assert!(server.accept().unwrap().is_none());
+
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"ok\":true,\"paused_ms\":0}\n");
+
+        drop(server);
+        assert!(!path.exists());
+        assert!(send(&path, Request::Status).is_err());
+        assert_eq!(
+            error_response("unknown \"command\""),
+            r#"{"ok":false,"error":"unknown \"command\""}"#
+        );
+    }
+
+    #[test]
+    fn clients_should_get_failures_as_errors() {
+        let path = std::env::temp_dir().join(format!("bustd-client-{}", std::process::id()));
+        let server = Server::bind(&path, None).unwrap();
+
+        let client_path = path.clone();
+        let client = std::thread::spawn(move || send(&client_path, Request::Evaluate));
+        let mut connection = loop {
+            match server.accept().unwrap() {
+                Some(connection) => break connection,
+                None => std::thread::sleep(Duration::from_millis(1)),
+            }
+        };
+        let request = loop {
+            match connection.read_request().unwrap() {
+                Some(request) => break request,
+                None => std::thread::sleep(Duration::from_millis(1)),
+            }
+        };
+        assert_eq!(request, Ok(Request::Evaluate));
+        connection.respond(&error_response("no memory")).unwrap();
+
+        let err = client.join().unwrap().unwrap_err();
+        assert!(matches!(err, crate::error::Error::Control { reason } if reason == "no memory"));
+    }
+
+    #[test]
+    fn slow_clients_should_never_block() {
+        let path = std::env::temp_dir().join(format!("bustd-slow-{}", std::process::id()));
+        let server = Server::bind(&path, None).unwrap();
+
+        // Sends its request a piece at a time
+        let mut slow = UnixStream::connect(&path).unwrap();
+        let mut connection = server.accept().unwrap().unwrap();
+        let started = Instant::now();
+        assert_eq!(connection.read_request().unwrap(), None);
+        slow.write_all(b"{\"command\":").unwrap();
+        assert_eq!(connection.read_request().unwrap(), None);
+        slow.write_all(b"\"status\"}\n").unwrap();
+        assert_eq!(
+            connection.read_request().unwrap(),
+            Some(Ok(Request::Status))
+        );
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert!(!connection.timed_out());
+
+        // Never sends anything, and gets dropped once it's had its time
+        let _silent = UnixStream::connect(&path).unwrap();
+        let mut connection = server.accept().unwrap().unwrap();
+        assert_eq!(connection.read_request().unwrap(), None);
+        std::thread::sleep(Duration::from_millis(600));
+        assert!(connection.timed_out());
+
+        // Goes away without sending anything, or before finishing its request
+        drop(UnixStream::connect(&path).unwrap());
+        let mut connection = server.accept().unwrap().unwrap();
+        assert!(connection.read_request().is_err());
+        let mut partial = UnixStream::connect(&path).unwrap();
+        partial.write_all(b"{\"command\"").unwrap();
+        drop(partial);
+        let mut connection = server.accept().unwrap().unwrap();
+        assert!(matches!(connection.read_request(), Ok(Some(Err(_)))));
+
+        // Sends more than any request could be
+        let mut flood = UnixStream::connect(&path).unwrap();
+        flood.write_all(&[b' '; 4096]).unwrap();
+        let mut connection = server.accept().unwrap().unwrap();
+        assert!(matches!(connection.read_request(), Ok(Some(Err(_)))));
+    }
+}
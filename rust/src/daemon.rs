@@ -1,10 +1,79 @@
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
 
 use daemonize::Daemonize;
+use libc::{STDERR_FILENO, STDOUT_FILENO};
 
-use crate::{error::Result, utils};
+use crate::{
+    error::Result,
+    log::{self, Event},
+    process::Process,
+    utils,
+};
 
-pub fn daemonize() -> Result<()> {
+/// Where the PID of the daemonized process is written when running as root
+const SYSTEM_PIDFILE: &str = "/var/run/bustd.pid";
+
+/// Where the PID of the daemonized process is written otherwise
+const USER_PIDFILE: &str = "/tmp/bustd.pid";
+
+/// Where the PID of the daemonized process is written
+pub fn pidfile_path() -> &'static Path {
+    Path::new(if utils::running_as_sudo() {
+        SYSTEM_PIDFILE
+    } else {
+        USER_PIDFILE
+    })
+}
+
+/// Returns the PID of the running daemon, as read from the pidfile of a daemon running as root,
+/// or otherwise from that of one running as any other user, whoever we're running as
+pub fn running_pid() -> Result<Option<u32>> {
+    running_pid_from(&[Path::new(SYSTEM_PIDFILE), Path::new(USER_PIDFILE)])
+}
+
+/// Returns the PID read from the first of the pidfiles whose process is alive.
+/// Pidfiles are left behind when the daemon exits, so the process must still be alive.
+fn running_pid_from(pidfiles: &[&Path]) -> Result<Option<u32>> {
+    for pidfile in pidfiles {
+        let contents = match fs::read_to_string(pidfile) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let pid = contents.trim().parse()?;
+
+        if Process::is_alive_from_pid(pid) {
+            return Ok(Some(pid));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Points stdout and stderr to the end of the given file, creating it if needed.
+/// Called again on reload so that the file can be rotated.
+pub fn redirect_output(log_file: &Path) -> Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)?;
+
+    for fd in [STDOUT_FILENO, STDERR_FILENO] {
+        // Safety: both file descriptors are valid, and dup2 atomically replaces the target one
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Daemonizes the current process. Its output is sent to `log_file` if given,
+/// and discarded otherwise, as is the case when logging to journald or syslog.
+pub fn daemonize(log_file: Option<&Path>) -> Result<()> {
     let running_as_sudo = utils::running_as_sudo();
 
     let username = if running_as_sudo {
@@ -13,39 +82,60 @@ pub fn daemonize() -> Result<()> {
         utils::get_username().unwrap_or_else(|| "nobody".into())
     };
 
-    let open_opts = OpenOptions::new()
-        .truncate(false)
-        .create(true)
-        .write(true)
-        .to_owned();
-
-    let (stdout_path, stderr_path, pidfile_path) = if running_as_sudo {
-        (
-            "/var/log/bustd.out",
-            "/var/log/bustd.err",
-            "/var/run/bustd.pid",
-        )
-    } else {
-        ("/tmp/bustd.out", "/tmp/bustd.err", "/tmp/bustd.pid")
-    };
-
-    let stdout = open_opts.open(stdout_path)?;
-    let stderr = open_opts.open(stderr_path)?;
+    // Open the log file before daemonizing, so that errors are still shown on the terminal
+    if let Some(log_file) = log_file {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)?;
+    }
 
     let daemonize = Daemonize::new()
         .user(&*username)
-        .pid_file(pidfile_path)
+        .pid_file(pidfile_path())
         .chown_pid_file(false)
-        .working_directory("/tmp")
-        .stdout(stdout)
-        .stderr(stderr);
+        .working_directory("/tmp");
 
     daemonize.start()?;
 
-    println!(
-        "[LOG] User {} has started the daemon successfully.",
-        username
-    );
+    if let Some(log_file) = log_file {
+        redirect_output(log_file)?;
+    }
+
+    log::emit(&Event::Daemonized { user: &username });
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::running_pid_from;
+
+    #[test]
+    fn should_find_daemons_running_as_other_users() {
+        let dir = std::env::temp_dir().join(format!("bustd-pidfiles-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (system, user) = (dir.join("system.pid"), dir.join("user.pid"));
+        let pidfiles = [system.as_path(), user.as_path()];
+        assert_eq!(running_pid_from(&pidfiles).unwrap(), None);
+
+        // As seen by a user other than the one running the daemon, which has no pidfile of its own
+        fs::write(&system, format!("{}\n", std::process::id())).unwrap();
+        assert_eq!(
+            running_pid_from(&pidfiles).unwrap(),
+            Some(std::process::id())
+        );
+
+        // A pidfile left behind by a daemon that's gone is skipped
+        fs::write(&system, "4194305\n").unwrap();
+        fs::write(&user, format!("{}\n", std::process::id())).unwrap();
+        assert_eq!(
+            running_pid_from(&pidfiles).unwrap(),
+            Some(std::process::id())
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
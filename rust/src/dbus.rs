@@ -0,0 +1,529 @@
+//! A minimal D-Bus client, just enough to call methods on a session bus.
+//!
+//! Only the parts of the wire protocol needed to marshal `s`, `u`, `i`, `as` and `a{sv}`
+//! arguments are implemented, always in little-endian.
+//!
+//! See https://dbus.freedesktop.org/doc/dbus-specification.html
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+
+// Header field codes
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// The fixed part of the header plus the length of the header field array
+const FIXED_HEADER_LEN: usize = 16;
+
+/// Messages larger than this are rejected instead of read
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+fn dbus_error(reason: impl Into<String>) -> Error {
+    Error::DBus {
+        reason: reason.into(),
+    }
+}
+
+/// Where a bus listens on, as given by `DBUS_SESSION_BUS_ADDRESS`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    Path(PathBuf),
+    Abstract(Vec<u8>),
+}
+
+impl Address {
+    /// Parses addresses such as `unix:path=/run/user/1000/bus,guid=...`.
+    /// When several `;`-separated addresses are given, the first supported one is used.
+    pub fn parse(addresses: &str) -> Option<Self> {
+        addresses.split(';').find_map(|address| {
+            let params = address.strip_prefix("unix:")?;
+            params.split(',').find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                match key {
+                    "path" => Some(Self::Path(PathBuf::from(unescape(value)?))),
+                    "abstract" => Some(Self::Abstract(unescape(value)?.into_bytes())),
+                    _ => None,
+                }
+            })
+        })
+    }
+
+    /// The bus systemd starts for the user with the given UID
+    pub fn user_bus(uid: u32) -> Self {
+        Self::Path(Path::new("/run/user").join(uid.to_string()).join("bus"))
+    }
+
+    fn connect(&self) -> Result<UnixStream> {
+        match self {
+            Self::Path(path) => Ok(UnixStream::connect(path)?),
+            Self::Abstract(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::SocketAddr;
+
+                let address = SocketAddr::from_abstract_name(name)?;
+                Ok(UnixStream::connect_addr(&address)?)
+            }
+        }
+    }
+}
+
+/// Values in D-Bus addresses may contain `%XX` escapes
+fn unescape(value: &str) -> Option<String> {
+    let mut unescaped = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            unescaped.push(byte);
+            continue;
+        }
+        let hex = [bytes.next()?, bytes.next()?];
+        let hex = std::str::from_utf8(&hex).ok()?;
+        unescaped.push(u8::from_str_radix(hex, 16).ok()?);
+    }
+
+    String::from_utf8(unescaped).ok()
+}
+
+/// A method argument
+pub enum Arg<'a> {
+    Uint32(u32),
+    Int32(i32),
+    Str(&'a str),
+    StrArray(&'a [&'a str]),
+    /// `a{sv}`, with byte values only, which is all notification hints need for now
+    ByteDict(&'a [(&'a str, u8)]),
+}
+
+impl Arg<'_> {
+    fn signature(&self) -> &'static str {
+        match self {
+            Arg::Uint32(_) => "u",
+            Arg::Int32(_) => "i",
+            Arg::Str(_) => "s",
+            Arg::StrArray(_) => "as",
+            Arg::ByteDict(_) => "a{sv}",
+        }
+    }
+}
+
+/// Marshals values into a message buffer, keeping track of alignment
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    fn align(&mut self, alignment: usize) {
+        while self.buf.len() % alignment != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.buf.push(byte);
+    }
+
+    fn uint32(&mut self, value: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, string: &str) {
+        self.uint32(string.len() as u32);
+        self.buf.extend_from_slice(string.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, signature: &str) {
+        self.byte(signature.len() as u8);
+        self.buf.extend_from_slice(signature.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// Writes an array whose elements are aligned to `alignment`, filling them in with `elements`
+    fn array(&mut self, alignment: usize, elements: impl FnOnce(&mut Self)) {
+        self.uint32(0);
+        let len_at = self.buf.len() - 4;
+        // The padding up to the first element is not part of the array's length
+        self.align(alignment);
+        let start = self.buf.len();
+
+        elements(self);
+
+        let len = (self.buf.len() - start) as u32;
+        self.buf[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    fn arg(&mut self, arg: &Arg) {
+        match arg {
+            Arg::Uint32(value) => self.uint32(*value),
+            Arg::Int32(value) => self.uint32(*value as u32),
+            Arg::Str(string) => self.string(string),
+            Arg::StrArray(strings) => self.array(4, |writer| {
+                for string in strings.iter() {
+                    writer.string(string);
+                }
+            }),
+            Arg::ByteDict(entries) => self.array(8, |writer| {
+                for (key, value) in entries.iter() {
+                    writer.align(8);
+                    writer.string(key);
+                    writer.signature("y");
+                    writer.byte(*value);
+                }
+            }),
+        }
+    }
+
+    /// Writes a header field whose value is a string-like type
+    fn field(&mut self, code: u8, signature: &str, value: &str) {
+        self.align(8);
+        self.byte(code);
+        self.signature(signature);
+        match signature {
+            "g" => self.signature(value),
+            _ => self.string(value),
+        }
+    }
+}
+
+/// A method to be called on some object
+pub struct MethodCall<'a> {
+    pub destination: &'a str,
+    pub path: &'a str,
+    pub interface: &'a str,
+    pub member: &'a str,
+    pub args: &'a [Arg<'a>],
+}
+
+impl MethodCall<'_> {
+    fn marshal(&self, serial: u32) -> Vec<u8> {
+        let mut body = Writer::new();
+        for arg in self.args {
+            body.arg(arg);
+        }
+        let signature: String = self.args.iter().map(Arg::signature).collect();
+
+        let mut message = Writer::new();
+        message.buf.extend_from_slice(&[b'l', METHOD_CALL, 0, 1]);
+        message.uint32(body.buf.len() as u32);
+        message.uint32(serial);
+        message.array(8, |fields| {
+            fields.field(FIELD_PATH, "o", self.path);
+            fields.field(FIELD_INTERFACE, "s", self.interface);
+            fields.field(FIELD_MEMBER, "s", self.member);
+            fields.field(FIELD_DESTINATION, "s", self.destination);
+            if !signature.is_empty() {
+                fields.field(FIELD_SIGNATURE, "g", &signature);
+            }
+        });
+        message.align(8);
+        message.buf.extend_from_slice(&body.buf);
+
+        message.buf
+    }
+}
+
+/// The parts of a received message we care about
+#[derive(Debug)]
+pub struct Message {
+    pub message_type: u8,
+    #[allow(dead_code)]
+    /// Only needed when replying, which only the tests' stand-in bus does
+    pub serial: u32,
+    pub reply_serial: Option<u32>,
+    pub member: Option<String>,
+    pub signature: Option<String>,
+    pub body: Vec<u8>,
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32> {
+    let bytes = bytes
+        .get(at..at + 4)
+        .ok_or_else(|| dbus_error("truncated message"))?;
+
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn align_to(at: usize, alignment: usize) -> usize {
+    at.div_ceil(alignment) * alignment
+}
+
+impl Message {
+    pub fn read(stream: &mut impl Read) -> Result<Self> {
+        let mut fixed = [0_u8; FIXED_HEADER_LEN];
+        stream.read_exact(&mut fixed)?;
+        if fixed[0] != b'l' {
+            return Err(dbus_error("big-endian messages are not supported"));
+        }
+
+        let body_len = read_u32(&fixed, 4)? as usize;
+        let serial = read_u32(&fixed, 8)?;
+        let fields_len = read_u32(&fixed, 12)? as usize;
+        let total_len = align_to(FIXED_HEADER_LEN + fields_len, 8) + body_len;
+        if total_len > MAX_MESSAGE_LEN {
+            return Err(dbus_error("message too long"));
+        }
+
+        let mut message = vec![0_u8; total_len];
+        message[..FIXED_HEADER_LEN].copy_from_slice(&fixed);
+        stream.read_exact(&mut message[FIXED_HEADER_LEN..])?;
+
+        let mut parsed = Self {
+            message_type: fixed[1],
+            serial,
+            reply_serial: None,
+            member: None,
+            signature: None,
+            body: message[total_len - body_len..].to_vec(),
+        };
+        parsed.read_fields(&message[..FIXED_HEADER_LEN + fields_len])?;
+
+        Ok(parsed)
+    }
+
+    fn read_fields(&mut self, header: &[u8]) -> Result<()> {
+        let truncated = || dbus_error("truncated header field");
+        let mut at = FIXED_HEADER_LEN;
+
+        while at < header.len() {
+            at = align_to(at, 8);
+            let code = *header.get(at).ok_or_else(truncated)?;
+            let signature_len = *header.get(at + 1).ok_or_else(truncated)? as usize;
+            let signature = header
+                .get(at + 2..at + 2 + signature_len)
+                .ok_or_else(truncated)?;
+            at += 3 + signature_len;
+
+            match signature {
+                b"u" => {
+                    at = align_to(at, 4);
+                    let value = read_u32(header, at)?;
+                    if code == FIELD_REPLY_SERIAL {
+                        self.reply_serial = Some(value);
+                    }
+                    at += 4;
+                }
+                b"s" | b"o" => {
+                    at = align_to(at, 4);
+                    let len = read_u32(header, at)? as usize;
+                    let value = header.get(at + 4..at + 4 + len).ok_or_else(truncated)?;
+                    if code == FIELD_MEMBER {
+                        self.member = Some(String::from_utf8_lossy(value).into());
+                    }
+                    at += 4 + len + 1;
+                }
+                b"g" => {
+                    let len = *header.get(at).ok_or_else(truncated)? as usize;
+                    let value = header.get(at + 1..at + 1 + len).ok_or_else(truncated)?;
+                    if code == FIELD_SIGNATURE {
+                        self.signature = Some(String::from_utf8_lossy(value).into());
+                    }
+                    at += 1 + len + 1;
+                }
+                _ => return Err(dbus_error("unexpected header field type")),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the string the body starts with, such as the description of an error
+    pub fn first_string(&self) -> Option<String> {
+        let len = read_u32(&self.body, 0).ok()? as usize;
+        let string = self.body.get(4..4 + len)?;
+
+        Some(String::from_utf8_lossy(string).into())
+    }
+}
+
+/// An authenticated connection to a bus
+pub struct Connection {
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Connection {
+    /// Connects to the bus and authenticates as the calling process' effective user
+    pub fn open(address: &Address, timeout: Duration) -> Result<Self> {
+        let stream = address.connect()?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        Self::authenticate(stream)
+    }
+
+    fn authenticate(mut stream: UnixStream) -> Result<Self> {
+        // Safety: geteuid always succeeds
+        let uid = unsafe { libc::geteuid() };
+        let hex_uid: String = uid
+            .to_string()
+            .bytes()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        // The credentials byte must come before anything else
+        write!(stream, "\0AUTH EXTERNAL {}\r\n", hex_uid)?;
+
+        // Read byte by byte, as anything past the line belongs to the message stream
+        let mut reply = Vec::new();
+        let mut byte = [0_u8];
+        while !reply.ends_with(b"\r\n") && reply.len() < 512 {
+            stream.read_exact(&mut byte)?;
+            reply.push(byte[0]);
+        }
+        let reply = String::from_utf8_lossy(&reply);
+        if !reply.starts_with("OK ") {
+            return Err(dbus_error(format!(
+                "authentication rejected: {}",
+                reply.trim()
+            )));
+        }
+        stream.write_all(b"BEGIN\r\n")?;
+
+        let mut connection = Self { stream, serial: 0 };
+        connection.call(&MethodCall {
+            destination: "org.freedesktop.DBus",
+            path: "/org/freedesktop/DBus",
+            interface: "org.freedesktop.DBus",
+            member: "Hello",
+            args: &[],
+        })?;
+
+        Ok(connection)
+    }
+
+    /// Calls a method and waits for its reply, skipping any signals received meanwhile
+    pub fn call(&mut self, method: &MethodCall) -> Result<Message> {
+        self.serial += 1;
+        self.stream.write_all(&method.marshal(self.serial))?;
+
+        loop {
+            let message = Message::read(&mut self.stream)?;
+            if message.reply_serial != Some(self.serial) {
+                continue;
+            }
+
+            return match message.message_type {
+                METHOD_RETURN => Ok(message),
+                ERROR => Err(dbus_error(format!(
+                    "{} failed: {}",
+                    method.member,
+                    message.first_string().unwrap_or_default()
+                ))),
+                _ => Err(dbus_error("unexpected reply")),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+    use std::thread;
+
+    use super::{read_u32, Address, Arg, Connection, Message, MethodCall, METHOD_RETURN};
+
+    /// Replies to every method call with an empty method return, as a bus would to `Hello`
+    fn method_return(reply_serial: u32) -> Vec<u8> {
+        let mut reply = vec![b'l', METHOD_RETURN, 0, 1];
+        reply.extend_from_slice(&0_u32.to_le_bytes());
+        reply.extend_from_slice(&1_u32.to_le_bytes());
+        // A single REPLY_SERIAL field: code, signature "u", padding, value
+        reply.extend_from_slice(&8_u32.to_le_bytes());
+        reply.extend_from_slice(&[5, 1, b'u', 0]);
+        reply.extend_from_slice(&reply_serial.to_le_bytes());
+
+        reply
+    }
+
+    #[test]
+    fn should_parse_bus_addresses() {
+        assert_eq!(
+            Address::parse("unix:path=/run/user/1000/bus,guid=abc"),
+            Some(Address::Path(PathBuf::from("/run/user/1000/bus")))
+        );
+        assert_eq!(
+            Address::parse("tcp:host=localhost;unix:abstract=/tmp/dbus%2dx"),
+            Some(Address::Abstract(b"/tmp/dbus-x".to_vec()))
+        );
+        assert_eq!(Address::parse("tcp:host=localhost,port=1"), None);
+    }
+
+    #[test]
+    fn should_call_methods_on_a_bus() {
+        let (client, server) = UnixStream::pair().unwrap();
+
+        let bus = thread::spawn(move || {
+            let mut reader = BufReader::new(server.try_clone().unwrap());
+            let mut server = server;
+
+            let mut auth = String::new();
+            reader.read_line(&mut auth).unwrap();
+            assert!(auth.starts_with("\0AUTH EXTERNAL "));
+            server.write_all(b"OK 1234deadbeef\r\n").unwrap();
+            let mut begin = String::new();
+            reader.read_line(&mut begin).unwrap();
+            assert_eq!(begin, "BEGIN\r\n");
+
+            let mut calls = Vec::new();
+            for _ in 0..2 {
+                let call = Message::read(&mut reader).unwrap();
+                server.write_all(&method_return(call.serial)).unwrap();
+                calls.push(call);
+            }
+            calls
+        });
+
+        let mut connection = Connection::authenticate(client).unwrap();
+        connection
+            .call(&MethodCall {
+                destination: "org.example.Test",
+                path: "/org/example/Test",
+                interface: "org.example.Test",
+                member: "Echo",
+                args: &[
+                    Arg::Str("hello"),
+                    Arg::Uint32(7),
+                    Arg::StrArray(&[]),
+                    Arg::ByteDict(&[("urgency", 2)]),
+                    Arg::Int32(-1),
+                ],
+            })
+            .unwrap();
+
+        let calls = bus.join().unwrap();
+        assert_eq!(calls[0].member.as_deref(), Some("Hello"));
+        assert_eq!(calls[0].signature, None);
+
+        let echo = &calls[1];
+        assert_eq!(echo.member.as_deref(), Some("Echo"));
+        assert_eq!(echo.signature.as_deref(), Some("suasa{sv}i"));
+        assert_eq!(echo.first_string().as_deref(), Some("hello"));
+        assert_eq!(read_u32(&echo.body, 12).unwrap(), 7);
+        // An empty string array, followed by the dict entry padded to 8 bytes
+        assert_eq!(read_u32(&echo.body, 16).unwrap(), 0);
+        assert_eq!(read_u32(&echo.body, 20).unwrap(), 16);
+        assert_eq!(&echo.body[24..36], b"\x07\0\0\0urgency\0");
+        assert_eq!(&echo.body[36..40], b"\x01y\0\x02");
+        assert_eq!(read_u32(&echo.body, 40).unwrap(), u32::MAX);
+    }
+}
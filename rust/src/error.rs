@@ -13,6 +13,16 @@ pub enum Error {
     Io {
         reason: String,
     },
+    Config {
+        reason: String,
+    },
+    // The running daemon could not be reached, or refused a request
+    Control {
+        reason: String,
+    },
+    DBus {
+        reason: String,
+    },
     Daemonize {
         error: daemonize::Error,
     },
@@ -21,6 +31,10 @@ pub enum Error {
         error: Utf8Error,
     },
     NoPermission,
+    // The kernel stopped monitoring a PSI trigger
+    PressureTriggerLost,
+    // `proc_root` belongs to another PID namespace than ours, so its PIDs can't be signalled
+    PidNamespaceMismatch,
 
     // mlockall-specific errors
     CouldNotLockMemory,
@@ -30,6 +44,8 @@ pub enum Error {
     UnknownMlockall,
     UnknownKill,
     UnknownGetpguid,
+    UnknownSigaction,
+    UnknownFork,
 
     #[cfg(feature = "glob-ignore")]
     GlobPattern {
@@ -39,7 +55,9 @@ pub enum Error {
     // Errors that are likely impossible to happen
     InvalidLinuxVersion,
     MalformedStatm,
+    MalformedStat,
     MalformedPressureFile,
+    MalformedMeminfo,
     ParseInt,
     ParseFloat,
     SysConfFailed,
@@ -48,6 +66,47 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// The name of the variant, used to label error metrics
+    pub fn variant(&self) -> &'static str {
+        match self {
+            Error::UnameFailed => "UnameFailed",
+            Error::ProcessNotFound(_) => "ProcessNotFound",
+            Error::InvalidPidSupplied => "InvalidPidSupplied",
+            Error::ProcessGroupNotFound => "ProcessGroupNotFound",
+            Error::InvalidSignal => "InvalidSignal",
+            Error::Io { .. } => "Io",
+            Error::Config { .. } => "Config",
+            Error::Control { .. } => "Control",
+            Error::DBus { .. } => "DBus",
+            Error::Daemonize { .. } => "Daemonize",
+            Error::Unicode { .. } => "Unicode",
+            Error::NoPermission => "NoPermission",
+            Error::PressureTriggerLost => "PressureTriggerLost",
+            Error::PidNamespaceMismatch => "PidNamespaceMismatch",
+            Error::CouldNotLockMemory => "CouldNotLockMemory",
+            Error::TooMuchMemoryToLock => "TooMuchMemoryToLock",
+            Error::InvalidFlags => "InvalidFlags",
+            Error::UnknownMlockall => "UnknownMlockall",
+            Error::UnknownKill => "UnknownKill",
+            Error::UnknownGetpguid => "UnknownGetpguid",
+            Error::UnknownSigaction => "UnknownSigaction",
+            Error::UnknownFork => "UnknownFork",
+            #[cfg(feature = "glob-ignore")]
+            Error::GlobPattern { .. } => "GlobPattern",
+            Error::InvalidLinuxVersion => "InvalidLinuxVersion",
+            Error::MalformedStatm => "MalformedStatm",
+            Error::MalformedStat => "MalformedStat",
+            Error::MalformedPressureFile => "MalformedPressureFile",
+            Error::MalformedMeminfo => "MalformedMeminfo",
+            Error::ParseInt => "ParseInt",
+            Error::ParseFloat => "ParseFloat",
+            Error::SysConfFailed => "SysConfFailed",
+            Error::SysInfoFailed => "SysInfoFailed",
+        }
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         Self::Io {
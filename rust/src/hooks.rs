@@ -0,0 +1,249 @@
+//! User-supplied commands run before and after a kill, through `sh -c`.
+//!
+//! Hooks are given the details of the victim through environment variables:
+//! `BUSTD_PID`, `BUSTD_COMM`, `BUSTD_OOM_SCORE`, `BUSTD_RSS_KIB` and `BUSTD_PSI`
+//! (the `some avg10` value that triggered the kill), along with `BUSTD_RESULT`
+//! for `post_kill` hooks.
+//!
+//! Each hook runs in a process group of its own, which is killed as a whole once the hook
+//! times out, so that nothing it started outlives it.
+
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use libc::SIGKILL;
+
+use crate::error::Result;
+use crate::kill::Victim;
+use crate::log::{self, Event};
+
+/// How often a `pre_kill` hook is checked on while we wait for it
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What became of the victim, as given to `post_kill` hooks through `BUSTD_RESULT`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KillResult {
+    /// The victim exited
+    Killed,
+    /// The victim was signalled but had not exited by the time we stopped waiting on it
+    Survived,
+    /// The victim could not be signalled
+    Failed,
+}
+
+impl KillResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Killed => "killed",
+            Self::Survived => "survived",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// The environment variables a hook about `victim` runs with
+fn environment(
+    victim: &Victim,
+    psi: f32,
+    result: Option<KillResult>,
+) -> Vec<(&'static str, String)> {
+    let mut vars = vec![
+        ("BUSTD_PID", victim.process.pid.to_string()),
+        ("BUSTD_COMM", victim.comm.clone()),
+        ("BUSTD_OOM_SCORE", victim.process.oom_score.to_string()),
+        ("BUSTD_RSS_KIB", victim.vm_rss_kib.to_string()),
+        ("BUSTD_PSI", format!("{:.2}", psi)),
+    ];
+    if let Some(result) = result {
+        vars.push(("BUSTD_RESULT", result.as_str().into()));
+    }
+
+    vars
+}
+
+/// A hook command that was started and has not been reaped yet
+pub struct Hook {
+    name: &'static str,
+    child: Child,
+    started: Instant,
+}
+
+impl Hook {
+    /// Starts `command` with the details of the victim in its environment.
+    /// `result` is only known, and given, after the kill.
+    pub fn spawn(
+        name: &'static str,
+        command: &str,
+        victim: &Victim,
+        psi: f32,
+        result: Option<KillResult>,
+    ) -> Result<Self> {
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(environment(victim, psi, result))
+            .stdin(Stdio::null())
+            .process_group(0)
+            .spawn()?;
+
+        Ok(Self {
+            name,
+            child,
+            started: Instant::now(),
+        })
+    }
+
+    /// Reaps the hook if it exited, or kills it if it ran for longer than `timeout`.
+    /// Returns true once the hook is gone.
+    pub fn reap(&mut self, timeout: Duration) -> bool {
+        match self.child.try_wait() {
+            Ok(Some(status)) => {
+                if !status.success() {
+                    log::emit(&Event::HookFailed {
+                        hook: self.name,
+                        status: &status,
+                    });
+                }
+                true
+            }
+            Ok(None) if self.started.elapsed() >= timeout => {
+                log::emit(&Event::HookTimedOut {
+                    hook: self.name,
+                    timeout,
+                });
+                // The group is named after the hook, whose PID can't be reused until it's reaped.
+                // Safety: kill() takes no pointers
+                unsafe { libc::kill(-(self.child.id() as i32), SIGKILL) };
+                let _ = self.child.wait();
+                true
+            }
+            Ok(None) => false,
+            Err(err) => {
+                log::emit(&Event::Error {
+                    message: "Could not wait on a hook",
+                    error: &err,
+                });
+                true
+            }
+        }
+    }
+
+    /// Blocks for up to `limit` until the hook is gone, killing it if it runs for longer than
+    /// `timeout`. Returns false if it's still running, in which case it must be reaped later on.
+    pub fn wait(&mut self, limit: Duration, timeout: Duration) -> bool {
+        let deadline = Instant::now() + limit;
+
+        loop {
+            if self.reap(timeout) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(WAIT_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use super::{environment, Hook, KillResult};
+    use crate::kill::Victim;
+    use crate::process::Process;
+
+    fn victim() -> Victim {
+        Victim {
+            process: Process {
+                pid: 1234,
+                oom_score: 800,
+            },
+            comm: "firefox".into(),
+            vm_rss_kib: 2048,
+            start_time: None,
+            pidfd: None,
+        }
+    }
+
+    #[test]
+    fn hooks_should_see_the_victim_in_their_environment() {
+        let vars = environment(&victim(), 42.5, Some(KillResult::Survived));
+
+        assert_eq!(
+            vars,
+            [
+                ("BUSTD_PID", "1234".into()),
+                ("BUSTD_COMM", "firefox".into()),
+                ("BUSTD_OOM_SCORE", "800".into()),
+                ("BUSTD_RSS_KIB", "2048".into()),
+                ("BUSTD_PSI", "42.50".into()),
+                ("BUSTD_RESULT", "survived".into()),
+            ]
+        );
+        assert_eq!(environment(&victim(), 0.0, None).len(), 5);
+    }
+
+    /// Lists the processes of the group that haven't exited
+    fn live_members(pgid: u32) -> Vec<u32> {
+        let proc_root = Path::new("/proc");
+
+        std::fs::read_dir(proc_root)
+            .unwrap()
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .map(|pid| Process { pid, oom_score: 0 })
+            .filter(|process| process.process_group(proc_root).ok() == Some(pgid as i32))
+            .filter(|process| !matches!(process.state(proc_root), Ok('Z' | 'X') | Err(_)))
+            .map(|process| process.pid)
+            .collect()
+    }
+
+    #[test]
+    fn hooks_should_be_killed_after_their_timeout() {
+        let mut hook = Hook::spawn("pre_kill", "sleep 10", &victim(), 0.0, None).unwrap();
+
+        let started = Instant::now();
+        assert!(!hook.wait(Duration::from_millis(50), Duration::from_secs(5)));
+        assert!(hook.wait(Duration::from_secs(5), Duration::from_millis(100)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn hooks_should_take_what_they_started_with_them() {
+        let mut hook =
+            Hook::spawn("post_kill", "sleep 10 & sleep 10", &victim(), 0.0, None).unwrap();
+        let pgid = hook.child.id();
+        // Wait for the shell to start its background job
+        while live_members(pgid).len() < 2 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_ne!(pgid as i32, crate::utils::get_process_group(0).unwrap());
+
+        assert!(hook.wait(Duration::from_secs(5), Duration::from_millis(50)));
+        // The group was sent SIGKILL, which may take a moment to be acted upon
+        let started = Instant::now();
+        while !live_members(pgid).is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn hooks_should_run_with_the_victim_in_their_environment() {
+        let mut hook = Hook::spawn(
+            "post_kill",
+            "test \"$BUSTD_COMM-$BUSTD_RESULT\" = firefox-killed",
+            &victim(),
+            0.0,
+            Some(KillResult::Killed),
+        )
+        .unwrap();
+
+        while !hook.reap(Duration::from_secs(5)) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(hook.child.wait().unwrap().success());
+    }
+}
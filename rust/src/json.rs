@@ -0,0 +1,467 @@
+//! Just enough JSON for bustd's logs and control socket: objects are written into
+//! a `fmt::Write` without allocating, and parsed into [`Value`]s.
+
+use std::fmt::{self, Write};
+
+/// Escapes everything written through it as the contents of a JSON string
+struct Escaper<'w, W: Write> {
+    out: &'w mut W,
+}
+
+impl<W: Write> Write for Escaper<'_, W> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for ch in string.chars() {
+            match ch {
+                '"' => self.out.write_str("\\\"")?,
+                '\\' => self.out.write_str("\\\\")?,
+                '\n' => self.out.write_str("\\n")?,
+                '\r' => self.out.write_str("\\r")?,
+                '\t' => self.out.write_str("\\t")?,
+                ch if ch.is_control() => write!(self.out, "\\u{:04x}", ch as u32)?,
+                ch => self.out.write_char(ch)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A JSON object being written, closed by [`JsonObject::finish`]
+pub struct JsonObject<'w, W: Write> {
+    out: &'w mut W,
+    first: bool,
+}
+
+impl<'w, W: Write> JsonObject<'w, W> {
+    pub fn new(out: &'w mut W) -> Result<Self, fmt::Error> {
+        out.write_char('{')?;
+        Ok(Self { out, first: true })
+    }
+
+    fn key(&mut self, key: &str) -> fmt::Result {
+        if !self.first {
+            self.out.write_char(',')?;
+        }
+        self.first = false;
+
+        write!(self.out, "\"{}\":", key)
+    }
+
+    /// Writes anything that implements `Display` as a string
+    pub fn string(&mut self, key: &str, value: impl fmt::Display) -> fmt::Result {
+        self.key(key)?;
+        self.out.write_char('"')?;
+        write!(Escaper { out: self.out }, "{}", value)?;
+        self.out.write_char('"')
+    }
+
+    /// Writes the `Debug` representation of a value as a string, as done for errors
+    pub fn debug(&mut self, key: &str, value: &dyn fmt::Debug) -> fmt::Result {
+        self.key(key)?;
+        self.out.write_char('"')?;
+        write!(Escaper { out: self.out }, "{:?}", value)?;
+        self.out.write_char('"')
+    }
+
+    /// Writes an integer
+    pub fn number(&mut self, key: &str, value: impl Into<i128>) -> fmt::Result {
+        self.key(key)?;
+        write!(self.out, "{}", value.into())
+    }
+
+    /// Writes a float, or null if it's not finite, since JSON has no NaN nor infinity
+    pub fn float(&mut self, key: &str, value: Option<f32>) -> fmt::Result {
+        self.key(key)?;
+        match value {
+            Some(value) if value.is_finite() => write!(self.out, "{}", value),
+            _ => self.out.write_str("null"),
+        }
+    }
+
+    pub fn boolean(&mut self, key: &str, value: bool) -> fmt::Result {
+        self.key(key)?;
+        write!(self.out, "{}", value)
+    }
+
+    /// Starts a nested object, which must be finished before writing anything else to this one
+    pub fn object(&mut self, key: &str) -> Result<JsonObject<'_, W>, fmt::Error> {
+        self.key(key)?;
+        JsonObject::new(self.out)
+    }
+
+    /// Starts a nested array, which must be finished before writing anything else to this one
+    pub fn array(&mut self, key: &str) -> Result<JsonArray<'_, W>, fmt::Error> {
+        self.key(key)?;
+        self.out.write_char('[')?;
+        Ok(JsonArray {
+            out: self.out,
+            first: true,
+        })
+    }
+
+    pub fn finish(self) -> fmt::Result {
+        self.out.write_char('}')
+    }
+}
+
+/// A JSON array of objects being written, closed by [`JsonArray::finish`]
+pub struct JsonArray<'w, W: Write> {
+    out: &'w mut W,
+    first: bool,
+}
+
+impl<W: Write> JsonArray<'_, W> {
+    /// Starts the next element, which must be finished before starting another one
+    pub fn object(&mut self) -> Result<JsonObject<'_, W>, fmt::Error> {
+        if !self.first {
+            self.out.write_char(',')?;
+        }
+        self.first = false;
+
+        JsonObject::new(self.out)
+    }
+
+    pub fn finish(self) -> fmt::Result {
+        self.out.write_char(']')
+    }
+}
+
+/// A parsed JSON value. Objects keep their keys in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The value of the given key, if this is an object that has it
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(boolean) => Some(*boolean),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a single JSON value, surrounded by nothing but whitespace
+pub fn parse(input: &str) -> Result<Value, String> {
+    let mut parser = Parser { input, pos: 0 };
+    let value = parser.value(0)?;
+
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(value),
+        Some(_) => Err(parser.error("trailing characters")),
+    }
+}
+
+/// Deeper values are rejected, so that parsing can't overflow the stack
+const MAX_DEPTH: usize = 32;
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &str) -> String {
+        format!("invalid JSON at offset {}: {}", self.pos, reason)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected `{}`", byte as char)));
+        }
+        self.pos += 1;
+
+        Ok(())
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> Result<Value, String> {
+        if !self.input[self.pos..].starts_with(literal) {
+            return Err(self.error("unexpected character"));
+        }
+        self.pos += literal.len();
+
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("too deeply nested"));
+        }
+
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, String> {
+        self.expect(b'{')?;
+        let mut entries = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            entries.push((key, self.value(depth + 1)?));
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(entries));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, String> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value(depth + 1)?);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while matches!(
+            self.peek(),
+            Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        ) {
+            self.pos += 1;
+        }
+
+        self.input[start..self.pos]
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, String> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| self.error("truncated escape"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid escape"))?;
+        self.pos += 4;
+
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut string = String::new();
+
+        loop {
+            let rest = &self.input[self.pos..];
+            // Copy everything up to the next quote or escape at once
+            let end = rest
+                .find(['"', '\\'])
+                .ok_or_else(|| self.error("unterminated string"))?;
+            string.push_str(&rest[..end]);
+            self.pos += end + 1;
+
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(string);
+            }
+
+            let escaped = self
+                .peek()
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match escaped {
+                b'"' => string.push('"'),
+                b'\\' => string.push('\\'),
+                b'/' => string.push('/'),
+                b'b' => string.push('\u{8}'),
+                b'f' => string.push('\u{c}'),
+                b'n' => string.push('\n'),
+                b'r' => string.push('\r'),
+                b't' => string.push('\t'),
+                b'u' => {
+                    let mut code = self.hex_escape()?;
+                    // Characters outside of the BMP are escaped as a surrogate pair
+                    if (0xD800..0xDC00).contains(&code) && self.input[self.pos..].starts_with("\\u")
+                    {
+                        self.pos += 2;
+                        let low = self.hex_escape()?;
+                        code =
+                            0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                    }
+                    string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                _ => return Err(self.error("invalid escape")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, JsonObject, Value};
+
+    #[test]
+    fn should_write_flat_objects() {
+        let mut out = String::new();
+        let mut object = JsonObject::new(&mut out).unwrap();
+        object.string("comm", "a \"quoted\"\tname\u{1}").unwrap();
+        object.number("pid", 1234_u32).unwrap();
+        object.number("signal", -9).unwrap();
+        object.float("psi", Some(12.5)).unwrap();
+        object.float("window", None).unwrap();
+        object.float("nan", Some(f32::NAN)).unwrap();
+        object.boolean("dry_run", true).unwrap();
+        object.debug("error", &Some("x")).unwrap();
+        object.finish().unwrap();
+
+        assert_eq!(
+            out,
+            r#"{"comm":"a \"quoted\"\tname\u0001","pid":1234,"signal":-9,"psi":12.5,"window":null,"nan":null,"dry_run":true,"error":"Some(\"x\")"}"#
+        );
+    }
+
+    #[test]
+    fn should_write_nested_values() {
+        let mut out = String::new();
+        let mut object = JsonObject::new(&mut out).unwrap();
+        object.boolean("ok", true).unwrap();
+        let mut memory = object.object("memory").unwrap();
+        memory.number("available_ram_mb", 512_u32).unwrap();
+        memory.finish().unwrap();
+        let mut kills = object.array("kills").unwrap();
+        for pid in [10_u32, 20] {
+            let mut kill = kills.object().unwrap();
+            kill.number("pid", pid).unwrap();
+            kill.finish().unwrap();
+        }
+        kills.finish().unwrap();
+        object.array("empty").unwrap().finish().unwrap();
+        object.finish().unwrap();
+
+        assert_eq!(
+            out,
+            r#"{"ok":true,"memory":{"available_ram_mb":512},"kills":[{"pid":10},{"pid":20}],"empty":[]}"#
+        );
+        assert!(parse(&out).is_ok());
+    }
+
+    #[test]
+    fn should_parse_values() {
+        let value = parse(
+            r#" {"command": "pause", "duration": "10m", "n": -1.5e1, "on": true,
+                 "list": [null, {}, []], "text": "a\"b\\c\n\u00e9\ud83d\ude00"} "#,
+        )
+        .unwrap();
+
+        assert_eq!(value.get("command").and_then(Value::as_str), Some("pause"));
+        assert_eq!(value.get("n"), Some(&Value::Number(-15.0)));
+        assert_eq!(value.get("on"), Some(&Value::Bool(true)));
+        assert_eq!(
+            value.get("list"),
+            Some(&Value::Array(vec![
+                Value::Null,
+                Value::Object(vec![]),
+                Value::Array(vec![])
+            ]))
+        );
+        assert_eq!(
+            value.get("text").and_then(Value::as_str),
+            Some("a\"b\\c\n\u{e9}\u{1f600}")
+        );
+        assert_eq!(value.get("missing"), None);
+
+        for invalid in [
+            "",
+            "{",
+            r#"{"a" 1}"#,
+            r#"{"a": 1,}"#,
+            "[1 2]",
+            r#""unterminated"#,
+            "nul",
+            "{} {}",
+            &"[".repeat(100),
+        ] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
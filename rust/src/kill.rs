@@ -1,151 +1,1170 @@
+use std::fmt;
 use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::time::Instant;
 
-use libc::kill;
-use libc::{EINVAL, EPERM, ESRCH, SIGKILL, SIGTERM};
+use libc::{kill, pollfd, POLLIN};
+use libc::{EINVAL, ENOSYS, EPERM, ESRCH, SIGKILL};
 
+use crate::config::{Config, Pattern, Rule, SignalLadder};
 use crate::errno::errno;
 use crate::error::{Error, Result};
-use crate::process::Process;
-use crate::{cli, utils};
+use crate::log::{self, Event};
+use crate::metrics;
+use crate::pidfd::PidFd;
+use crate::process::{read_comm, Facts, Process};
+use crate::utils;
 
-pub fn choose_victim(
-    proc_buf: &mut [u8],
-    buf: &mut [u8],
-    args: &cli::CommandLineArgs,
-) -> Result<Process> {
-    let now = Instant::now();
+/// How often victims are checked on when there's no pidfd to poll
+const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How many pidfds are polled at once while waiting for victims to exit, so that waiting
+/// doesn't allocate however large the victim's process group is
+const MAX_POLLED_EXITS: usize = 64;
+
+/// Set once a failure of process_mrelease was logged, since it likely fails the same way every time
+static RELEASE_FAILURE_LOGGED: AtomicBool = AtomicBool::new(false);
+
+/// The process chosen to be killed, along with what was read about it while choosing it
+#[derive(Debug)]
+pub struct Victim {
+    pub process: Process,
+    pub comm: String,
+    pub vm_rss_kib: i64,
+    /// When the victim started, in clock ticks since boot. Unset if it couldn't be read.
+    pub start_time: Option<u64>,
+    /// Set once the victim is held, unless the kernel lacks pidfd support
+    pub pidfd: Option<PidFd>,
+}
+
+impl Victim {
+    /// Holds on to the victim through a pidfd, so that it can't be mistaken for a process
+    /// that reuses its PID once it has exited. Kernels older than 5.3 lack pidfds, in which
+    /// case the victim is only checked to still be around, and is signalled through its PID.
+    pub fn hold(&mut self, proc_root: &Path) -> Result<()> {
+        let pidfd = match PidFd::open(self.process.pid) {
+            Ok(pidfd) => Some(pidfd),
+            Err(err) if err.raw_os_error() == Some(ENOSYS) => None,
+            Err(err) if err.raw_os_error() == Some(ESRCH) => {
+                return Err(Error::ProcessNotFound("pidfd_open"))
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        // pidfd_open resolves the PID in our own PID namespace, which needn't be the one of
+        // proc_root, e.g. a host's /proc mounted in a container
+        let pid_in_proc_root = pidfd.as_ref().and_then(|pidfd| pidfd.pid_in(proc_root));
+        if matches!(pid_in_proc_root, Some(pid) if pid >= 0 && pid != self.process.pid as i64) {
+            return Err(Error::PidNamespaceMismatch);
+        }
+
+        // The victim may have exited and had its PID reused since it was chosen
+        let start_time = self.process.start_time(proc_root).ok();
+        if start_time.is_none() || start_time != self.start_time {
+            return Err(Error::ProcessNotFound("pidfd_open"));
+        }
+        self.pidfd = pidfd;
+
+        Ok(())
+    }
+
+    /// Returns true once the victim has exited, even if it hasn't been reaped yet.
+    /// A process that reuses its PID doesn't count as the victim.
+    fn has_exited(&self, proc_root: &Path) -> bool {
+        if self.start_time.is_some() && self.process.start_time(proc_root).ok() != self.start_time {
+            return true;
+        }
+
+        // Unreadable once the process is gone
+        matches!(self.process.state(proc_root), Ok('Z' | 'X') | Err(_))
+    }
+
+    /// Waits for up to `timeout` for the victim to exit, returning true as soon as it does
+    pub fn wait_for_exit(&self, timeout: Duration, proc_root: &Path) -> bool {
+        wait_for_exits(&[self], timeout, proc_root).is_empty()
+    }
+
+    /// Reaps the memory of the victim, which must have just been sent SIGKILL, right away.
+    /// Does nothing without a pidfd. Failures, e.g. on kernels older than 5.15 or if the
+    /// victim has already released its memory, are counted, but only logged the first time.
+    fn release_memory(&self) {
+        let pidfd = match &self.pidfd {
+            Some(pidfd) => pidfd,
+            None => return,
+        };
+
+        let now = Instant::now();
+        match pidfd.release_memory() {
+            Ok(()) => {
+                let took = now.elapsed();
+                metrics::observe_memory_release(took);
+                log::emit(&Event::MemoryReleased {
+                    pid: self.process.pid,
+                    took,
+                });
+            }
+            Err(err) => {
+                metrics::record_memory_release_failure();
+                if !RELEASE_FAILURE_LOGGED.swap(true, Ordering::Relaxed) {
+                    log::emit(&Event::MemoryReleaseFailed {
+                        pid: self.process.pid,
+                        error: &err,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Sends the signal through the victim's pidfd, or to its PID if it has none
+    pub fn signal(&self, signal: i32) -> Result<()> {
+        let pid = self.process.pid as i32;
+        let pidfd = match &self.pidfd {
+            Some(pidfd) => pidfd,
+            None => return kill_process(pid, signal),
+        };
+
+        if let Err(err) = pidfd.send_signal(signal) {
+            let err = signal_error(err.raw_os_error().unwrap_or_default(), "pidfd_send_signal");
+            metrics::record_error(&err);
+            return Err(err);
+        }
+        log::emit(&Event::SignalSent { pid, signal });
+
+        Ok(())
+    }
+}
+
+/// Waits for up to `timeout` for every one of the victims to exit, returning those that haven't.
+/// Victims without a pidfd to poll, or beyond the first `MAX_POLLED_EXITS`, have their
+/// /proc/<PID>/stat checked every few milliseconds.
+fn wait_for_exits<'a>(
+    victims: &[&'a Victim],
+    timeout: Duration,
+    proc_root: &Path,
+) -> Vec<&'a Victim> {
+    let deadline = Instant::now() + timeout;
+    let mut survivors = victims.to_vec();
+
+    loop {
+        survivors.retain(|victim| !victim.has_exited(proc_root));
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if survivors.is_empty() || remaining.is_zero() {
+            return survivors;
+        }
+
+        // A pidfd becomes readable once its process has exited
+        let mut fds = [pollfd {
+            fd: -1,
+            events: POLLIN,
+            revents: 0,
+        }; MAX_POLLED_EXITS];
+        for (fd, victim) in fds.iter_mut().zip(&survivors) {
+            fd.fd = victim.pidfd.as_ref().map_or(-1, |pidfd| pidfd.as_raw_fd());
+        }
+        let polled = survivors.len().min(MAX_POLLED_EXITS);
+        let all_polled = polled == survivors.len();
+        let timeout = if all_polled && survivors.iter().all(|victim| victim.pidfd.is_some()) {
+            remaining
+        } else {
+            EXIT_CHECK_INTERVAL.min(remaining)
+        };
+
+        match utils::poll(&mut fds[..polled], timeout) {
+            Ok(_) => {
+                let mut exited = fds[..polled].iter().map(|fd| fd.revents & POLLIN != 0);
+                survivors.retain(|_| !exited.next().unwrap_or(false));
+            }
+            Err(_) => std::thread::sleep(EXIT_CHECK_INTERVAL.min(remaining)),
+        }
+    }
+}
+
+/// How an attempt at killing a process went
+#[derive(Debug, Clone, Copy)]
+pub struct KillOutcome {
+    /// Whether the process was seen exiting
+    pub exited: bool,
+    /// The last signal the process was sent
+    pub signal: i32,
+}
+
+/// How a victim is killed, which the rule it matches may change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Strategy {
+    /// The signals sent to the victim in turn, ending with SIGKILL
+    pub ladder: SignalLadder,
+    /// How long to wait for the victim to exit once sent SIGKILL
+    pub exit_timeout: Duration,
+    pub kill_pgroup: bool,
+}
+
+impl Strategy {
+    /// Consults the rules again, as they stand now, to decide how the victim is killed
+    pub fn for_victim(process: &Process, config: &Config) -> Self {
+        let facts = Facts::new(process, &config.proc_root);
+        let rule = config.matching_rule(&facts);
+        if let Some(rule) = rule {
+            log::emit(&Event::RuleApplied {
+                pid: process.pid,
+                line: rule.line,
+            });
+        }
+
+        Self {
+            ladder: rule
+                .and_then(|rule| rule.signal_ladder.clone())
+                .unwrap_or_else(|| config.signal_ladder.clone()),
+            exit_timeout: config.exit_timeout,
+            kill_pgroup: rule
+                .and_then(|rule| rule.kill_pgroup)
+                .unwrap_or(config.kill_pgroup),
+        }
+    }
+}
+
+/// What victims are ranked by, the greatest being chosen first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Rank {
+    /// Processes over the RSS ceiling set by their rule come before any other
+    pub over_rss_max: bool,
+    /// The process' oom_score, moved by the prefer and avoid patterns and by its rule
+    pub score: i16,
+    /// Ties go to the largest VmRSS
+    pub vm_rss_kib: i64,
+}
+
+impl Rank {
+    fn new(oom_score: i16, bias: i16, vm_rss_kib: i64, rule: Option<&Rule>) -> Self {
+        let rss_max = rule.and_then(|rule| rule.rss_max);
+
+        Self {
+            over_rss_max: rss_max.is_some_and(|max| vm_rss_kib as u64 * 1024 > max.bytes()),
+            score: oom_score.saturating_add(bias),
+            vm_rss_kib,
+        }
+    }
+}
+
+/// Why a process is never chosen as a victim
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exclusion {
+    /// Its name matches one of the `unkillables` patterns
+    #[cfg(feature = "glob-ignore")]
+    Unkillable,
+    /// The first rule it matches says never to kill it
+    Rule,
+    /// It has no resident memory, which is the case of kernel threads
+    KernelThread,
+    /// Its oom_score_adj is -1000, which the kernel's OOM killer respects as well
+    OomDisabled,
+}
+
+impl Exclusion {
+    /// Every rule, in the order they're checked
+    pub const ALL: &'static [Exclusion] = &[
+        #[cfg(feature = "glob-ignore")]
+        Exclusion::Unkillable,
+        Exclusion::Rule,
+        Exclusion::KernelThread,
+        Exclusion::OomDisabled,
+    ];
+
+    /// Returns true if this keeps the process from being chosen,
+    /// `rule` being the first rule the process matches
+    #[cfg_attr(not(feature = "glob-ignore"), allow(unused_variables))]
+    pub fn applies(
+        self,
+        process: &Process,
+        vm_rss_kib: i64,
+        oom_score_adj: i16,
+        rule: Option<&Rule>,
+        buf: &mut [u8],
+        config: &Config,
+    ) -> bool {
+        match self {
+            #[cfg(feature = "glob-ignore")]
+            Exclusion::Unkillable => matches!(
+                process.is_unkillable(&config.proc_root, buf, &config.unkillables),
+                Ok(true)
+            ),
+            Exclusion::Rule => rule.is_some_and(|rule| rule.never_kill),
+            Exclusion::KernelThread => vm_rss_kib == 0,
+            // Follow the behaviour of the standard OOM killer: don't kill processes with oom_score_adj equals to -1000
+            Exclusion::OomDisabled => oom_score_adj == -1000,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            #[cfg(feature = "glob-ignore")]
+            Exclusion::Unkillable => "unkillable",
+            Exclusion::Rule => "rule",
+            Exclusion::KernelThread => "kernel_thread",
+            Exclusion::OomDisabled => "oom_score_adj",
+        }
+    }
+}
+
+impl fmt::Display for Exclusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            #[cfg(feature = "glob-ignore")]
+            Exclusion::Unkillable => "matches an unkillables pattern",
+            Exclusion::Rule => "a rule says never to kill it",
+            Exclusion::KernelThread => "kernel thread",
+            Exclusion::OomDisabled => "oom_score_adj is -1000",
+        })
+    }
+}
 
-    // `args` is currently only used when checking for unkillable patterns
-    #[cfg(not(feature = "glob-ignore"))]
-    let _ = args;
+/// A process along with everything that decides whether it's chosen
+#[derive(Debug)]
+pub struct Candidate {
+    pub process: Process,
+    pub comm: String,
+    pub uid: u32,
+    pub oom_score_adj: i16,
+    pub vm_rss_kib: i64,
+    /// Unset if it couldn't be read
+    pub vm_swap_kib: Option<i64>,
+    /// What the prefer and avoid patterns and its rule add to its oom_score
+    pub bias: i16,
+    /// The first rule it matches
+    pub rule: Option<Rule>,
+    pub exclusion: Option<Exclusion>,
+}
 
-    let mut processes = fs::read_dir("/proc/")?
+impl Candidate {
+    pub fn rank(&self) -> Rank {
+        Rank::new(
+            self.process.oom_score,
+            self.bias,
+            self.vm_rss_kib,
+            self.rule.as_ref(),
+        )
+    }
+
+    /// The oom_score, moved by the prefer and avoid patterns and by its rule
+    pub fn score(&self) -> i16 {
+        self.rank().score
+    }
+}
+
+/// Lists the processes that may be killed, which leaves init out
+fn processes<'a>(
+    proc_root: &'a Path,
+    proc_buf: &'a mut [u8],
+) -> Result<impl Iterator<Item = Process> + 'a> {
+    Ok(fs::read_dir(proc_root)?
         .filter_map(|e| e.ok())
         .filter_map(|entry| entry.file_name().to_str()?.trim().parse::<u32>().ok())
         .filter(|pid| *pid > 1)
-        .filter_map(|pid| Process::from_pid(pid, proc_buf).ok());
+        .filter_map(move |pid| Process::from_pid(pid, proc_root, proc_buf).ok()))
+}
 
-    let first_process = processes.next();
-    if first_process.is_none() {
-        // Likely an impossible scenario but we found no process to kill!
-        return Err(Error::ProcessNotFound("choose_victim"));
+/// Returns how much the prefer and avoid patterns, along with the process' rule, move its oom_score.
+/// A pattern applies if it matches the process' name, executable path or command line.
+fn bias(facts: &Facts, rule: Option<&Rule>, config: &Config) -> i16 {
+    let rule_bias = rule.map_or(0, |rule| rule.bias);
+    if config.prefer.is_none() && config.avoid.is_none() {
+        return rule_bias;
     }
 
-    let mut victim = first_process.unwrap();
-    // TODO: find another victim if victim.vm_rss_kib() fails here
-    let mut victim_vm_rss_kib = victim.vm_rss_kib(buf)?;
+    // Kernel threads have no executable, and processes may exit while being read
+    let texts = [facts.comm(), facts.exe(), facts.cmdline()];
+    let matches = |pattern: &Option<Pattern>| {
+        pattern
+            .as_ref()
+            .is_some_and(|pattern| texts.iter().flatten().any(|text| pattern.is_match(text)))
+    };
 
-    for process in processes {
-        if victim.oom_score > process.oom_score {
-            // Our current victim is less innocent than the process being analysed
-            continue;
-        }
+    let mut bias = rule_bias;
+    if matches(&config.prefer) {
+        bias += config.prefer_bias as i16;
+    }
+    if matches(&config.avoid) {
+        bias -= config.avoid_bias as i16;
+    }
+    bias
+}
 
-        #[cfg(feature = "glob-ignore")]
-        {
-            if let Some(patterns) = &args.ignored {
-                if matches!(process.is_unkillable(buf, patterns), Ok(true)) {
-                    continue;
-                }
+/// The highest rank a process with the given oom_score could have, whatever else is read about it
+fn best_possible_rank(oom_score: i16, config: &Config) -> Rank {
+    let prefer_bias = match config.prefer {
+        Some(_) => config.prefer_bias as i16,
+        None => 0,
+    };
+    let rule_bias = config.rules.iter().map(|rule| rule.bias).max().unwrap_or(0);
+
+    Rank {
+        over_rss_max: config.rules.iter().any(|rule| rule.rss_max.is_some()),
+        score: oom_score.saturating_add(prefer_bias + rule_bias.max(0)),
+        vm_rss_kib: i64::MAX,
+    }
+}
+
+/// Returns the first check that keeps a process from ever being chosen, regardless of the others
+fn exclusion(
+    process: &Process,
+    vm_rss_kib: i64,
+    oom_score_adj: i16,
+    rule: Option<&Rule>,
+    buf: &mut [u8],
+    config: &Config,
+) -> Option<Exclusion> {
+    Exclusion::ALL
+        .iter()
+        .copied()
+        .find(|check| check.applies(process, vm_rss_kib, oom_score_adj, rule, buf, config))
+}
+
+pub fn choose_victim(proc_buf: &mut [u8], buf: &mut [u8], config: &Config) -> Result<Victim> {
+    let now = Instant::now();
+
+    let proc_root = config.proc_root.as_path();
+
+    // The current victim along with its rank
+    let mut victim: Option<(Process, Rank)> = None;
+
+    for process in processes(proc_root, proc_buf)? {
+        if let Some((_, victim_rank)) = &victim {
+            if *victim_rank > best_possible_rank(process.oom_score, config) {
+                // Our current victim is less innocent than the process being analysed
+                continue;
             }
         }
 
-        let cur_vm_rss_kib = process.vm_rss_kib(buf)?;
-        if cur_vm_rss_kib == 0 {
-            // Current process is a kernel thread
-            continue;
-        }
+        let cur_vm_rss_kib = match process.vm_rss_kib(proc_root, buf) {
+            Ok(vm_rss_kib) => vm_rss_kib,
+            // The process has likely exited since we've listed it
+            Err(_) => continue,
+        };
+        let (rule, cur_rank) = {
+            let facts = Facts::new(&process, proc_root);
+            let rule = config.matching_rule(&facts);
+            let bias = bias(&facts, rule, config);
 
-        if process.oom_score == victim.oom_score && cur_vm_rss_kib <= victim_vm_rss_kib {
-            continue;
+            (
+                rule,
+                Rank::new(process.oom_score, bias, cur_vm_rss_kib, rule),
+            )
+        };
+        if let Some((_, victim_rank)) = &victim {
+            if cur_rank <= *victim_rank {
+                continue;
+            }
         }
 
-        let cur_oom_score_adj = match process.oom_score_adj(buf) {
+        let cur_oom_score_adj = match process.oom_score_adj(proc_root, buf) {
             Ok(oom_score_adj) => oom_score_adj,
             // TODO: warn that this error happened
             Err(_) => continue,
         };
-
-        if cur_oom_score_adj == -1000 {
-            // Follow the behaviour of the standard OOM killer: don't kill processes with oom_score_adj equals to -1000
+        let excluded = exclusion(
+            &process,
+            cur_vm_rss_kib,
+            cur_oom_score_adj,
+            rule,
+            buf,
+            config,
+        );
+        if excluded.is_some() {
             continue;
         }
 
-        // eprintln!("[DBG] New victim with PID={}!", process.pid);
-        victim = process;
-        victim_vm_rss_kib = cur_vm_rss_kib;
+        log::emit(&Event::Candidate {
+            pid: process.pid,
+            oom_score: process.oom_score,
+            rss_kib: cur_vm_rss_kib,
+        });
+        victim = Some((process, cur_rank));
     }
 
-    println!("[LOG] Found victim in {} secs.", now.elapsed().as_secs());
-    println!(
-        "[LOG] Victim => pid: {}, comm: {}, oom_score: {}",
-        victim.pid,
-        victim.comm(buf).unwrap_or("unknown").trim(),
-        victim.oom_score
-    );
+    // Likely an impossible scenario but we found no process to kill!
+    let (process, rank) = victim.ok_or(Error::ProcessNotFound("choose_victim"))?;
+    let comm = process
+        .comm(proc_root, buf)
+        .unwrap_or("unknown")
+        .trim()
+        .into();
+    let victim = Victim {
+        start_time: process.start_time(proc_root).ok(),
+        pidfd: None,
+        process,
+        comm,
+        vm_rss_kib: rank.vm_rss_kib,
+    };
+
+    let search_time = now.elapsed();
+    metrics::observe_victim_selection(search_time);
+    log::emit(&Event::VictimChosen {
+        pid: victim.process.pid,
+        comm: &victim.comm,
+        oom_score: victim.process.oom_score,
+        rss_kib: victim.vm_rss_kib,
+        search_time,
+    });
 
     Ok(victim)
 }
 
+/// Reads every process that may be killed, ordered as `choose_victim` would pick them,
+/// excluded processes being kept where they'd rank otherwise
+pub fn rank_candidates(
+    proc_buf: &mut [u8],
+    buf: &mut [u8],
+    config: &Config,
+) -> Result<Vec<Candidate>> {
+    let proc_root = config.proc_root.as_path();
+    let mut candidates = Vec::new();
+
+    for process in processes(proc_root, proc_buf)? {
+        // Skip processes that exited since they were listed
+        let (vm_rss_kib, oom_score_adj, uid) = match (
+            process.vm_rss_kib(proc_root, buf),
+            process.oom_score_adj(proc_root, buf),
+            process.uid(proc_root),
+        ) {
+            (Ok(vm_rss_kib), Ok(oom_score_adj), Ok(uid)) => (vm_rss_kib, oom_score_adj, uid),
+            _ => continue,
+        };
+        let (comm, rule, bias) = {
+            let facts = Facts::new(&process, proc_root);
+            let rule = config.matching_rule(&facts);
+
+            (
+                facts.comm().unwrap_or("unknown").to_owned(),
+                rule,
+                bias(&facts, rule, config),
+            )
+        };
+
+        candidates.push(Candidate {
+            exclusion: exclusion(&process, vm_rss_kib, oom_score_adj, rule, buf, config),
+            vm_swap_kib: process.vm_swap_kib(proc_root).ok(),
+            rule: rule.cloned(),
+            comm,
+            bias,
+            uid,
+            oom_score_adj,
+            vm_rss_kib,
+            process,
+        });
+    }
+
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.rank()));
+    Ok(candidates)
+}
+
+/// Maps the errno left by kill() or pidfd_send_signal() to our errors
+fn signal_error(errno: i32, context: &'static str) -> Error {
+    match errno {
+        // An invalid signal was specified
+        EINVAL => Error::InvalidSignal,
+        // Calling process doesn't have permission to send signals to any
+        // of the target processes
+        EPERM => Error::NoPermission,
+        // The target process or process group does not exist.
+        ESRCH => Error::ProcessNotFound(context),
+        _ => Error::UnknownKill,
+    }
+}
+
 pub fn kill_process(pid: i32, signal: i32) -> Result<()> {
     let res = unsafe { kill(pid, signal) };
 
     if res == -1 {
-        return Err(match errno() {
-            // An invalid signal was specified
-            EINVAL => Error::InvalidSignal,
-            // Calling process doesn't have permission to send signals to any
-            // of the target processes
-            EPERM => Error::NoPermission,
-            // The target process or process group does not exist.
-            ESRCH => Error::ProcessNotFound("kill"),
-            _ => Error::UnknownKill,
-        });
+        let err = signal_error(errno(), "kill");
+        metrics::record_error(&err);
+        return Err(err);
     }
+    log::emit(&Event::SignalSent { pid, signal });
 
     Ok(())
 }
 
-pub fn kill_process_group(process: Process) -> Result<()> {
-    let pid = process.pid;
+/// Lists the processes of the group, each held like a victim, leaving out those that exit
+/// while being read
+fn group_members(pgid: i32, proc_root: &Path) -> Result<Vec<Victim>> {
+    let members = fs::read_dir(proc_root)?
+        .filter_map(|e| e.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .map(|pid| Process { pid, oom_score: 0 })
+        .filter(|process| process.process_group(proc_root).ok() == Some(pgid))
+        .filter_map(|process| {
+            let mut member = Victim {
+                comm: read_comm(process.pid, proc_root).unwrap_or_else(|| "unknown".into()),
+                vm_rss_kib: 0,
+                start_time: Some(process.start_time(proc_root).ok()?),
+                pidfd: None,
+                process,
+            };
+            member.hold(proc_root).ok()?;
+            Some(member)
+        })
+        .collect();
 
-    let pgid = utils::get_process_group(pid as i32)?;
+    Ok(members)
+}
 
-    // TODO: kill and wait
-    let _ = kill_process(-pgid, SIGTERM);
+/// Logs the members of the victim's group that are still around after being sent the signal
+fn report_survivors(pgid: i32, signal: i32, survivors: &[&Victim]) {
+    if survivors.is_empty() {
+        return;
+    }
 
-    Ok(())
+    let survivors: Vec<_> = survivors
+        .iter()
+        .map(|member| (member.process.pid, member.comm.as_str()))
+        .collect();
+    log::emit(&Event::GroupSurvived {
+        pgid,
+        signal,
+        survivors: &survivors,
+    });
+}
+
+/// Sends the signal to the victim's group, treating a group that's already gone as signalled.
+/// The group is only signalled as a whole while the held victim is alive, which keeps its PGID
+/// from being reused: once it has exited, the remaining members are signalled one by one
+/// through their own pidfds instead.
+fn signal_group(
+    pgid: i32,
+    signal: i32,
+    victim: &Victim,
+    members: &[&Victim],
+    proc_root: &Path,
+) -> Result<()> {
+    if victim.has_exited(proc_root) {
+        for member in members
+            .iter()
+            .filter(|member| !member.has_exited(proc_root))
+        {
+            let _ = member.signal(signal);
+        }
+        return Ok(());
+    }
+
+    match kill_process(-pgid, signal) {
+        Err(Error::ProcessNotFound(_)) => Ok(()),
+        sent => sent,
+    }
+}
+
+/// Waits for up to `wait` for the members of the group to exit after being sent the signal,
+/// reporting and returning those that haven't
+fn wait_for_group<'a>(
+    pgid: i32,
+    signal: i32,
+    members: &[&'a Victim],
+    wait: Duration,
+    proc_root: &Path,
+) -> Vec<&'a Victim> {
+    let survivors = wait_for_exits(members, wait, proc_root);
+    report_survivors(pgid, signal, &survivors);
+    survivors
+}
+
+/// Kills the victim's whole process group, climbing the signal ladder as `kill_and_wait` does,
+/// and waits for every process that was in the group when it was first signalled to exit
+pub fn kill_process_group(
+    victim: &Victim,
+    strategy: &Strategy,
+    proc_root: &Path,
+) -> Result<KillOutcome> {
+    let pid = victim.process.pid;
+    let now = Instant::now();
+    let rungs = strategy.ladder.rungs();
+    let gone = KillOutcome {
+        exited: true,
+        signal: rungs.first().map_or(SIGKILL, |&(signal, _)| signal),
+    };
+
+    // The victim may exit, and its PID be reused, before its group is looked up
+    let pgid = match utils::get_process_group(pid as i32) {
+        Ok(_) if victim.has_exited(proc_root) => return Ok(gone),
+        Ok(pgid) => pgid,
+        Err(Error::ProcessGroupNotFound) => return Ok(gone),
+        Err(err) => return Err(err),
+    };
+    let others = group_members(pgid, proc_root)?;
+    let mut survivors: Vec<&Victim> = std::iter::once(victim)
+        .chain(others.iter().filter(|member| member.process.pid != pid))
+        .collect();
+
+    for (step, &(signal, wait)) in rungs.iter().enumerate() {
+        let sent = signal_group(pgid, signal, victim, &survivors, proc_root);
+        // Later signals fail if the group is gone by then
+        if step == 0 {
+            sent?;
+        }
+        survivors = wait_for_group(pgid, signal, &survivors, wait, proc_root);
+        if survivors.is_empty() {
+            return Ok(KillOutcome {
+                exited: true,
+                signal,
+            });
+        }
+    }
+
+    let sent = signal_group(pgid, SIGKILL, victim, &survivors, proc_root);
+    if rungs.is_empty() {
+        sent?;
+    } else {
+        metrics::record_escalation();
+        log::emit(&Event::Escalated {
+            pid,
+            after: now.elapsed(),
+        });
+    }
+    for member in &survivors {
+        member.release_memory();
+    }
+    let survivors = wait_for_group(pgid, SIGKILL, &survivors, strategy.exit_timeout, proc_root);
+
+    Ok(KillOutcome {
+        exited: survivors.is_empty(),
+        signal: SIGKILL,
+    })
 }
 
 /// Tries to kill a process and wait for it to exit
-/// Will first send the victim a SIGTERM and escalate to SIGKILL if necessary
-/// Returns Ok(true) if the victim was successfully terminated
-pub fn kill_and_wait(process: Process) -> Result<bool> {
-    let pid = process.pid;
+/// Will send the victim each signal of the strategy's ladder in turn, SIGTERM by default,
+/// until it exits or is sent SIGKILL
+pub fn kill_and_wait(
+    victim: &Victim,
+    strategy: &Strategy,
+    proc_root: &Path,
+) -> Result<KillOutcome> {
+    let pid = victim.process.pid;
     let now = Instant::now();
+    let rungs = strategy.ladder.rungs();
+
+    for &(signal, wait) in rungs {
+        let _ = victim.signal(signal);
+        if victim.wait_for_exit(wait, proc_root) {
+            log::emit(&Event::Exited {
+                pid,
+                after: now.elapsed(),
+            });
+            return Ok(KillOutcome {
+                exited: true,
+                signal,
+            });
+        }
+    }
+
+    let _ = victim.signal(SIGKILL);
+    if !rungs.is_empty() {
+        metrics::record_escalation();
+        log::emit(&Event::Escalated {
+            pid,
+            after: now.elapsed(),
+        });
+    }
+    victim.release_memory();
+
+    let exited = victim.wait_for_exit(strategy.exit_timeout, proc_root);
+    if exited {
+        log::emit(&Event::Exited {
+            pid,
+            after: now.elapsed(),
+        });
+    }
+
+    Ok(KillOutcome {
+        exited,
+        signal: SIGKILL,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::path::Path;
+    use std::process::{Child, Command};
+    use std::time::Duration;
+
+    use libc::{SIGINT, SIGKILL, SIGTERM};
+
+    use super::{Exclusion, Strategy, Victim};
+    use crate::config::{Config, Rule, SignalLadder};
+    use crate::error::Error;
+    use crate::pidfd::PidFd;
+    use crate::process::Process;
+    use crate::testing::ProcFixture;
+
+    #[test]
+    fn should_choose_the_victim_from_the_proc_root() {
+        let fixture = ProcFixture::new("choose-victim");
+        // init is never considered
+        fixture.add_process(1, "init", 1000, 0, 4096);
+        // Kernel threads have no resident memory
+        fixture.add_process(200, "kworker", 900, 0, 0);
+        // Protected from the OOM killer
+        fixture.add_process(300, "sshd", 800, -1000, 4096);
+        fixture.add_process(400, "firefox", 600, 0, 8192);
+        // Same score as firefox, but less memory
+        fixture.add_process(500, "thunderbird", 600, 0, 4096);
+        fixture.add_process(600, "bash", 100, 0, 1024);
+
+        let (mut proc_buf, mut buf) = ([0_u8; 128], [0_u8; 256]);
+        let victim = super::choose_victim(&mut proc_buf, &mut buf, &fixture.config()).unwrap();
+
+        assert_eq!(victim.process.pid, 400);
+        assert_eq!(victim.process.oom_score, 600);
+        assert_eq!(victim.comm, "firefox");
+        assert_eq!(victim.vm_rss_kib, 8192);
+    }
 
-    let _ = kill_process(pid as i32, SIGTERM);
+    #[test]
+    fn should_rank_every_candidate() {
+        let fixture = ProcFixture::new("rank");
+        fixture.add_process(1, "init", 1000, 0, 4096);
+        fixture.add_process(200, "kworker", 0, 0, 0);
+        fixture.add_process(300, "sshd", 800, -1000, 4096);
+        fixture.add_process(400, "thunderbird", 600, 0, 4096);
+        fixture.add_process(500, "firefox", 600, 0, 8192);
 
-    let half_a_sec = Duration::from_secs_f32(0.5);
-    let mut sigkill_sent = false;
+        let (mut proc_buf, mut buf) = ([0_u8; 128], [0_u8; 256]);
+        let candidates =
+            super::rank_candidates(&mut proc_buf, &mut buf, &fixture.config()).unwrap();
+        let ranking: Vec<_> = candidates
+            .iter()
+            .map(|candidate| (candidate.process.pid, candidate.exclusion))
+            .collect();
 
-    for _ in 0..20 {
-        std::thread::sleep(half_a_sec);
-        if !process.is_alive() {
-            println!("[LOG] Process with PID {} has exited.\n", pid);
-            return Ok(true);
+        assert_eq!(
+            ranking,
+            [
+                (300, Some(Exclusion::OomDisabled)),
+                (500, None),
+                (400, None),
+                (200, Some(Exclusion::KernelThread)),
+            ]
+        );
+        // The fixture has no status files
+        assert_eq!(candidates[1].vm_swap_kib, None);
+        assert_eq!(candidates[1].comm, "firefox");
+    }
+
+    #[test]
+    fn should_bias_patterns_into_the_ranking() {
+        let fixture = ProcFixture::new("bias");
+        fixture.add_process(400, "firefox", 600, 0, 8192);
+        fixture.add_process(500, "thunderbird", 500, 0, 4096);
+        fixture.add_process(600, "bash", 100, 0, 1024);
+        let config = Config {
+            prefer: Some("^thunder".parse().unwrap()),
+            avoid: Some("^(bash|firefox)$".parse().unwrap()),
+            ..fixture.config()
+        };
+
+        let (mut proc_buf, mut buf) = ([0_u8; 128], [0_u8; 256]);
+        let victim = super::choose_victim(&mut proc_buf, &mut buf, &config).unwrap();
+        assert_eq!(victim.process.pid, 500);
+
+        let candidates = super::rank_candidates(&mut proc_buf, &mut buf, &config).unwrap();
+        let ranking: Vec<_> = candidates
+            .iter()
+            .map(|candidate| (candidate.process.pid, candidate.score()))
+            .collect();
+        assert_eq!(ranking, [(500, 800), (400, 300), (600, -200)]);
+    }
+
+    #[test]
+    fn should_follow_rules() {
+        let fixture = ProcFixture::new("rules");
+        fixture.add_process(300, "sshd", 800, 0, 4096);
+        fixture.add_process(400, "firefox", 600, 0, 8192);
+        fixture.add_process(500, "thunderbird", 500, 0, 4096);
+        fixture.add_process(600, "postgres", 100, 0, 1 << 20);
+        let config = Config {
+            rules: Rule::from_toml(
+                r#"
+                [[rule]]
+                cmdline = "sshd"
+                never_kill = true
+
+                [[rule]]
+                cmdline = "thunderbird"
+                bias = 200
+                signal = "SIGINT"
+                grace_period = "3s"
+                "#,
+            )
+            .unwrap(),
+            ..fixture.config()
+        };
+
+        let (mut proc_buf, mut buf) = ([0_u8; 128], [0_u8; 256]);
+        let victim = super::choose_victim(&mut proc_buf, &mut buf, &config).unwrap();
+        assert_eq!(victim.process.pid, 500);
+
+        let strategy = Strategy::for_victim(&victim.process, &config);
+        assert_eq!(strategy.ladder.to_string(), "SIGINT 3s SIGKILL");
+        let firefox = Process {
+            pid: 400,
+            oom_score: 600,
+        };
+        assert_eq!(
+            Strategy::for_victim(&firefox, &config).ladder,
+            SignalLadder::default()
+        );
+
+        // Going over its RSS ceiling puts postgres first, despite its low oom_score
+        let mut config = config;
+        config.rules.extend(
+            Rule::from_toml("[[rule]]\ncmdline = \"postgres\"\nrss_max = \"512M\"").unwrap(),
+        );
+        let candidates = super::rank_candidates(&mut proc_buf, &mut buf, &config).unwrap();
+        let ranking: Vec<_> = candidates
+            .iter()
+            .map(|candidate| (candidate.process.pid, candidate.exclusion))
+            .collect();
+        assert_eq!(
+            ranking,
+            [
+                (600, None),
+                (300, Some(Exclusion::Rule)),
+                (500, None),
+                (400, None)
+            ]
+        );
+        assert_eq!(
+            super::choose_victim(&mut proc_buf, &mut buf, &config)
+                .unwrap()
+                .process
+                .pid,
+            600
+        );
+    }
+
+    #[test]
+    fn should_hold_victims_started_when_chosen() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let process = Process {
+            pid: child.id(),
+            oom_score: 0,
+        };
+        let proc_root = Path::new("/proc");
+        let start_time = process.start_time(proc_root).unwrap();
+        let mut victim = Victim {
+            process,
+            comm: "sleep".into(),
+            vm_rss_kib: 0,
+            start_time: Some(start_time),
+            pidfd: None,
+        };
+
+        // As if the PID had been reused since the victim was chosen
+        victim.start_time = Some(start_time + 1);
+        assert!(victim.hold(proc_root).is_err());
+        assert!(victim.pidfd.is_none());
+
+        victim.start_time = Some(start_time);
+        victim.hold(proc_root).unwrap();
+        victim.signal(SIGKILL).unwrap();
+        assert_eq!(child.wait().unwrap().signal(), Some(SIGKILL));
+    }
+
+    /// Spawns the shell command in a process group of its own, which must end up running
+    /// `sleep`, held as a victim if `hold` is set
+    fn spawn_victim(command: &str, hold: bool) -> (Child, Victim) {
+        let child = Command::new("sh")
+            .args(["-c", command])
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let comm = Path::new("/proc").join(child.id().to_string()).join("comm");
+        // Signals sent before then would reach the shell, rather than what it runs
+        while fs::read_to_string(&comm).unwrap().trim() != "sleep" {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let process = Process {
+            pid: child.id(),
+            oom_score: 0,
+        };
+        let mut victim = Victim {
+            start_time: process.start_time(Path::new("/proc")).ok(),
+            process,
+            comm: "sleep".into(),
+            vm_rss_kib: 0,
+            pidfd: None,
+        };
+        if hold {
+            victim.hold(Path::new("/proc")).unwrap();
+        }
+
+        (child, victim)
+    }
+
+    #[test]
+    fn should_wait_for_victims_to_exit() {
+        let strategy = Strategy {
+            ladder: "SIGTERM 5s SIGKILL".parse().unwrap(),
+            exit_timeout: Duration::from_secs(5),
+            kill_pgroup: false,
+        };
+
+        // Both through the pidfd and by reading /proc, zombies included
+        for hold in [true, false] {
+            let (mut child, victim) = spawn_victim("exec sleep 10", hold);
+            let outcome = super::kill_and_wait(&victim, &strategy, Path::new("/proc")).unwrap();
+
+            // Had the exit been missed, the victim would have been sent SIGKILL after 5s
+            assert!(outcome.exited);
+            assert_eq!(outcome.signal, SIGTERM);
+            assert_eq!(child.wait().unwrap().signal(), Some(SIGTERM));
         }
-        if !sigkill_sent {
-            let _ = kill_process(pid as i32, SIGKILL);
-            sigkill_sent = true;
-            println!(
-                "[LOG] Escalated to SIGKILL after {} nanosecs",
-                now.elapsed().as_nanos()
+
+        // Climbing the ladder until a signal works
+        let strategy = Strategy {
+            ladder: "SIGTERM 100ms SIGINT 5s SIGKILL".parse().unwrap(),
+            ..strategy
+        };
+        let (mut child, victim) = spawn_victim("trap '' TERM; exec sleep 10", true);
+        let outcome = super::kill_and_wait(&victim, &strategy, Path::new("/proc")).unwrap();
+
+        assert!(outcome.exited);
+        assert_eq!(outcome.signal, SIGINT);
+        assert_eq!(child.wait().unwrap().signal(), Some(SIGINT));
+
+        let strategy = Strategy {
+            ladder: "SIGTERM 100ms SIGKILL".parse().unwrap(),
+            ..strategy
+        };
+        let (mut child, victim) = spawn_victim("trap '' TERM; exec sleep 10", true);
+        let outcome = super::kill_and_wait(&victim, &strategy, Path::new("/proc")).unwrap();
+
+        assert!(outcome.exited);
+        assert_eq!(outcome.signal, SIGKILL);
+        assert_eq!(child.wait().unwrap().signal(), Some(SIGKILL));
+    }
+
+    #[test]
+    fn should_kill_whole_process_groups() {
+        let strategy = Strategy {
+            ladder: "SIGTERM 200ms SIGKILL".parse().unwrap(),
+            exit_timeout: Duration::from_secs(5),
+            kill_pgroup: true,
+        };
+        // The background sleep exits on SIGTERM, leaving the group leader to be sent SIGKILL
+        let (mut child, victim) = spawn_victim("sleep 10 & trap '' TERM; exec sleep 10", true);
+        let members = super::group_members(child.id() as i32, Path::new("/proc")).unwrap();
+        assert_eq!(members.len(), 2);
+
+        let outcome = super::kill_process_group(&victim, &strategy, Path::new("/proc")).unwrap();
+
+        assert!(outcome.exited);
+        assert_eq!(outcome.signal, SIGKILL);
+        assert_eq!(child.wait().unwrap().signal(), Some(SIGKILL));
+    }
+
+    #[test]
+    fn should_report_group_members_that_survive() {
+        let proc_root = Path::new("/proc");
+        let (mut child, victim) = spawn_victim(
+            "sleep 10 & (trap '' TERM; exec sleep 10) & trap '' TERM; exec sleep 10",
+            true,
+        );
+        let pgid = child.id() as i32;
+        // Signals sent before then would reach the shells, rather than what they run
+        let members = loop {
+            let members = super::group_members(pgid, proc_root).unwrap();
+            if members.len() == 3 && members.iter().all(|member| member.comm == "sleep") {
+                break members;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        let members: Vec<&Victim> = members.iter().collect();
+
+        super::signal_group(pgid, SIGTERM, &victim, &members, proc_root).unwrap();
+        let survivors = super::wait_for_group(
+            pgid,
+            SIGTERM,
+            &members,
+            Duration::from_millis(200),
+            proc_root,
+        );
+        assert_eq!(survivors.len(), 2);
+        assert!(survivors
+            .iter()
+            .any(|member| member.process.pid == child.id()));
+
+        // Once the victim is gone, its PGID may be reused, so the rest of the group is
+        // signalled through their pidfds instead
+        victim.signal(SIGKILL).unwrap();
+        assert_eq!(child.wait().unwrap().signal(), Some(SIGKILL));
+        let survivors: Vec<&Victim> = survivors
+            .into_iter()
+            .filter(|member| member.process.pid != child.id())
+            .collect();
+        super::signal_group(pgid, SIGKILL, &victim, &survivors, proc_root).unwrap();
+        let survivors =
+            super::wait_for_group(pgid, SIGKILL, &survivors, Duration::from_secs(5), proc_root);
+        assert!(survivors.is_empty());
+        assert!(members.iter().all(|member| member.has_exited(proc_root)));
+
+        // Nor is a group that's already gone an error
+        let strategy = Strategy {
+            ladder: "SIGTERM 200ms SIGKILL".parse().unwrap(),
+            exit_timeout: Duration::from_secs(5),
+            kill_pgroup: true,
+        };
+        let outcome = super::kill_process_group(&victim, &strategy, proc_root).unwrap();
+        assert!(outcome.exited);
+        assert_eq!(outcome.signal, SIGTERM);
+    }
+
+    #[test]
+    fn should_not_hold_processes_of_other_pid_namespaces() {
+        let (mut child, _) = spawn_victim("exec sleep 10", false);
+        let fixture = ProcFixture::new("pid-namespace");
+        let stat = fs::read_to_string(format!("/proc/{}/stat", child.id())).unwrap();
+        fixture.write(format!("{}/stat", child.id()), &stat);
+        let mut victim = Victim {
+            process: Process {
+                pid: child.id(),
+                oom_score: 0,
+            },
+            start_time: None,
+            comm: "sleep".into(),
+            vm_rss_kib: 0,
+            pidfd: None,
+        };
+        victim.start_time = victim.process.start_time(&fixture.root).ok();
+
+        // As a host's /proc, where our processes have other PIDs
+        for fd in 0..1024 {
+            fixture.write(
+                format!("self/fdinfo/{}", fd),
+                "pos:\t0\nflags:\t02000002\nPid:\t1\n",
             );
         }
+        let held = victim.hold(&fixture.root);
+        let has_pidfds = PidFd::open(child.id()).is_ok();
+        assert_eq!(matches!(held, Err(Error::PidNamespaceMismatch)), has_pidfds);
+
+        // As our own
+        fs::remove_dir_all(fixture.root.join("self")).unwrap();
+        std::os::unix::fs::symlink("/proc/self", fixture.root.join("self")).unwrap();
+        victim.hold(&fixture.root).unwrap();
+
+        child.kill().unwrap();
+        child.wait().unwrap();
     }
 
-    Ok(false)
+    #[test]
+    fn should_fail_if_there_is_no_candidate() {
+        let fixture = ProcFixture::new("no-victim");
+        fixture.add_process(200, "kworker", 900, 0, 0);
+
+        let (mut proc_buf, mut buf) = ([0_u8; 128], [0_u8; 256]);
+        assert!(super::choose_victim(&mut proc_buf, &mut buf, &fixture.config()).is_err());
+    }
 }
@@ -0,0 +1,265 @@
+//! Decides which events get logged, from a default level along with per-module overrides,
+//! e.g. `info,kill=debug,pressure=trace`.
+//!
+//! The levels are kept in atomics, so that checking them is cheap and so that
+//! SIGUSR1 and SIGUSR2 can shift them while the daemon runs.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI8, AtomicU8, Ordering};
+
+/// How severe an event is. Events more verbose than the configured level are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    const ALL: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    /// The matching syslog priority. syslog has nothing below debug, so trace is sent as debug.
+    pub fn priority(self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+
+    /// The level `shift` steps more verbose than this one, clamped to the existing levels
+    fn shifted(self, shift: i8) -> Level {
+        let index = (self as i8 + shift).clamp(0, Level::Trace as i8);
+        Level::ALL[index as usize]
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!(
+                "unknown log level \"{}\", expected error, warn, info, debug or trace",
+                level
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // `pad` so that levels can be aligned in the text format
+        f.pad(match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        })
+    }
+}
+
+/// The part of bustd an event comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Module {
+    /// Startup, daemonization and configuration
+    Daemon,
+    /// Memory readings and the decisions taken from them
+    Monitor,
+    /// Choosing victims and killing them
+    Kill,
+    /// PSI triggers and sleeping in between readings
+    Pressure,
+    /// Reading information about processes
+    Process,
+}
+
+impl Module {
+    const ALL: [Module; 5] = [
+        Module::Daemon,
+        Module::Monitor,
+        Module::Kill,
+        Module::Pressure,
+        Module::Process,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Module::Daemon => "daemon",
+            Module::Monitor => "monitor",
+            Module::Kill => "kill",
+            Module::Pressure => "pressure",
+            Module::Process => "process",
+        }
+    }
+}
+
+impl FromStr for Module {
+    type Err = String;
+
+    fn from_str(module: &str) -> Result<Self, Self::Err> {
+        Module::ALL
+            .iter()
+            .copied()
+            .find(|candidate| candidate.name() == module)
+            .ok_or_else(|| {
+                format!(
+                    "unknown log module \"{}\", expected daemon, monitor, kill, pressure or process",
+                    module
+                )
+            })
+    }
+}
+
+/// A default level, along with the modules that log at another level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filter {
+    pub default: Level,
+    /// Indexed by `Module`
+    modules: [Option<Level>; Module::ALL.len()],
+}
+
+impl Filter {
+    pub fn new(default: Level) -> Self {
+        Self {
+            default,
+            modules: [None; Module::ALL.len()],
+        }
+    }
+
+    pub fn level(&self, module: Module) -> Level {
+        self.modules[module as usize].unwrap_or(self.default)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// Parses a comma-separated list of `level` and `module=level` directives,
+    /// where the last one given for a module wins
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        let mut parsed = Filter::new(Level::Info);
+
+        for directive in filter.split(',').map(str::trim) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let module: Module = module.trim().parse()?;
+                    parsed.modules[module as usize] = Some(level.trim().parse()?);
+                }
+                None => parsed.default = directive.parse()?,
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default)?;
+        for module in Module::ALL {
+            if let Some(level) = self.modules[module as usize] {
+                write!(f, ",{}={}", module.name(), level)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The configured level of each module, indexed by `Module`
+static LEVELS: [AtomicU8; Module::ALL.len()] = [
+    AtomicU8::new(Level::Info as u8),
+    AtomicU8::new(Level::Info as u8),
+    AtomicU8::new(Level::Info as u8),
+    AtomicU8::new(Level::Info as u8),
+    AtomicU8::new(Level::Info as u8),
+];
+
+/// The configured default level, which runtime verbosity changes are reported against
+static DEFAULT: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// How many levels more (or, if negative, less) verbose than configured every module is
+static SHIFT: AtomicI8 = AtomicI8::new(0);
+
+/// Starts filtering events with the given filter, undoing any runtime verbosity change
+pub fn set_filter(filter: Filter) {
+    for module in Module::ALL {
+        LEVELS[module as usize].store(filter.level(module) as u8, Ordering::Relaxed);
+    }
+    DEFAULT.store(filter.default as u8, Ordering::Relaxed);
+    SHIFT.store(0, Ordering::Relaxed);
+}
+
+/// Makes every module `steps` levels more verbose, or less verbose if negative,
+/// returning the resulting default level
+pub fn shift_verbosity(steps: i8) -> Level {
+    let default = DEFAULT.load(Ordering::Relaxed) as i8;
+    // Going past error or trace for the default level would only make the next steps
+    // in the other direction seem to do nothing
+    let bounded = |shift: i8| (shift + steps).clamp(-default, Level::Trace as i8 - default);
+
+    let previous = SHIFT
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |shift| {
+            Some(bounded(shift))
+        })
+        .unwrap_or_else(|shift| shift);
+
+    Level::ALL[(default + bounded(previous)) as usize]
+}
+
+fn configured_level(module: Module) -> Level {
+    Level::ALL[LEVELS[module as usize].load(Ordering::Relaxed) as usize]
+}
+
+/// Returns true if events of this level and module are to be logged
+pub fn enabled(level: Level, module: Module) -> bool {
+    level <= configured_level(module).shifted(SHIFT.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, Level, Module};
+
+    #[test]
+    fn should_parse_filters() {
+        let filter: Filter = "debug,kill=trace, pressure = warn".parse().unwrap();
+        assert_eq!(filter.level(Module::Kill), Level::Trace);
+        assert_eq!(filter.level(Module::Pressure), Level::Warn);
+        assert_eq!(filter.level(Module::Monitor), Level::Debug);
+        assert_eq!(filter.to_string(), "debug,kill=trace,pressure=warn");
+
+        let filter: Filter = "process=error".parse().unwrap();
+        assert_eq!(filter.level(Module::Process), Level::Error);
+        assert_eq!(filter.level(Module::Daemon), Level::Info);
+        assert_eq!(filter.to_string().parse::<Filter>().unwrap(), filter);
+
+        assert!("verbose".parse::<Filter>().is_err());
+        assert!("memory=debug".parse::<Filter>().is_err());
+        assert!("kill=loud".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn shifted_levels_should_stay_within_bounds() {
+        assert_eq!(Level::Info.shifted(1), Level::Debug);
+        assert_eq!(Level::Info.shifted(-1), Level::Warn);
+        assert_eq!(Level::Debug.shifted(4), Level::Trace);
+        assert_eq!(Level::Warn.shifted(-4), Level::Error);
+    }
+}
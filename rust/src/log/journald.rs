@@ -0,0 +1,124 @@
+//! Sends events to journald through its native protocol, which keeps structured fields around.
+//!
+//! See https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+use super::Event;
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+pub struct Journald {
+    socket: UnixDatagram,
+}
+
+impl Journald {
+    pub fn connect() -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+
+        Ok(Self { socket })
+    }
+
+    /// Sends the event, with `message` being its text representation
+    pub fn send(&self, event: &Event, message: &[u8], buf: &mut [u8]) -> io::Result<()> {
+        let len = encode(event, message, buf)?;
+        self.socket.send(&buf[..len])?;
+
+        Ok(())
+    }
+}
+
+/// Writes `KEY=value` fields, one per line, into a fixed buffer
+struct FieldWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl FieldWriter<'_> {
+    fn push(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+
+        Ok(())
+    }
+
+    fn field(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.push(key.as_bytes())?;
+        if value.contains(&b'\n') {
+            // Values with newlines are sent as the key, a newline, their length and the value itself
+            self.push(b"\n")?;
+            self.push(&(value.len() as u64).to_le_bytes())?;
+        } else {
+            self.push(b"=")?;
+        }
+        self.push(value)?;
+        self.push(b"\n")
+    }
+}
+
+/// Encodes the event as a journald datagram, returning its length
+fn encode(event: &Event, message: &[u8], buf: &mut [u8]) -> io::Result<usize> {
+    let mut writer = FieldWriter { buf, len: 0 };
+    // Room for any number printed by `format_number`
+    let mut number = [0_u8; 24];
+
+    writer.field("MESSAGE", message)?;
+    writer.field("MESSAGE_ID", event.message_id().as_bytes())?;
+    writer.field(
+        "PRIORITY",
+        format_number(event.level().priority() as i64, &mut number),
+    )?;
+    writer.field("SYSLOG_IDENTIFIER", b"bustd")?;
+    writer.field("BUSTD_EVENT", event.name().as_bytes())?;
+    writer.field("BUSTD_MODULE", event.module().name().as_bytes())?;
+    if let Some(pid) = event.victim_pid() {
+        writer.field("BUSTD_PID", format_number(pid, &mut number))?;
+    }
+    if let Some(comm) = event.victim_comm() {
+        writer.field("BUSTD_VICTIM_COMM", comm.as_bytes())?;
+    }
+
+    Ok(writer.len)
+}
+
+fn format_number(number: i64, buf: &mut [u8; 24]) -> &[u8] {
+    use std::io::Write;
+
+    let mut cursor = &mut buf[..];
+    // An i64 is at most 20 bytes long, so this can't fail
+    let _ = write!(cursor, "{}", number);
+    let len = 24 - cursor.len();
+
+    &buf[..len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode;
+    use crate::log::Event;
+
+    #[test]
+    fn should_encode_structured_fields() {
+        let event = Event::SignalSent {
+            pid: 1234,
+            signal: 15,
+        };
+        let mut buf = [0_u8; 512];
+        let len = encode(&event, b"Sent signal\nto 1234", &mut buf).unwrap();
+
+        let mut expected = b"MESSAGE\n\x13\0\0\0\0\0\0\0Sent signal\nto 1234\n".to_vec();
+        expected.extend_from_slice(b"MESSAGE_ID=117cb0f0e16e4b2e8a1bf2d3779c3343\n");
+        expected.extend_from_slice(b"PRIORITY=6\nSYSLOG_IDENTIFIER=bustd\n");
+        expected.extend_from_slice(b"BUSTD_EVENT=signal_sent\nBUSTD_MODULE=kill\n");
+        expected.extend_from_slice(b"BUSTD_PID=1234\n");
+        assert_eq!(&buf[..len], &expected[..]);
+
+        assert!(encode(&event, b"too long", &mut buf[..16]).is_err());
+    }
+}
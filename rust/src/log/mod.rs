@@ -0,0 +1,1107 @@
+//! Everything bustd reports goes through [`emit`], either as the usual `[LOG]` text lines
+//! or as JSON lines, one object per event, with a timestamp, the event type and its fields.
+//!
+//! Events are written to the console (or to the log file it was redirected to),
+//! to journald, along with structured fields, or to syslog.
+//!
+//! Every event has a level and comes from a module, and is dropped unless the [`Filter`]
+//! lets it through. On the console, every line of text starts with the time, both as
+//! wall-clock and monotonic time, the level and the module.
+//!
+//! Events are formatted into a stack buffer so that logging doesn't allocate.
+
+mod filter;
+mod journald;
+mod syslog;
+mod timestamp;
+
+use std::fmt::{self, Write as _};
+use std::io::{self, Write as _};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub use self::filter::{set_filter, shift_verbosity, Filter, Level, Module};
+use self::journald::Journald;
+use self::timestamp::monotonic;
+pub use self::timestamp::Timestamp;
+use crate::json::JsonObject;
+use crate::memory::pressure::PressureSnapshot;
+
+/// Events longer than this are cut short
+const MAX_EVENT_LEN: usize = 4096;
+
+/// Set if events are to be written as JSON lines
+static JSON: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown log format \"{}\", expected text or json",
+                format
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        })
+    }
+}
+
+/// Where events are written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Standard output, or standard error for errors and verbose output when writing text
+    Console,
+    /// The console, redirected to the configured log file
+    File,
+    Journald,
+    Syslog,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        match target {
+            "console" => Ok(Self::Console),
+            "file" => Ok(Self::File),
+            "journald" => Ok(Self::Journald),
+            "syslog" => Ok(Self::Syslog),
+            _ => Err(format!(
+                "unknown log target \"{}\", expected console, file, journald or syslog",
+                target
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Console => "console",
+            Self::File => "file",
+            Self::Journald => "journald",
+            Self::Syslog => "syslog",
+        })
+    }
+}
+
+enum Sink {
+    Console,
+    Journald(Journald),
+    Syslog,
+}
+
+static SINK: Mutex<Sink> = Mutex::new(Sink::Console);
+
+pub fn set_format(format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+fn format() -> Format {
+    if JSON.load(Ordering::Relaxed) {
+        Format::Json
+    } else {
+        Format::Text
+    }
+}
+
+/// Starts sending events to the given target, falling back to the console if it's unavailable
+pub fn set_target(target: Target) {
+    let sink = match target {
+        Target::Console | Target::File => Sink::Console,
+        Target::Journald => match Journald::connect() {
+            Ok(journald) => Sink::Journald(journald),
+            Err(err) => {
+                emit(&Event::Error {
+                    message: "Could not connect to journald, logging to the console instead",
+                    error: &err,
+                });
+                Sink::Console
+            }
+        },
+        Target::Syslog => {
+            syslog::open();
+            Sink::Syslog
+        }
+    };
+
+    *SINK.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = sink;
+}
+
+pub enum Event<'a> {
+    /// Information about the running kernel, shown on startup
+    Startup {
+        sysname: &'a str,
+        hostname: &'a str,
+        release: &'a str,
+        arch: &'a str,
+    },
+    UnsupportedKernel {
+        release: &'a str,
+    },
+    Daemonizing,
+    Daemonized {
+        user: &'a str,
+    },
+    MemoryLocked,
+    /// Available RAM went under `near_terminal`, so PSI is now being checked
+    NearTerminal {
+        available_ram_mb: u64,
+        available_ram_percent: u8,
+    },
+    /// Available RAM went back above `near_terminal`
+    Recovered {
+        available_ram_mb: u64,
+        available_ram_percent: u8,
+    },
+    /// A process was skipped since it matches an unkillable pattern
+    #[cfg_attr(not(feature = "glob-ignore"), allow(dead_code))]
+    Unkillable {
+        comm: &'a str,
+    },
+    VictimChosen {
+        pid: u32,
+        comm: &'a str,
+        oom_score: i16,
+        rss_kib: i64,
+        search_time: Duration,
+    },
+    /// The victim is about to be killed, or would be in dry-run mode,
+    /// along with the pressure values that led to it
+    Kill {
+        pid: u32,
+        comm: &'a str,
+        oom_score: i16,
+        rss_kib: i64,
+        psi: &'a PressureSnapshot,
+        window_psi: Option<f32>,
+        dry_run: bool,
+    },
+    /// The victim matches a rule, which decides how it's killed
+    RuleApplied {
+        pid: u32,
+        line: usize,
+    },
+    /// The process is the most likely victim found so far
+    Candidate {
+        pid: u32,
+        oom_score: i16,
+        rss_kib: i64,
+    },
+    /// Memory is low, but a victim was chosen too recently
+    Cooldown,
+    /// Killing was paused through the control socket
+    Paused {
+        duration: Duration,
+    },
+    /// Killing resumed, either through the control socket or once the pause was over
+    Resumed,
+    /// Memory is low, but killing is paused
+    StillPaused,
+    /// A negative PID means the signal was sent to a process group
+    SignalSent {
+        pid: i32,
+        signal: i32,
+    },
+    /// Pressure reached `sigkill_psi_full`, so the victim is sent SIGKILL without going
+    /// through its signal ladder
+    LadderSkipped {
+        pid: u32,
+        full_avg10: f32,
+    },
+    /// The victim did not exit after the signals before SIGKILL, so SIGKILL was sent
+    Escalated {
+        pid: u32,
+        after: Duration,
+    },
+    Exited {
+        pid: u32,
+        after: Duration,
+    },
+    /// Members of the victim's process group, by PID and name, that outlived the signal
+    GroupSurvived {
+        pgid: i32,
+        signal: i32,
+        survivors: &'a [(u32, &'a str)],
+    },
+    /// The victim's memory was reaped through process_mrelease right after SIGKILL
+    MemoryReleased {
+        pid: u32,
+        took: Duration,
+    },
+    /// process_mrelease failed, which is only logged the first time it does
+    MemoryReleaseFailed {
+        pid: u32,
+        error: &'a dyn fmt::Debug,
+    },
+    ConfigReloaded {
+        changes: usize,
+    },
+    ConfigChanged {
+        key: &'a str,
+        old: &'a str,
+        new: &'a str,
+    },
+    /// A configuration key changed, but only takes effect after a restart
+    RestartRequired {
+        key: &'a str,
+    },
+    /// Verbosity was changed through SIGUSR1 or SIGUSR2
+    VerbosityChanged {
+        level: Level,
+    },
+    AdaptiveSleep {
+        duration: Duration,
+    },
+    WaitingForTrigger {
+        timeout: Duration,
+    },
+    TriggerFired,
+    /// A hook exited unsuccessfully
+    HookFailed {
+        hook: &'a str,
+        status: &'a dyn fmt::Display,
+    },
+    HookTimedOut {
+        hook: &'a str,
+        timeout: Duration,
+    },
+    Error {
+        message: &'a str,
+        error: &'a dyn fmt::Debug,
+    },
+}
+
+impl Event<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Startup { .. } => "startup",
+            Event::UnsupportedKernel { .. } => "unsupported_kernel",
+            Event::Daemonizing => "daemonizing",
+            Event::Daemonized { .. } => "daemonized",
+            Event::MemoryLocked => "memory_locked",
+            Event::NearTerminal { .. } => "near_terminal",
+            Event::Recovered { .. } => "recovered",
+            Event::Unkillable { .. } => "unkillable",
+            Event::VictimChosen { .. } => "victim_chosen",
+            Event::Kill { .. } => "kill",
+            Event::RuleApplied { .. } => "rule_applied",
+            Event::Candidate { .. } => "candidate",
+            Event::Cooldown => "cooldown",
+            Event::Paused { .. } => "paused",
+            Event::Resumed => "resumed",
+            Event::StillPaused => "still_paused",
+            Event::SignalSent { .. } => "signal_sent",
+            Event::LadderSkipped { .. } => "ladder_skipped",
+            Event::Escalated { .. } => "escalated",
+            Event::Exited { .. } => "exited",
+            Event::GroupSurvived { .. } => "group_survived",
+            Event::MemoryReleased { .. } => "memory_released",
+            Event::MemoryReleaseFailed { .. } => "memory_release_failed",
+            Event::ConfigReloaded { .. } => "config_reloaded",
+            Event::ConfigChanged { .. } => "config_changed",
+            Event::RestartRequired { .. } => "restart_required",
+            Event::VerbosityChanged { .. } => "verbosity_changed",
+            Event::AdaptiveSleep { .. } => "adaptive_sleep",
+            Event::WaitingForTrigger { .. } => "waiting_for_trigger",
+            Event::TriggerFired => "trigger_fired",
+            Event::HookFailed { .. } => "hook_failed",
+            Event::HookTimedOut { .. } => "hook_timed_out",
+            Event::Error { .. } => "error",
+        }
+    }
+
+    /// A stable identifier of the event type, given to journald as `MESSAGE_ID`
+    fn message_id(&self) -> &'static str {
+        match self {
+            Event::Startup { .. } => "42192b6f9632460e83f9a757bb463010",
+            Event::UnsupportedKernel { .. } => "ae2b17c1370244718b11897d31290bfd",
+            Event::Daemonizing => "b982eb5dc78a4c8c905622c1a6f6ed7e",
+            Event::Daemonized { .. } => "b160468f19374e7aaa7667d7b6b1858f",
+            Event::MemoryLocked => "a2fbf186458843a2808a1a0226c048b6",
+            Event::NearTerminal { .. } => "dcbe889ea0624c5ab73a9f37df43c24c",
+            Event::Recovered { .. } => "2851b6d81e114e27acf0bb2d4504d82a",
+            Event::Unkillable { .. } => "c51d5cd2195145f995ef57615d520bb5",
+            Event::VictimChosen { .. } => "93ca32b745f84786b5e2b9f0520d947f",
+            Event::Kill { .. } => "b2b61f7047d84826b7e1a194cec0257e",
+            Event::RuleApplied { .. } => "7b9c622180214a218cc6f3d8a544f4af",
+            Event::Candidate { .. } => "cb143264d9694b3a918f159bff85a1b5",
+            Event::Cooldown => "41e33b8a8e0a4ff98384bce4ec27de06",
+            Event::Paused { .. } => "dab4a12e2e2d4a22a8e7b95753faaad0",
+            Event::Resumed => "a061d16d104e4274964915fe50b6162f",
+            Event::StillPaused => "c6dbfad98cf5409ea434acc52046847f",
+            Event::SignalSent { .. } => "117cb0f0e16e4b2e8a1bf2d3779c3343",
+            Event::LadderSkipped { .. } => "e72a6b0245e8410cb30bb6989295a1d6",
+            Event::Escalated { .. } => "a18049e8461f46dfa3291f8981cd07f3",
+            Event::Exited { .. } => "e2b182a7928c4cd1ad543dcd47bd8808",
+            Event::GroupSurvived { .. } => "145c357b0d4a42c8a075bb202b1428ef",
+            Event::MemoryReleased { .. } => "e0f09da8b743408ab8d54def9300606f",
+            Event::MemoryReleaseFailed { .. } => "d72eba91cde9468ea0a19c0bade36069",
+            Event::ConfigReloaded { .. } => "704592635e9942a0816a0f9359e6635a",
+            Event::ConfigChanged { .. } => "f85c2e27f895470bad8690f1faf7cb9a",
+            Event::RestartRequired { .. } => "2ee04c0456d3464893a810db5b4abc4e",
+            Event::VerbosityChanged { .. } => "dd62afe279ee4eb8ad972f685ef7cacf",
+            Event::AdaptiveSleep { .. } => "0a61648982b147489a2cba9dccd864f6",
+            Event::WaitingForTrigger { .. } => "834f069ce96545c8a2f5ba5d009f5fae",
+            Event::TriggerFired => "1a4b9c8435ff4bbca17b20f08ad67a1b",
+            Event::HookFailed { .. } => "2c6579e0db4e4bfda07d1dab9f2be77c",
+            Event::HookTimedOut { .. } => "62e2bf59a9f6423d81d3547f24a8db67",
+            Event::Error { .. } => "b784d7af57534bb88b93352f4991e9e8",
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        match self {
+            Event::UnsupportedKernel { .. } | Event::Error { .. } => Level::Error,
+            Event::Kill { .. }
+            | Event::LadderSkipped { .. }
+            | Event::Escalated { .. }
+            | Event::GroupSurvived { .. }
+            | Event::HookFailed { .. }
+            | Event::HookTimedOut { .. } => Level::Warn,
+            Event::Startup { .. }
+            | Event::Daemonizing
+            | Event::Daemonized { .. }
+            | Event::MemoryLocked
+            | Event::NearTerminal { .. }
+            | Event::Recovered { .. }
+            | Event::Unkillable { .. }
+            | Event::VictimChosen { .. }
+            | Event::RuleApplied { .. }
+            | Event::SignalSent { .. }
+            | Event::Exited { .. }
+            | Event::MemoryReleased { .. }
+            | Event::ConfigReloaded { .. }
+            | Event::ConfigChanged { .. }
+            | Event::RestartRequired { .. }
+            | Event::VerbosityChanged { .. }
+            | Event::Paused { .. }
+            | Event::Resumed => Level::Info,
+            Event::Cooldown
+            | Event::StillPaused
+            | Event::AdaptiveSleep { .. }
+            | Event::WaitingForTrigger { .. }
+            | Event::TriggerFired
+            | Event::MemoryReleaseFailed { .. } => Level::Debug,
+            Event::Candidate { .. } => Level::Trace,
+        }
+    }
+
+    pub fn module(&self) -> Module {
+        match self {
+            Event::Startup { .. }
+            | Event::UnsupportedKernel { .. }
+            | Event::Daemonizing
+            | Event::Daemonized { .. }
+            | Event::MemoryLocked
+            | Event::ConfigReloaded { .. }
+            | Event::ConfigChanged { .. }
+            | Event::RestartRequired { .. }
+            | Event::VerbosityChanged { .. }
+            | Event::Error { .. } => Module::Daemon,
+            Event::NearTerminal { .. }
+            | Event::Recovered { .. }
+            | Event::Kill { .. }
+            | Event::Cooldown
+            | Event::Paused { .. }
+            | Event::Resumed
+            | Event::StillPaused
+            | Event::HookFailed { .. }
+            | Event::HookTimedOut { .. } => Module::Monitor,
+            Event::VictimChosen { .. }
+            | Event::RuleApplied { .. }
+            | Event::Candidate { .. }
+            | Event::SignalSent { .. }
+            | Event::LadderSkipped { .. }
+            | Event::Escalated { .. }
+            | Event::Exited { .. }
+            | Event::GroupSurvived { .. }
+            | Event::MemoryReleased { .. }
+            | Event::MemoryReleaseFailed { .. } => Module::Kill,
+            Event::AdaptiveSleep { .. } | Event::WaitingForTrigger { .. } | Event::TriggerFired => {
+                Module::Pressure
+            }
+            Event::Unkillable { .. } => Module::Process,
+        }
+    }
+
+    /// The PID of the process this event is about, if any.
+    /// Negative for signals sent to process groups.
+    fn victim_pid(&self) -> Option<i64> {
+        match self {
+            Event::VictimChosen { pid, .. }
+            | Event::Kill { pid, .. }
+            | Event::RuleApplied { pid, .. }
+            | Event::Candidate { pid, .. }
+            | Event::LadderSkipped { pid, .. }
+            | Event::Escalated { pid, .. }
+            | Event::Exited { pid, .. }
+            | Event::MemoryReleased { pid, .. }
+            | Event::MemoryReleaseFailed { pid, .. } => Some(*pid as i64),
+            Event::SignalSent { pid, .. } => Some(*pid as i64),
+            Event::GroupSurvived { pgid, .. } => Some(-*pgid as i64),
+            _ => None,
+        }
+    }
+
+    /// The name of the process this event is about, if any
+    fn victim_comm(&self) -> Option<&str> {
+        match self {
+            Event::Unkillable { comm }
+            | Event::VictimChosen { comm, .. }
+            | Event::Kill { comm, .. } => Some(comm),
+            _ => None,
+        }
+    }
+
+    /// Errors and verbose output go to stderr when writing text
+    fn is_diagnostic(&self) -> bool {
+        matches!(
+            self,
+            Event::UnsupportedKernel { .. }
+                | Event::Candidate { .. }
+                | Event::Cooldown
+                | Event::StillPaused
+                | Event::AdaptiveSleep { .. }
+                | Event::WaitingForTrigger { .. }
+                | Event::TriggerFired
+                | Event::MemoryReleaseFailed { .. }
+                | Event::HookFailed { .. }
+                | Event::HookTimedOut { .. }
+                | Event::Error { .. }
+        )
+    }
+
+    fn write_json(&self, out: &mut impl fmt::Write, header: &Header) -> fmt::Result {
+        let mut object = JsonObject::new(out)?;
+        object.string("ts", header.timestamp)?;
+        object.number("mono_ms", header.monotonic.as_millis() as i128)?;
+        object.string("level", header.level)?;
+        object.string("module", header.module.name())?;
+        object.string("event", self.name())?;
+
+        match self {
+            Event::Startup {
+                sysname,
+                hostname,
+                release,
+                arch,
+            } => {
+                object.string("sysname", sysname)?;
+                object.string("hostname", hostname)?;
+                object.string("release", release)?;
+                object.string("arch", arch)?;
+            }
+            Event::UnsupportedKernel { release } => object.string("release", release)?,
+            Event::Daemonized { user } => object.string("user", user)?,
+            Event::NearTerminal {
+                available_ram_mb,
+                available_ram_percent,
+            }
+            | Event::Recovered {
+                available_ram_mb,
+                available_ram_percent,
+            } => {
+                object.number("available_ram_mb", *available_ram_mb)?;
+                object.number("available_ram_percent", *available_ram_percent)?;
+            }
+            Event::Unkillable { comm } => object.string("comm", comm)?,
+            Event::VictimChosen {
+                pid,
+                comm,
+                oom_score,
+                rss_kib,
+                search_time,
+            } => {
+                object.number("pid", *pid)?;
+                object.string("comm", comm)?;
+                object.number("oom_score", *oom_score)?;
+                object.number("rss_kib", *rss_kib)?;
+                object.number("search_time_ms", search_time.as_millis() as i128)?;
+            }
+            Event::Kill {
+                pid,
+                comm,
+                oom_score,
+                rss_kib,
+                psi,
+                window_psi,
+                dry_run,
+            } => {
+                object.number("pid", *pid)?;
+                object.string("comm", comm)?;
+                object.number("oom_score", *oom_score)?;
+                object.number("rss_kib", *rss_kib)?;
+                object.float("psi_some_avg10", Some(psi.some.avg10))?;
+                object.float("psi_some_avg60", Some(psi.some.avg60))?;
+                object.float("psi_full_avg10", Some(psi.full.avg10))?;
+                object.float("psi_window", *window_psi)?;
+                object.boolean("dry_run", *dry_run)?;
+            }
+            Event::RuleApplied { pid, line } => {
+                object.number("pid", *pid)?;
+                object.number("line", *line as i128)?;
+            }
+            Event::Candidate {
+                pid,
+                oom_score,
+                rss_kib,
+            } => {
+                object.number("pid", *pid)?;
+                object.number("oom_score", *oom_score)?;
+                object.number("rss_kib", *rss_kib)?;
+            }
+            Event::SignalSent { pid, signal } => {
+                object.number("pid", *pid)?;
+                object.number("signal", *signal)?;
+            }
+            Event::LadderSkipped { pid, full_avg10 } => {
+                object.number("pid", *pid)?;
+                object.float("psi_full_avg10", Some(*full_avg10))?;
+            }
+            Event::Escalated { pid, after } | Event::Exited { pid, after } => {
+                object.number("pid", *pid)?;
+                object.number("after_ms", after.as_millis() as i128)?;
+            }
+            Event::GroupSurvived {
+                pgid,
+                signal,
+                survivors,
+            } => {
+                object.number("pgid", *pgid)?;
+                object.number("signal", *signal)?;
+                let mut array = object.array("survivors")?;
+                for (pid, comm) in survivors.iter() {
+                    let mut survivor = array.object()?;
+                    survivor.number("pid", *pid)?;
+                    survivor.string("comm", comm)?;
+                    survivor.finish()?;
+                }
+                array.finish()?;
+            }
+            Event::MemoryReleased { pid, took } => {
+                object.number("pid", *pid)?;
+                object.number("took_us", took.as_micros() as i128)?;
+            }
+            Event::MemoryReleaseFailed { pid, error } => {
+                object.number("pid", *pid)?;
+                object.debug("error", *error)?;
+            }
+            Event::ConfigReloaded { changes } => object.number("changes", *changes as i128)?,
+            Event::ConfigChanged { key, old, new } => {
+                object.string("key", key)?;
+                object.string("old", old)?;
+                object.string("new", new)?;
+            }
+            Event::RestartRequired { key } => object.string("key", key)?,
+            Event::VerbosityChanged { level } => object.string("level", level)?,
+            Event::Paused { duration } | Event::AdaptiveSleep { duration } => {
+                object.number("duration_ms", duration.as_millis() as i128)?
+            }
+            Event::WaitingForTrigger { timeout } => {
+                object.number("timeout_ms", timeout.as_millis() as i128)?
+            }
+            Event::HookFailed { hook, status } => {
+                object.string("hook", hook)?;
+                object.string("status", status)?;
+            }
+            Event::HookTimedOut { hook, timeout } => {
+                object.string("hook", hook)?;
+                object.number("timeout_ms", timeout.as_millis() as i128)?;
+            }
+            Event::Error { message, error } => {
+                object.string("message", message)?;
+                object.debug("error", *error)?;
+            }
+            Event::Daemonizing
+            | Event::MemoryLocked
+            | Event::Cooldown
+            | Event::Resumed
+            | Event::StillPaused
+            | Event::TriggerFired => {}
+        }
+
+        object.finish()
+    }
+}
+
+/// The text format, as bustd has always written it
+impl fmt::Display for Event<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Startup {
+                sysname,
+                hostname,
+                release,
+                arch,
+            } => {
+                writeln!(f, "OS:           {}", sysname)?;
+                writeln!(f, "Hostname:     {}", hostname)?;
+                writeln!(f, "Version:      {}", release)?;
+                write!(f, "Architecture: {}", arch)
+            }
+            Event::UnsupportedKernel { release } => write!(
+                f,
+                "{} does not meet minimum requirements for bustd!\nEnsure you're running at least Linux 4.20",
+                release
+            ),
+            Event::Daemonizing => write!(f, "Starting daemonization process!"),
+            Event::Daemonized { user } => write!(
+                f,
+                "[LOG] User {} has started the daemon successfully.",
+                user
+            ),
+            Event::MemoryLocked => write!(f, "Memory pages locked!"),
+            Event::NearTerminal {
+                available_ram_mb,
+                available_ram_percent,
+            } => write!(
+                f,
+                "[LOG] Available RAM is near terminal ({} MB, {}%), checking PSI.",
+                available_ram_mb, available_ram_percent
+            ),
+            Event::Recovered {
+                available_ram_mb,
+                available_ram_percent,
+            } => write!(
+                f,
+                "[LOG] Available RAM is no longer near terminal ({} MB, {}%).",
+                available_ram_mb, available_ram_percent
+            ),
+            Event::Unkillable { comm } => write!(
+                f,
+                "Skipping \"{}\" since it matches an unkillable pattern",
+                comm
+            ),
+            Event::VictimChosen {
+                pid,
+                comm,
+                oom_score,
+                rss_kib,
+                search_time,
+            } => write!(
+                f,
+                "[LOG] Found victim in {} secs.\n[LOG] Victim => pid: {}, comm: {}, oom_score: {}, rss: {} KiB",
+                search_time.as_secs(),
+                pid,
+                comm,
+                oom_score,
+                rss_kib
+            ),
+            Event::Kill {
+                pid,
+                comm,
+                oom_score,
+                rss_kib,
+                psi,
+                window_psi,
+                dry_run,
+            } => write!(
+                f,
+                "[LOG] {} => pid: {}, comm: {}, oom_score: {}, rss: {} KiB, \
+                 psi: some avg10={:.2} avg60={:.2} full avg10={:.2} window={:.2}",
+                if *dry_run {
+                    "[DRY-RUN] Would kill"
+                } else {
+                    "Killing"
+                },
+                pid,
+                comm,
+                oom_score,
+                rss_kib,
+                psi.some.avg10,
+                psi.some.avg60,
+                psi.full.avg10,
+                window_psi.unwrap_or(0.0),
+            ),
+            Event::RuleApplied { pid, line } => write!(
+                f,
+                "[LOG] PID {} matches the rule at line {} of the rules file.",
+                pid, line
+            ),
+            Event::Candidate {
+                pid,
+                oom_score,
+                rss_kib,
+            } => write!(
+                f,
+                "[candidate] pid: {}, oom_score: {}, rss: {} KiB",
+                pid, oom_score, rss_kib
+            ),
+            Event::Cooldown => write!(
+                f,
+                "[cooldown] memory is low, but a victim was chosen recently"
+            ),
+            Event::Paused { duration } => write!(
+                f,
+                "[LOG] Paused through the control socket for {}s.",
+                duration.as_secs()
+            ),
+            Event::Resumed => write!(f, "[LOG] Resumed, processes may be killed again."),
+            Event::StillPaused => write!(f, "[paused] memory is low, but killing is paused"),
+            Event::SignalSent { pid, signal } if *pid < 0 => write!(
+                f,
+                "[LOG] Sent signal {} to process group {}.",
+                signal, -pid
+            ),
+            Event::SignalSent { pid, signal } => {
+                write!(f, "[LOG] Sent signal {} to PID {}.", signal, pid)
+            }
+            Event::LadderSkipped { pid, full_avg10 } => write!(
+                f,
+                "[LOG] Pressure is critical (full avg10={:.2}), sending SIGKILL to PID {} right away.",
+                full_avg10, pid
+            ),
+            Event::Escalated { after, .. } => write!(
+                f,
+                "[LOG] Escalated to SIGKILL after {} nanosecs",
+                after.as_nanos()
+            ),
+            Event::Exited { pid, after } => write!(
+                f,
+                "[LOG] Process with PID {} has exited after {} ms.",
+                pid,
+                after.as_millis()
+            ),
+            Event::GroupSurvived {
+                pgid,
+                signal,
+                survivors,
+            } => {
+                write!(
+                    f,
+                    "[LOG] {} process(es) of group {} survived signal {}:",
+                    survivors.len(),
+                    pgid,
+                    signal
+                )?;
+                for (pid, comm) in survivors.iter() {
+                    write!(f, " {} ({})", pid, comm)?;
+                }
+                Ok(())
+            }
+            Event::MemoryReleased { pid, took } => write!(
+                f,
+                "[LOG] Released the memory of PID {} in {} us.",
+                pid,
+                took.as_micros()
+            ),
+            Event::MemoryReleaseFailed { pid, error } => write!(
+                f,
+                "[LOG] Could not release the memory of PID {}, leaving it to the victim: {:?}",
+                pid, error
+            ),
+            Event::ConfigReloaded { changes: 0 } => {
+                write!(f, "[LOG] Configuration reloaded, nothing changed.")
+            }
+            Event::ConfigReloaded { changes } => write!(
+                f,
+                "[LOG] Configuration reloaded, {} key(s) changed.",
+                changes
+            ),
+            Event::ConfigChanged { key, old, new } => write!(
+                f,
+                "[LOG] Configuration reloaded: {} changed from {} to {}",
+                key, old, new
+            ),
+            Event::RestartRequired { key } => write!(
+                f,
+                "[LOG] Changes to {} only take effect after a restart.",
+                key
+            ),
+            Event::VerbosityChanged { level } => {
+                write!(f, "[LOG] Verbosity changed, now logging at {}.", level)
+            }
+            Event::AdaptiveSleep { duration } => {
+                write!(f, "[adaptive-sleep] {}ms", duration.as_millis())
+            }
+            Event::WaitingForTrigger { timeout } => write!(
+                f,
+                "[psi-trigger] waiting for up to {}ms",
+                timeout.as_millis()
+            ),
+            Event::TriggerFired => write!(f, "[psi-trigger] stall threshold exceeded"),
+            Event::HookFailed { hook, status } => {
+                write!(f, "[LOG] The {} hook exited with {}", hook, status)
+            }
+            Event::HookTimedOut { hook, timeout } => write!(
+                f,
+                "[LOG] The {} hook timed out after {}ms, killing it.",
+                hook,
+                timeout.as_millis()
+            ),
+            Event::Error { message, error } => write!(f, "[LOG] {}: {:?}", message, error),
+        }
+    }
+}
+
+/// What every event is logged along with
+struct Header {
+    timestamp: Timestamp,
+    /// Time since boot, which unlike the timestamp never jumps around
+    monotonic: Duration,
+    level: Level,
+    module: Module,
+}
+
+impl Header {
+    fn of(event: &Event) -> Self {
+        Self {
+            timestamp: Timestamp::now(),
+            monotonic: monotonic(),
+            level: event.level(),
+            module: event.module(),
+        }
+    }
+}
+
+/// Starts lines of text, e.g. `2021-09-04T21:30:12.345Z [ 1234.567890] info  kill: `
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{:>5}.{:06}] {:<5} {}: ",
+            self.timestamp,
+            self.monotonic.as_secs(),
+            self.monotonic.subsec_micros(),
+            self.level,
+            self.module.name()
+        )
+    }
+}
+
+/// A `fmt::Write` over a fixed buffer, failing once the buffer is full
+struct StackWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl StackWriter<'_> {
+    fn written(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl fmt::Write for StackWriter<'_> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        let available = self.buf.len() - self.len;
+        let fits = string.len().min(available);
+        self.buf[self.len..self.len + fits].copy_from_slice(&string.as_bytes()[..fits]);
+        self.len += fits;
+
+        if fits < string.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// Writes the header at the start of every line, if there is one
+struct LineWriter<'a, W> {
+    out: W,
+    header: Option<&'a Header>,
+    at_line_start: bool,
+}
+
+impl<W: fmt::Write> fmt::Write for LineWriter<'_, W> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for line in string.split_inclusive('\n') {
+            if let (Some(header), true) = (self.header, self.at_line_start) {
+                write!(self.out, "{}", header)?;
+            }
+            self.out.write_str(line)?;
+            self.at_line_start = line.ends_with('\n');
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the event in the given format, followed by a newline.
+/// Lines of text start with the header unless `with_header` is false.
+fn format_event(
+    event: &Event,
+    format: Format,
+    header: &Header,
+    with_header: bool,
+    buf: &mut [u8],
+) -> usize {
+    // Leave room for the newline
+    let capacity = buf.len() - 1;
+    let mut writer = StackWriter {
+        buf: &mut buf[..capacity],
+        len: 0,
+    };
+
+    let written = match format {
+        Format::Text => {
+            let mut lines = LineWriter {
+                out: &mut writer,
+                header: Some(header).filter(|_| with_header),
+                at_line_start: true,
+            };
+            write!(lines, "{}", event)
+        }
+        Format::Json => event.write_json(&mut writer, header),
+    };
+    if written.is_err() && format == Format::Json {
+        // Truncated JSON is of no use, so we only keep what's sure to fit
+        writer.len = 0;
+        let _ = write!(
+            writer,
+            r#"{{"ts":"{}","event":"{}","truncated":true}}"#,
+            header.timestamp,
+            event.name()
+        );
+    }
+
+    let len = writer.written().len();
+    buf[len] = b'\n';
+    len + 1
+}
+
+/// Reports an event in the configured format, to the configured target,
+/// unless the filter drops it.
+/// On the console, text goes to stdout, or to stderr for errors and verbose output,
+/// while JSON always goes to stdout.
+pub fn emit(event: &Event) {
+    if !filter::enabled(event.level(), event.module()) {
+        return;
+    }
+
+    let mut buf = [0_u8; MAX_EVENT_LEN];
+    let format = format();
+    let header = Header::of(event);
+    let sink = SINK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    // Logging must never bring the daemon down
+    match &*sink {
+        Sink::Console => {
+            let len = format_event(event, format, &header, true, &mut buf);
+            let _ = if format == Format::Text && event.is_diagnostic() {
+                io::stderr().write_all(&buf[..len])
+            } else {
+                io::stdout().write_all(&buf[..len])
+            };
+        }
+        Sink::Journald(journald) => {
+            // journald keeps its own timestamps and gets the fields on their own,
+            // so the message is always text, without a header
+            let len = format_event(event, Format::Text, &header, false, &mut buf);
+            let mut datagram = [0_u8; MAX_EVENT_LEN + 512];
+            let _ = journald.send(event, &buf[..len - 1], &mut datagram);
+        }
+        Sink::Syslog => {
+            // syslog timestamps messages and knows their priority already
+            let len = format_event(event, format, &header, false, &mut buf);
+            // Swap the newline for the NUL terminator
+            buf[len - 1] = 0;
+            syslog::send(event.level(), &buf[..len]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format_event, Event, Format, Header, Timestamp};
+    use crate::memory::pressure::{PressureLine, PressureSnapshot};
+
+    fn format(event: &Event, format: Format, buf: &mut [u8]) -> String {
+        let header = Header {
+            timestamp: Timestamp::from_millis(1_000),
+            monotonic: Duration::from_micros(1_500_250),
+            level: event.level(),
+            module: event.module(),
+        };
+        let len = format_event(event, format, &header, true, buf);
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn should_write_events_as_json_lines() {
+        let psi = PressureSnapshot {
+            some: PressureLine {
+                avg10: 30.5,
+                ..PressureLine::default()
+            },
+            ..PressureSnapshot::default()
+        };
+        let event = Event::Kill {
+            pid: 1234,
+            comm: "firefox",
+            oom_score: 800,
+            rss_kib: 2048,
+            psi: &psi,
+            window_psi: None,
+            dry_run: false,
+        };
+
+        let mut buf = [0_u8; 512];
+        assert_eq!(
+            format(&event, Format::Json, &mut buf),
+            "{\"ts\":\"1970-01-01T00:00:01.000Z\",\"mono_ms\":1500,\"level\":\"warn\",\
+             \"module\":\"monitor\",\"event\":\"kill\",\"pid\":1234,\"comm\":\"firefox\",\
+             \"oom_score\":800,\"rss_kib\":2048,\"psi_some_avg10\":30.5,\"psi_some_avg60\":0,\
+             \"psi_full_avg10\":0,\"psi_window\":null,\"dry_run\":false}\n"
+        );
+        assert_eq!(
+            format(&event, Format::Text, &mut buf),
+            "1970-01-01T00:00:01.000Z [    1.500250] warn  monitor: [LOG] Killing => pid: 1234, comm: firefox, oom_score: 800, rss: 2048 KiB, \
+             psi: some avg10=30.50 avg60=0.00 full avg10=0.00 window=0.00\n"
+        );
+    }
+
+    #[test]
+    fn every_line_of_text_should_start_with_the_header() {
+        let event = Event::VictimChosen {
+            pid: 1234,
+            comm: "firefox",
+            oom_score: 800,
+            rss_kib: 2048,
+            search_time: Duration::from_millis(20),
+        };
+
+        let mut buf = [0_u8; 512];
+        assert_eq!(
+            format(&event, Format::Text, &mut buf),
+            "1970-01-01T00:00:01.000Z [    1.500250] info  kill: [LOG] Found victim in 0 secs.\n\
+             1970-01-01T00:00:01.000Z [    1.500250] info  kill: [LOG] Victim => pid: 1234, \
+             comm: firefox, oom_score: 800, rss: 2048 KiB\n"
+        );
+    }
+
+    #[test]
+    fn json_events_that_dont_fit_should_still_be_valid() {
+        let event = Event::Exited {
+            pid: 1,
+            after: Duration::from_millis(500),
+        };
+
+        let mut buf = [0_u8; 72];
+        assert_eq!(
+            format(&event, Format::Json, &mut buf),
+            "{\"ts\":\"1970-01-01T00:00:01.000Z\",\"event\":\"exited\",\"truncated\":true}\n"
+        );
+    }
+}
@@ -0,0 +1,34 @@
+//! Sends events through syslog(3), which writes them to `/dev/log`
+
+use libc::{c_char, c_int, LOG_DAEMON, LOG_NDELAY, LOG_PID};
+
+use super::Level;
+
+/// openlog(3) keeps this pointer around, so it must live for as long as the program
+static IDENTIFIER: &[u8] = b"bustd\0";
+
+pub fn open() {
+    // Safety: the identifier is NUL-terminated and has a static lifetime
+    unsafe {
+        libc::openlog(
+            IDENTIFIER.as_ptr() as *const c_char,
+            LOG_PID | LOG_NDELAY,
+            LOG_DAEMON,
+        )
+    };
+}
+
+/// Sends the message, which must be NUL-terminated
+pub fn send(level: Level, message: &[u8]) {
+    debug_assert_eq!(message.last(), Some(&0));
+
+    // Safety: both strings are NUL-terminated, and the message is passed as an argument
+    //         rather than as the format string, so it may contain `%` safely
+    unsafe {
+        libc::syslog(
+            level.priority() as c_int,
+            b"%s\0".as_ptr() as *const c_char,
+            message.as_ptr() as *const c_char,
+        )
+    };
+}
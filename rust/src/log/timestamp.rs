@@ -0,0 +1,104 @@
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A point in time, displayed in RFC 3339 format and in UTC, with millisecond precision,
+/// e.g. `2021-09-04T21:30:12.345Z`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timestamp {
+    /// Milliseconds since the Unix epoch
+    millis: u64,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Self {
+            millis: since_epoch.as_millis() as u64,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_millis(millis: u64) -> Self {
+        Self { millis }
+    }
+}
+
+/// Time elapsed since boot, not counting time spent suspended
+pub fn monotonic() -> Duration {
+    let mut time = MaybeUninit::<libc::timespec>::uninit();
+
+    // Safety: CLOCK_MONOTONIC is always supported and `time` is a valid timespec to write to
+    let time = unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, time.as_mut_ptr());
+        time.assume_init()
+    };
+
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date in the Gregorian calendar
+///
+/// Credits: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.millis / 1000;
+        let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+        let seconds_of_day = seconds % 86400;
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year,
+            month,
+            day,
+            seconds_of_day / 3600,
+            seconds_of_day % 3600 / 60,
+            seconds_of_day % 60,
+            self.millis % 1000
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timestamp;
+
+    #[test]
+    fn should_format_timestamps_as_rfc3339() {
+        assert_eq!(
+            Timestamp::from_millis(0).to_string(),
+            "1970-01-01T00:00:00.000Z"
+        );
+        assert_eq!(
+            Timestamp::from_millis(951_782_400_001).to_string(),
+            "2000-02-29T00:00:00.001Z"
+        );
+        assert_eq!(
+            Timestamp::from_millis(1_630_791_012_345).to_string(),
+            "2021-09-04T21:30:12.345Z"
+        );
+    }
+}
@@ -5,17 +5,36 @@ use std::ops::Not;
 use linux_version::LinuxVersion;
 use uname::Uname;
 
-use crate::{error::Error, memory::lock_memory_pages, monitor::Monitor};
+use crate::{
+    config::Config,
+    error::Error,
+    log::{Event, Target},
+    memory::lock_memory_pages,
+    monitor::Monitor,
+};
 
 mod cli;
+mod commands;
+mod config;
+mod control;
 mod daemon;
+mod dbus;
 mod errno;
 mod error;
+mod hooks;
+mod json;
 mod kill;
 mod linux_version;
+mod log;
 mod memory;
+mod metrics;
 mod monitor;
+mod notify;
+mod pidfd;
 mod process;
+mod signals;
+#[cfg(test)]
+mod testing;
 mod uname;
 mod utils;
 
@@ -26,63 +45,82 @@ const LINUX_4_20: LinuxVersion = LinuxVersion {
 };
 
 fn main() -> error::Result<()> {
-    let args: cli::CommandLineArgs = argh::from_env();
-    let should_daemonize = args.no_daemon.not();
+    let mut args: cli::CommandLineArgs = argh::from_env();
+    // The daemon changes its working directory, so make sure the configuration
+    // file can still be found when it gets reloaded
+    args.config = args
+        .config
+        .map(|path| std::fs::canonicalize(&path).unwrap_or(path));
+
+    let config = Config::load(&args)?;
+    if args.print_config {
+        print!("{}", config);
+        return Ok(());
+    }
+    if let Some(command) = &args.command {
+        return commands::run(command, &config);
+    }
+
+    let should_daemonize = config.no_daemon.not();
+
+    log::set_filter(config.log_filter());
+    log::set_format(config.log_format);
+    log::set_target(config.log_target());
+    let log_file = match config.log_target() {
+        Target::File => Some(config.log_file()),
+        _ => None,
+    };
+    let log_file = log_file.as_deref();
+    if let (Some(log_file), false) = (log_file, should_daemonize) {
+        daemon::redirect_output(log_file)?;
+    }
 
     // Show uname info and return the Linux version running
     {
-        let ensure_msg = "Ensure you're running at least Linux 4.20";
         let uname = Uname::new()?;
         uname.print_info()?;
 
         match uname.parse_version() {
-            Ok(version) => {
-                if version < LINUX_4_20 {
-                    eprintln!(
-                        "{version} does not meet minimum requirements for bustd!\n{ensure_msg}"
-                    );
-                    return Err(Error::InvalidLinuxVersion);
-                }
-            }
-            Err(_) => {
-                eprintln!("Failed to parse Linux version!\n{ensure_msg}");
-            }
-        }
-
-        if let Ok(version) = uname.parse_version() {
-            if version < LINUX_4_20 {
-                eprintln!("{version} does not meet minimum requirements for bustd!\n{ensure_msg}");
+            Ok(version) if version < LINUX_4_20 => {
+                log::emit(&Event::UnsupportedKernel {
+                    release: &version.to_string(),
+                });
                 return Err(Error::InvalidLinuxVersion);
             }
-        } else {
-            eprintln!("Failed to parse Linux version!\n{ensure_msg}");
+            Ok(_) => {}
+            Err(err) => log::emit(&Event::Error {
+                message: "Failed to parse Linux version! Ensure you're running at least Linux 4.20",
+                error: &err,
+            }),
         }
     };
 
     // In order to correctly use `mlockall`, we'll try our best to avoid heap allocations and
     // reuse these buffers right here, even though it makes the code less readable.
     // Buffer specific to process creation
-    let proc_buf = [0_u8; 50];
+    let proc_buf = [0_u8; 128];
 
     // Buffer for anything else
-    let buf = [0_u8; 100];
+    let buf = [0_u8; 256];
 
     if should_daemonize {
         // Daemonize current process
-        println!("\nStarting daemonization process!");
-        daemon::daemonize()?;
+        log::emit(&Event::Daemonizing);
+        daemon::daemonize(log_file)?;
     }
 
     // Attempt to lock the memory pages mapped to the daemon
     // in order to avoid being sent to swap when the system
     // memory is stressed
-    if let Err(err) = lock_memory_pages() {
-        eprintln!("Failed to lock memory pages: {:?}. Continuing anyway.", err);
-    } else {
-        // Save this on both bustd.out and bustd.err
-        println!("Memory pages locked!");
-        eprintln!("Memory pages locked!");
+    match lock_memory_pages() {
+        Ok(()) => log::emit(&Event::MemoryLocked),
+        Err(err) => log::emit(&Event::Error {
+            message: "Failed to lock memory pages, continuing anyway",
+            error: &err,
+        }),
     }
 
-    Monitor::new(proc_buf, buf, args)?.poll()
+    signals::install_handlers()?;
+
+    Monitor::new(proc_buf, buf, config, args)?.poll()
 }
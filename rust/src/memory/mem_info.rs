@@ -1,10 +1,16 @@
-use std::{fmt, mem};
+use std::{
+    fmt,
+    io::{Read, Write},
+    mem,
+    path::Path,
+    str::FromStr,
+};
 
 use libc::sysinfo;
 
 use crate::{
     error::{Error, Result},
-    utils::bytes_to_megabytes,
+    utils::{self, bytes_to_megabytes, str_from_bytes},
 };
 
 #[derive(Debug, Default)]
@@ -17,6 +23,46 @@ pub struct MemoryInfo {
     pub available_swap_percent: u8,
 }
 
+/// Where the amount of available memory is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemorySource {
+    /// `freeram` as given by the sysinfo syscall. The cheapest source, but it
+    /// does not account for reclaimable memory such as the page cache
+    SysInfo,
+    /// `MemAvailable` as given by `/proc/meminfo`, the kernel's own estimate
+    MemAvailable,
+    /// `MemFree + Cached + SReclaimable - Shmem`, as given by `/proc/meminfo`
+    Custom,
+}
+
+impl FromStr for MemorySource {
+    type Err = String;
+
+    fn from_str(source: &str) -> std::result::Result<Self, Self::Err> {
+        match source {
+            "sysinfo" => Ok(Self::SysInfo),
+            "meminfo" => Ok(Self::MemAvailable),
+            "custom" => Ok(Self::Custom),
+            _ => Err(format!(
+                "unknown memory source \"{}\", expected sysinfo, meminfo or custom",
+                source
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MemorySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::SysInfo => "sysinfo",
+            Self::MemAvailable => "meminfo",
+            Self::Custom => "custom",
+        };
+
+        f.write_str(name)
+    }
+}
+
 /// Simple wrapper over libc's sysinfo
 fn sys_info() -> Result<sysinfo> {
     // Safety: the all-zero byte pattern is a valid sysinfo struct
@@ -36,8 +82,89 @@ fn sys_info() -> Result<sysinfo> {
     Ok(sys_info)
 }
 
+/// The fields of `/proc/meminfo` we care about, in KiB
+#[derive(Debug, Default, PartialEq)]
+struct MemInfoFile {
+    mem_total: u64,
+    mem_free: u64,
+    mem_available: u64,
+    cached: u64,
+    s_reclaimable: u64,
+    shmem: u64,
+    swap_total: u64,
+    swap_free: u64,
+}
+
+impl MemInfoFile {
+    fn read(proc_root: &Path) -> Result<Self> {
+        // `/proc/meminfo` is usually around 1.5 KiB long
+        let mut buf = [0_u8; 4096];
+        write!(&mut buf[..], "{}/meminfo\0", proc_root.display())?;
+        let mut file = utils::file_from_buffer(&buf)?;
+        buf.fill(0);
+
+        let mut read = 0;
+        while read < buf.len() {
+            match file.read(&mut buf[read..])? {
+                0 => break,
+                bytes => read += bytes,
+            }
+        }
+
+        Self::parse(str_from_bytes(&buf[..read])?)
+    }
+
+    /// Parses lines of the form `MemAvailable:    5667636 kB`
+    fn parse(contents: &str) -> Result<Self> {
+        let mut meminfo = Self::default();
+        let mut found_available = false;
+
+        for line in contents.lines() {
+            let (name, value) = match line.split_once(':') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let field = match name {
+                "MemTotal" => &mut meminfo.mem_total,
+                "MemFree" => &mut meminfo.mem_free,
+                "MemAvailable" => {
+                    found_available = true;
+                    &mut meminfo.mem_available
+                }
+                "Cached" => &mut meminfo.cached,
+                "SReclaimable" => &mut meminfo.s_reclaimable,
+                "Shmem" => &mut meminfo.shmem,
+                "SwapTotal" => &mut meminfo.swap_total,
+                "SwapFree" => &mut meminfo.swap_free,
+                _ => continue,
+            };
+
+            let value = value.trim().trim_end_matches("kB").trim();
+            *field = value.parse()?;
+        }
+
+        // Present since Linux 3.14, so a missing value means we read something else entirely
+        if meminfo.mem_total == 0 || !found_available {
+            return Err(Error::MalformedMeminfo);
+        }
+
+        Ok(meminfo)
+    }
+}
+
 impl MemoryInfo {
-    pub fn new() -> Result<MemoryInfo> {
+    /// Reads the available memory from the given source. `/proc/meminfo` is read from
+    /// the procfs mounted at `proc_root`, while sysinfo always reports on the running kernel.
+    pub fn new(source: MemorySource, proc_root: &Path) -> Result<MemoryInfo> {
+        match source {
+            MemorySource::SysInfo => Self::from_sysinfo(),
+            MemorySource::MemAvailable | MemorySource::Custom => {
+                Ok(Self::from_meminfo(&MemInfoFile::read(proc_root)?, source))
+            }
+        }
+    }
+
+    fn from_sysinfo() -> Result<MemoryInfo> {
         let sysinfo {
             mem_unit,
             freeram,
@@ -47,12 +174,37 @@ impl MemoryInfo {
             ..
         } = sys_info()?;
 
-        let ratio = |x, y| ((x as f32 / y as f32) * 100.0) as u8;
+        Ok(Self::from_megabytes(
+            bytes_to_megabytes(totalram, mem_unit),
+            bytes_to_megabytes(freeram, mem_unit),
+            bytes_to_megabytes(totalswap, mem_unit),
+            bytes_to_megabytes(freeswap, mem_unit),
+        ))
+    }
+
+    fn from_meminfo(meminfo: &MemInfoFile, source: MemorySource) -> MemoryInfo {
+        let available_ram_kib = match source {
+            MemorySource::Custom => (meminfo.mem_free + meminfo.cached + meminfo.s_reclaimable)
+                .saturating_sub(meminfo.shmem),
+            _ => meminfo.mem_available,
+        };
+        let kib_to_megabytes = |kib: u64| bytes_to_megabytes(kib * 1024, 1_u64);
 
-        let available_ram_mb = bytes_to_megabytes(freeram, mem_unit);
-        let total_ram_mb = bytes_to_megabytes(totalram, mem_unit);
-        let total_swap_mb = bytes_to_megabytes(totalswap, mem_unit);
-        let available_swap_mb = bytes_to_megabytes(freeswap, mem_unit);
+        Self::from_megabytes(
+            kib_to_megabytes(meminfo.mem_total),
+            kib_to_megabytes(available_ram_kib),
+            kib_to_megabytes(meminfo.swap_total),
+            kib_to_megabytes(meminfo.swap_free),
+        )
+    }
+
+    fn from_megabytes(
+        total_ram_mb: u64,
+        available_ram_mb: u64,
+        total_swap_mb: u64,
+        available_swap_mb: u64,
+    ) -> MemoryInfo {
+        let ratio = |x, y| ((x as f32 / y as f32) * 100.0) as u8;
 
         let available_ram_percent = ratio(available_ram_mb, total_ram_mb);
         let available_swap_percent = if total_swap_mb != 0 {
@@ -61,14 +213,14 @@ impl MemoryInfo {
             0
         };
 
-        Ok(MemoryInfo {
+        MemoryInfo {
             total_ram_mb,
             available_ram_mb,
             total_swap_mb,
             available_swap_mb,
             available_ram_percent,
             available_swap_percent,
-        })
+        }
     }
 }
 
@@ -88,3 +240,74 @@ impl fmt::Display for MemoryInfo {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{MemInfoFile, MemoryInfo, MemorySource};
+
+    const MEMINFO: &str = "MemTotal:        8000000 kB
+MemFree:         1000000 kB
+MemAvailable:    4000000 kB
+Buffers:           60100 kB
+Cached:          2000000 kB
+SwapCached:            0 kB
+SwapTotal:       2000000 kB
+SwapFree:        1000000 kB
+Shmem:            500000 kB
+SReclaimable:     250000 kB
+HugePages_Total:       0
+";
+
+    #[test]
+    fn should_parse_meminfo() {
+        let meminfo = MemInfoFile::parse(MEMINFO).unwrap();
+
+        assert_eq!(
+            meminfo,
+            MemInfoFile {
+                mem_total: 8_000_000,
+                mem_free: 1_000_000,
+                mem_available: 4_000_000,
+                cached: 2_000_000,
+                s_reclaimable: 250_000,
+                shmem: 500_000,
+                swap_total: 2_000_000,
+                swap_free: 1_000_000,
+            }
+        );
+
+        assert!(MemInfoFile::parse("MemTotal: 100 kB").is_err());
+    }
+
+    #[test]
+    fn memory_sources_should_differ_in_available_ram() {
+        let meminfo = MemInfoFile::parse(MEMINFO).unwrap();
+
+        let available = MemoryInfo::from_meminfo(&meminfo, MemorySource::MemAvailable);
+        assert_eq!(available.total_ram_mb, 8192);
+        assert_eq!(available.available_ram_mb, 4096);
+        assert_eq!(available.available_ram_percent, 50);
+        assert_eq!(available.available_swap_percent, 50);
+
+        // 1000000 + 2000000 + 250000 - 500000 KiB
+        let custom = MemoryInfo::from_meminfo(&meminfo, MemorySource::Custom);
+        assert_eq!(custom.available_ram_mb, 2816);
+        assert_eq!(custom.available_ram_percent, 34);
+    }
+
+    #[test]
+    fn should_read_every_memory_source() {
+        for source in [
+            MemorySource::SysInfo,
+            MemorySource::MemAvailable,
+            MemorySource::Custom,
+        ] {
+            let memory_info = MemoryInfo::new(source, Path::new("/proc")).unwrap();
+            assert!(memory_info.total_ram_mb > 0);
+            assert!(memory_info.available_ram_mb <= memory_info.total_ram_mb);
+            assert_eq!(source.to_string().parse(), Ok(source));
+        }
+    }
+}
@@ -4,6 +4,7 @@ use libc::{MCL_CURRENT, MCL_FUTURE};
 
 use crate::errno::errno;
 use crate::error::{Error, Result};
+use crate::log::{self, Event};
 
 extern "C" {
     pub static _MCL_ONFAULT: libc::c_int;
@@ -40,7 +41,10 @@ pub fn lock_memory_pages() -> Result<()> {
     let MCL_ONFAULT: c_int = unsafe { _MCL_ONFAULT };
     match _mlockall_wrapper(MCL_CURRENT | MCL_FUTURE | MCL_ONFAULT) {
         Err(err) => {
-            eprintln!("First try at mlockall failed: {:?}", err);
+            log::emit(&Event::Error {
+                message: "First try at mlockall failed",
+                error: &err,
+            });
         }
         Ok(_) => return Ok(()),
     }
@@ -2,5 +2,5 @@ mod mem_info;
 mod mem_lock;
 pub mod pressure;
 
-pub use mem_info::MemoryInfo;
+pub use mem_info::{MemoryInfo, MemorySource};
 pub use mem_lock::lock_memory_pages;
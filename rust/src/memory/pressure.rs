@@ -1,8 +1,14 @@
-use std::fs::File;
-use std::io::Read;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use libc::{pollfd, O_NONBLOCK, POLLERR, POLLPRI};
 
 use crate::error::{Error, Result};
-use crate::utils::str_from_bytes;
+use crate::utils::{self, str_from_bytes};
 
 macro_rules! malformed {
     () => {
@@ -10,36 +16,272 @@ macro_rules! malformed {
     };
 }
 
-/// Returns the avg10 value in the `some` row of `/proc/pressure/memory`, which
-/// indicates the absolute stall time (in us) in which at least some tasks were stalled.
+/// One of the rows of `/proc/pressure/memory`.
 ///
-/// The data we're reading looks like:
-/// ```some avg10=0.00 avg60=0.00 avg300=0.00 total=0```
+/// The averages are the percentages of time, over the last ten, sixty and three hundred seconds,
+/// in which tasks were stalled waiting for memory, while `total` is the absolute stall time, in us.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PressureLine {
+    pub avg10: f32,
+    pub avg60: f32,
+    pub avg300: f32,
+    pub total: u64,
+}
+
+impl PressureLine {
+    /// Parses the fields that follow the `some` or `full` indicator, of the form
+    /// ```avg10=0.00 avg60=0.00 avg300=0.00 total=0```
+    fn parse<'a>(fields: impl Iterator<Item = &'a str>) -> Result<Self> {
+        let mut line = Self::default();
+
+        for field in fields {
+            // We'll break each field in two in order to parse the value on the right-hand side
+            let (name, value) = field.split_once('=').ok_or(malformed!())?;
+            match name {
+                "avg10" => line.avg10 = value.parse()?,
+                "avg60" => line.avg60 = value.parse()?,
+                "avg300" => line.avg300 = value.parse()?,
+                "total" => line.total = value.parse()?,
+                // Ignore fields that newer kernels might add
+                _ => {}
+            }
+        }
+
+        Ok(line)
+    }
+}
+
+/// The full contents of `/proc/pressure/memory`, which look like:
+/// ```text
+/// some avg10=0.00 avg60=0.00 avg300=0.00 total=11220657
+/// full avg10=0.00 avg60=0.00 avg300=0.00 total=10947429
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PressureSnapshot {
+    /// Time in which at least some tasks were stalled
+    pub some: PressureLine,
+    /// Time in which all non-idle tasks were stalled simultaneously
+    pub full: PressureLine,
+}
+
+impl PressureSnapshot {
+    /// Reads `pressure/memory` from the procfs mounted at `proc_root`
+    pub fn read(proc_root: &Path) -> Result<Self> {
+        // Large enough to fit both lines even with every value maxed out
+        let mut buf = [0_u8; 256];
+        write!(&mut buf[..], "{}/pressure/memory\0", proc_root.display())?;
+        let mut file = utils::file_from_buffer(&buf)?;
+        buf.fill(0);
+
+        let mut read = 0;
+        while read < buf.len() {
+            match file.read(&mut buf[read..])? {
+                0 => break,
+                bytes => read += bytes,
+            }
+        }
+
+        Self::parse(str_from_bytes(&buf[..read])?)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut some = None;
+        let mut full = None;
+
+        for line in contents.lines() {
+            let mut words = line.split_ascii_whitespace();
+            match words.next() {
+                Some("some") => some = Some(PressureLine::parse(words)?),
+                Some("full") => full = Some(PressureLine::parse(words)?),
+                _ => return Err(malformed!()),
+            }
+        }
+
+        Ok(Self {
+            some: some.ok_or(malformed!())?,
+            full: full.ok_or(malformed!())?,
+        })
+    }
+}
+
+/// How many samples `PressureWindow` keeps around
+const WINDOW_SAMPLES: usize = 64;
+
+/// Measures the percentage of time in which some tasks were stalled over a short window,
+/// using the cumulative `total` counter of the `some` row.
+///
+/// The kernel's `avg10` lags by several seconds, which is too slow to catch processes
+/// that allocate very quickly. Sampling the counter on every iteration lets us compute the
+/// stall percentage over the actual time elapsed between samples instead, which spans between
+/// one and two window lengths as long as samples are taken at least once per window length.
+pub struct PressureWindow {
+    /// Ring buffer of `(some total, time of sampling)`
+    samples: [Option<(u64, Instant)>; WINDOW_SAMPLES],
+    next: usize,
+}
+
+impl PressureWindow {
+    pub fn new() -> Self {
+        Self {
+            samples: [None; WINDOW_SAMPLES],
+            next: 0,
+        }
+    }
+
+    /// Records a new sample of the `some total` counter and returns the stall percentage over
+    /// (at least) the last `length`, or over the longest interval we have samples for, if shorter.
+    /// Returns `None` until there are at least two samples.
+    pub fn sample(&mut self, total: u64, now: Instant, length: Duration) -> Option<f32> {
+        let samples = self.samples.iter().flatten();
+
+        // The newest sample that is at least `length` old, or otherwise the oldest one we have
+        let (old_total, then) = samples
+            .clone()
+            .filter(|(_, then)| now.saturating_duration_since(*then) >= length)
+            .max_by_key(|(_, then)| *then)
+            .or_else(|| samples.min_by_key(|(_, then)| *then))
+            .copied()
+            .unzip();
+
+        self.samples[self.next] = Some((total, now));
+        self.next = (self.next + 1) % WINDOW_SAMPLES;
+
+        let elapsed_us = now.saturating_duration_since(then?).as_micros();
+        if elapsed_us == 0 {
+            return None;
+        }
+
+        // The counter is in microseconds
+        let stalled_us = total.saturating_sub(old_total?);
+        let percent = stalled_us as f64 / elapsed_us as f64 * 100.0;
+
+        Some(percent.min(100.0) as f32)
+    }
+}
+
+/// A PSI trigger registered on `/proc/pressure/memory`, which lets the kernel
+/// notify us whenever tasks stall for longer than `stall` within a `window`,
+/// instead of us having to check the pressure file every so often.
 ///
-pub fn pressure_some_avg10(buf: &mut [u8]) -> Result<f32> {
-    let mut file = File::open("/proc/pressure/memory")?;
-    buf.fill(0);
+/// See https://www.kernel.org/doc/html/latest/accounting/psi.html#monitoring-for-pressure-thresholds
+pub struct PressureTrigger {
+    /// The trigger stays registered for as long as this file is open
+    file: File,
+}
 
-    // `buf` won't be large enough to fit all of `/proc/pressure/memory`
-    // but will be large enough to hold at least the first line, which has the data we want
-    file.read(buf)?;
-    let contents = str_from_bytes(buf)?;
+impl PressureTrigger {
+    pub fn new(proc_root: &Path, stall: Duration, window: Duration) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(O_NONBLOCK)
+            .open(proc_root.join("pressure/memory"))?;
 
-    let line = contents.lines().next().ok_or(malformed!())?;
-    let mut words = line.split_ascii_whitespace();
-    if let Some(indicator) = words.next() {
-        // This has to be the case but checking to be sure
-        if indicator == "some" {
-            let entry = words.next().ok_or(malformed!())?;
+        // The trigger is written as `<some|full> <stall amount in us> <time window in us>`.
+        // The kernel expects it in a single write, so we format it into a buffer first
+        let mut buf = [0_u8; 64];
+        let len = {
+            let mut cursor = &mut buf[..];
+            write!(
+                cursor,
+                "some {} {}\0",
+                stall.as_micros(),
+                window.as_micros()
+            )?;
+            64 - cursor.len()
+        };
+        file.write_all(&buf[..len])?;
+
+        Ok(Self { file })
+    }
 
-            // The entry is of the form `avg10=0.00`
-            // We'll break this string in two in order to parse the value on the right-hand side
-            let equals_pos = entry.find('=').ok_or(malformed!())?;
-            let avg10 = entry.get(equals_pos + 1..).ok_or(malformed!())?;
-            let avg10: f32 = avg10.trim().parse()?;
-            return Ok(avg10);
+    /// The pollfd to pass to poll(2) in order to wait for the trigger to fire
+    pub fn pollfd(&self) -> pollfd {
+        pollfd {
+            fd: self.file.as_raw_fd(),
+            events: POLLPRI,
+            revents: 0,
         }
     }
 
-    Err(malformed!())
+    /// Given the pollfd returned by `pollfd` after poll(2) returned,
+    /// returns true if the trigger fired
+    pub fn fired(fd: &pollfd) -> Result<bool> {
+        if fd.revents & POLLERR != 0 {
+            // The kernel no longer monitors this trigger
+            return Err(Error::PressureTriggerLost);
+        }
+
+        Ok(fd.revents & POLLPRI != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use super::{PressureLine, PressureSnapshot, PressureWindow};
+
+    #[test]
+    fn should_parse_both_pressure_lines() {
+        let snapshot = PressureSnapshot::parse(
+            "some avg10=1.50 avg60=0.75 avg300=0.10 total=11220657\n\
+             full avg10=0.25 avg60=0.00 avg300=0.00 total=10947429\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            snapshot,
+            PressureSnapshot {
+                some: PressureLine {
+                    avg10: 1.5,
+                    avg60: 0.75,
+                    avg300: 0.1,
+                    total: 11220657
+                },
+                full: PressureLine {
+                    avg10: 0.25,
+                    avg60: 0.0,
+                    avg300: 0.0,
+                    total: 10947429
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn should_reject_malformed_pressure_files() {
+        assert!(PressureSnapshot::parse("").is_err());
+        assert!(PressureSnapshot::parse("some avg10=0.00 total=1").is_err());
+        assert!(PressureSnapshot::parse("some avg10=abc\nfull avg10=0.00").is_err());
+        assert!(PressureSnapshot::parse("half avg10=0.00").is_err());
+    }
+
+    #[test]
+    fn should_measure_pressure_over_short_windows() {
+        let mut window = PressureWindow::new();
+        let length = Duration::from_millis(500);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(window.sample(1_000, at(0), length), None);
+        // 50ms stalled in the last 100ms
+        assert_eq!(window.sample(51_000, at(100), length), Some(50.0));
+        // Only 200ms worth of samples, so we measure over all of them: 100ms out of 200ms
+        assert_eq!(window.sample(101_000, at(200), length), Some(50.0));
+        // No stalls for a whole second, measured against the sample taken 500ms ago
+        assert_eq!(window.sample(101_000, at(700), length), Some(0.0));
+        assert_eq!(window.sample(101_000, at(1200), length), Some(0.0));
+        // 250ms out of the last 500ms
+        assert_eq!(window.sample(351_000, at(1700), length), Some(50.0));
+    }
+
+    #[test]
+    fn should_read_the_pressure_file() {
+        // Not every kernel in which these tests run has PSI enabled
+        if Path::new("/proc/pressure/memory").exists() {
+            assert!(PressureSnapshot::read(Path::new("/proc")).is_ok());
+        }
+    }
 }
@@ -0,0 +1,443 @@
+//! Prometheus metrics about what bustd sees and does, served over HTTP by [`Server`].
+//!
+//! Counters are kept in statics so that they can be updated from wherever things happen,
+//! while gauges are taken from the monitor's latest readings whenever metrics are scraped.
+//!
+//! See https://prometheus.io/docs/instrumenting/exposition_formats/
+
+mod server;
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub use self::server::{Address, Server};
+use crate::config::SIGNALS;
+use crate::error::Error;
+use crate::hooks::KillResult;
+use crate::memory::pressure::{PressureLine, PressureSnapshot};
+use crate::memory::MemoryInfo;
+
+const OUTCOMES: [KillResult; 3] = [KillResult::Killed, KillResult::Survived, KillResult::Failed];
+
+/// Upper bounds of the victim selection histogram buckets, in seconds
+const SELECTION_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+/// More than there are `Error` variants
+const MAX_ERROR_VARIANTS: usize = 32;
+
+/// Indexed by signal, then by outcome
+static KILLS: [[AtomicU64; OUTCOMES.len()]; SIGNALS.len()] =
+    [const { [const { AtomicU64::new(0) }; OUTCOMES.len()] }; SIGNALS.len()];
+
+static ESCALATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Selections that took no longer than the matching bucket's bound, but longer than the
+/// previous one's. The last one counts selections slower than every bound.
+static SELECTION_COUNTS: [AtomicU64; SELECTION_BUCKETS.len() + 1] =
+    [const { AtomicU64::new(0) }; SELECTION_BUCKETS.len() + 1];
+
+static SELECTION_SUM_US: AtomicU64 = AtomicU64::new(0);
+
+static MEMORY_RELEASES: Summary = Summary::new();
+
+static MEMORY_RELEASE_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+/// How many times each `Error` variant occurred, in the order they first did.
+/// Unused slots have an empty name.
+static ERRORS: Mutex<[(&str, u64); MAX_ERROR_VARIANTS]> = Mutex::new([("", 0); MAX_ERROR_VARIANTS]);
+
+/// How many durations were observed, and their sum
+struct Summary {
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl Summary {
+    const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Writes the `_sum`, in seconds, and `_count` series of the summary called `name`
+    fn render(&self, out: &mut impl Write, name: &str) -> io::Result<()> {
+        writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            self.sum_us.load(Ordering::Relaxed) as f64 / 1e6
+        )?;
+        writeln!(out, "{}_count {}", name, self.count.load(Ordering::Relaxed))
+    }
+}
+
+/// Counts an attempt at killing a process, labelled by the last signal it was sent
+pub fn record_kill(signal: i32, result: KillResult) {
+    let signal = SIGNALS.iter().position(|(_, number)| *number == signal);
+    let outcome = OUTCOMES.iter().position(|outcome| *outcome == result);
+
+    if let (Some(signal), Some(outcome)) = (signal, outcome) {
+        KILLS[signal][outcome].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn record_escalation() {
+    ESCALATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records how long it took to choose a victim
+pub fn observe_victim_selection(duration: Duration) {
+    SELECTION_COUNTS[selection_bucket(duration)].fetch_add(1, Ordering::Relaxed);
+    SELECTION_SUM_US.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+}
+
+/// Records how long process_mrelease took to reap a victim's memory
+pub fn observe_memory_release(duration: Duration) {
+    MEMORY_RELEASES.observe(duration);
+}
+
+/// Counts a call to process_mrelease that failed, e.g. on kernels older than 5.15
+pub fn record_memory_release_failure() {
+    MEMORY_RELEASE_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The index of the first bucket whose bound isn't below `duration`
+fn selection_bucket(duration: Duration) -> usize {
+    let seconds = duration.as_secs_f64();
+
+    SELECTION_BUCKETS
+        .iter()
+        .position(|bound| seconds <= *bound)
+        .unwrap_or(SELECTION_BUCKETS.len())
+}
+
+pub fn record_error(error: &Error) {
+    let variant = error.variant();
+    let mut errors = ERRORS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if let Some(slot) = errors
+        .iter_mut()
+        .find(|(name, _)| *name == variant || name.is_empty())
+    {
+        *slot = (variant, slot.1 + 1);
+    }
+}
+
+/// The monitor's latest readings
+pub struct Gauges<'a> {
+    pub memory: &'a MemoryInfo,
+    /// Unset if the pressure file could not be read
+    pub psi: Option<PressureSnapshot>,
+    pub window_psi: Option<f32>,
+    pub near_terminal: bool,
+    pub sleep: Duration,
+}
+
+fn describe(out: &mut impl Write, name: &str, kind: &str, help: &str) -> io::Result<()> {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)
+}
+
+/// Writes every metric in the Prometheus text format
+pub fn render(out: &mut impl Write, gauges: &Gauges) -> io::Result<()> {
+    let memory = gauges.memory;
+    let memory_gauges = [
+        (
+            "bustd_ram_total_megabytes",
+            "Total RAM.",
+            memory.total_ram_mb,
+        ),
+        (
+            "bustd_ram_available_megabytes",
+            "Available RAM.",
+            memory.available_ram_mb,
+        ),
+        (
+            "bustd_swap_total_megabytes",
+            "Total swap.",
+            memory.total_swap_mb,
+        ),
+        (
+            "bustd_swap_available_megabytes",
+            "Available swap.",
+            memory.available_swap_mb,
+        ),
+        (
+            "bustd_ram_available_percent",
+            "Available RAM, as a percentage of the total.",
+            memory.available_ram_percent.into(),
+        ),
+        (
+            "bustd_swap_available_percent",
+            "Available swap, as a percentage of the total.",
+            memory.available_swap_percent.into(),
+        ),
+    ];
+    for (name, help, value) in memory_gauges {
+        describe(out, name, "gauge", help)?;
+        writeln!(out, "{} {}", name, value)?;
+    }
+
+    describe(
+        out,
+        "bustd_memory_near_terminal",
+        "gauge",
+        "Whether available RAM is under near_terminal.",
+    )?;
+    writeln!(
+        out,
+        "bustd_memory_near_terminal {}",
+        gauges.near_terminal as u8
+    )?;
+
+    if let Some(psi) = &gauges.psi {
+        let lines: [(&str, &PressureLine); 2] = [("some", &psi.some), ("full", &psi.full)];
+
+        describe(
+            out,
+            "bustd_memory_pressure_percent",
+            "gauge",
+            "Memory PSI averages.",
+        )?;
+        for (kind, line) in lines {
+            for (window, value) in [
+                ("avg10", line.avg10),
+                ("avg60", line.avg60),
+                ("avg300", line.avg300),
+            ] {
+                writeln!(
+                    out,
+                    "bustd_memory_pressure_percent{{kind=\"{}\",window=\"{}\"}} {}",
+                    kind, window, value
+                )?;
+            }
+        }
+
+        describe(
+            out,
+            "bustd_memory_pressure_stall_seconds_total",
+            "counter",
+            "Total time tasks stalled on memory.",
+        )?;
+        for (kind, line) in lines {
+            writeln!(
+                out,
+                "bustd_memory_pressure_stall_seconds_total{{kind=\"{}\"}} {}",
+                kind,
+                line.total as f64 / 1e6
+            )?;
+        }
+    }
+
+    if let Some(window_psi) = gauges.window_psi {
+        describe(
+            out,
+            "bustd_memory_pressure_window_percent",
+            "gauge",
+            "Memory stall percentage over psi_window_length.",
+        )?;
+        writeln!(out, "bustd_memory_pressure_window_percent {}", window_psi)?;
+    }
+
+    describe(
+        out,
+        "bustd_sleep_seconds",
+        "gauge",
+        "The current adaptive sleep time.",
+    )?;
+    writeln!(out, "bustd_sleep_seconds {}", gauges.sleep.as_secs_f64())?;
+
+    describe(
+        out,
+        "bustd_kills_total",
+        "counter",
+        "Attempts at killing a victim, by the last signal sent and outcome.",
+    )?;
+    for (counts, (signal, _)) in KILLS.iter().zip(SIGNALS) {
+        for (count, outcome) in counts.iter().zip(OUTCOMES) {
+            writeln!(
+                out,
+                "bustd_kills_total{{signal=\"SIG{}\",outcome=\"{}\"}} {}",
+                signal,
+                outcome.as_str(),
+                count.load(Ordering::Relaxed)
+            )?;
+        }
+    }
+
+    describe(
+        out,
+        "bustd_kill_escalations_total",
+        "counter",
+        "Victims that had to be sent SIGKILL after ignoring the signals before it.",
+    )?;
+    writeln!(
+        out,
+        "bustd_kill_escalations_total {}",
+        ESCALATIONS.load(Ordering::Relaxed)
+    )?;
+
+    describe(
+        out,
+        "bustd_victim_selection_seconds",
+        "histogram",
+        "Time taken to choose a victim.",
+    )?;
+    let mut cumulative = 0;
+    for (count, bound) in SELECTION_COUNTS.iter().zip(SELECTION_BUCKETS) {
+        cumulative += count.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "bustd_victim_selection_seconds_bucket{{le=\"{}\"}} {}",
+            bound, cumulative
+        )?;
+    }
+    cumulative += SELECTION_COUNTS[SELECTION_BUCKETS.len()].load(Ordering::Relaxed);
+    writeln!(
+        out,
+        "bustd_victim_selection_seconds_bucket{{le=\"+Inf\"}} {}",
+        cumulative
+    )?;
+    writeln!(
+        out,
+        "bustd_victim_selection_seconds_sum {}",
+        SELECTION_SUM_US.load(Ordering::Relaxed) as f64 / 1e6
+    )?;
+    writeln!(out, "bustd_victim_selection_seconds_count {}", cumulative)?;
+
+    describe(
+        out,
+        "bustd_memory_release_seconds",
+        "summary",
+        "Time taken by process_mrelease to reap the memory of victims sent SIGKILL.",
+    )?;
+    MEMORY_RELEASES.render(out, "bustd_memory_release_seconds")?;
+
+    describe(
+        out,
+        "bustd_memory_release_failures_total",
+        "counter",
+        "Calls to process_mrelease that failed, so that victims released their memory by themselves.",
+    )?;
+    writeln!(
+        out,
+        "bustd_memory_release_failures_total {}",
+        MEMORY_RELEASE_FAILURES.load(Ordering::Relaxed)
+    )?;
+
+    describe(
+        out,
+        "bustd_errors_total",
+        "counter",
+        "Errors that occurred, by variant.",
+    )?;
+    let errors = *ERRORS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for (variant, count) in errors.iter().take_while(|(name, _)| !name.is_empty()) {
+        writeln!(
+            out,
+            "bustd_errors_total{{variant=\"{}\"}} {}",
+            variant, count
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{render, selection_bucket, Gauges, Summary};
+    use crate::memory::pressure::{PressureLine, PressureSnapshot};
+    use crate::memory::MemoryInfo;
+
+    #[test]
+    fn should_place_selections_in_buckets() {
+        assert_eq!(selection_bucket(Duration::ZERO), 0);
+        assert_eq!(selection_bucket(Duration::from_millis(1)), 0);
+        assert_eq!(selection_bucket(Duration::from_millis(2)), 1);
+        assert_eq!(selection_bucket(Duration::from_millis(100)), 5);
+        assert_eq!(selection_bucket(Duration::from_secs(3)), 10);
+    }
+
+    #[test]
+    fn should_sum_observed_durations() {
+        let summary = Summary::new();
+        summary.observe(Duration::from_micros(1500));
+        summary.observe(Duration::from_micros(2500));
+
+        let mut out = Vec::new();
+        summary.render(&mut out, "bustd_test_seconds").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "bustd_test_seconds_sum 0.004\nbustd_test_seconds_count 2\n"
+        );
+    }
+
+    #[test]
+    fn should_render_every_pressure_value() {
+        let memory = MemoryInfo {
+            total_ram_mb: 16000,
+            total_swap_mb: 2000,
+            available_ram_mb: 1000,
+            available_swap_mb: 500,
+            available_ram_percent: 6,
+            available_swap_percent: 25,
+        };
+        let psi = PressureSnapshot {
+            some: PressureLine {
+                avg10: 30.5,
+                avg60: 12.25,
+                avg300: 3.0,
+                total: 2_500_000,
+            },
+            ..PressureSnapshot::default()
+        };
+        let gauges = Gauges {
+            memory: &memory,
+            psi: Some(psi),
+            window_psi: Some(40.0),
+            near_terminal: true,
+            sleep: Duration::from_millis(100),
+        };
+
+        let mut buf = [0_u8; 16 * 1024];
+        let mut cursor = &mut buf[..];
+        render(&mut cursor, &gauges).unwrap();
+        let len = 16 * 1024 - cursor.len();
+        let metrics = std::str::from_utf8(&buf[..len]).unwrap();
+
+        for line in [
+            "bustd_swap_available_percent 25",
+            "bustd_memory_near_terminal 1",
+            "bustd_memory_pressure_percent{kind=\"some\",window=\"avg10\"} 30.5",
+            "bustd_memory_pressure_percent{kind=\"some\",window=\"avg60\"} 12.25",
+            "bustd_memory_pressure_percent{kind=\"full\",window=\"avg300\"} 0",
+            "bustd_memory_pressure_stall_seconds_total{kind=\"some\"} 2.5",
+            "bustd_memory_pressure_window_percent 40",
+            "# TYPE bustd_victim_selection_seconds histogram",
+            "# TYPE bustd_memory_release_seconds summary",
+            "# TYPE bustd_memory_release_failures_total counter",
+        ] {
+            assert!(metrics.lines().any(|metric| metric == line), "{}", line);
+        }
+        // Every series has a single, numeric value
+        for metric in metrics.lines().filter(|line| !line.starts_with('#')) {
+            let (_, value) = metric.rsplit_once(' ').unwrap();
+            assert!(value.parse::<f64>().is_ok(), "{}", metric);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! A minimal HTTP server for Prometheus to scrape, listening on TCP or on a Unix socket.
+//!
+//! It never blocks the monitor: the listener is non-blocking and only polled along with
+//! the PSI trigger, and clients get a short timeout to send their request in.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::{render, Gauges};
+use crate::error::Result;
+use crate::utils;
+
+/// How long clients get to send their request and read the response
+const CLIENT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Requests are only read this far, which is plenty for a request line and a few headers
+const MAX_REQUEST_LEN: usize = 1024;
+
+/// Large enough for every metric, even with every error variant having occurred
+const MAX_RESPONSE_LEN: usize = 16 * 1024;
+
+/// Where metrics are served, either `host:port` or `unix:/path/to/socket`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(address: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            return Ok(Self::Unix(path.into()));
+        }
+
+        address.parse().map(Self::Tcp).map_err(|_| {
+            format!(
+                "invalid metrics address \"{}\", expected host:port or unix:/path",
+                address
+            )
+        })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{}", address),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    /// The socket file is removed once the listener is dropped
+    Unix(UnixListener, PathBuf),
+}
+
+pub struct Server {
+    listener: Listener,
+}
+
+impl Server {
+    pub fn bind(address: &Address) -> Result<Self> {
+        let listener = match address {
+            Address::Tcp(address) => {
+                let listener = TcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                Listener::Tcp(listener)
+            }
+            Address::Unix(path) => Listener::Unix(utils::bind_unix_socket(path)?, path.clone()),
+        };
+
+        Ok(Self { listener })
+    }
+
+    /// Answers every pending connection
+    pub fn serve(&self, gauges: &Gauges) -> io::Result<()> {
+        loop {
+            let accepted = match &self.listener {
+                Listener::Tcp(listener) => listener.accept().map(|(stream, _)| {
+                    let _ = stream.set_nonblocking(false);
+                    let _ = stream.set_read_timeout(Some(CLIENT_TIMEOUT));
+                    let _ = stream.set_write_timeout(Some(CLIENT_TIMEOUT));
+                    respond(stream, gauges)
+                }),
+                Listener::Unix(listener, _) => listener.accept().map(|(stream, _)| {
+                    let _ = stream.set_nonblocking(false);
+                    let _ = stream.set_read_timeout(Some(CLIENT_TIMEOUT));
+                    let _ = stream.set_write_timeout(Some(CLIENT_TIMEOUT));
+                    respond(stream, gauges)
+                }),
+            };
+
+            match accepted {
+                // A client that misbehaves or goes away is its own problem
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl AsRawFd for Server {
+    fn as_raw_fd(&self) -> RawFd {
+        match &self.listener {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener, _) => listener.as_raw_fd(),
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = &self.listener {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Reads the request line, ignoring headers, and returns its method and path
+fn read_request<'a>(stream: &mut impl Read, buf: &'a mut [u8]) -> io::Result<(&'a str, &'a str)> {
+    let mut len = 0;
+    while len < buf.len() && !buf[..len].windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buf[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+
+    let request = std::str::from_utf8(&buf[..len]).unwrap_or("");
+    let mut words = request.lines().next().unwrap_or("").split_whitespace();
+
+    Ok((words.next().unwrap_or(""), words.next().unwrap_or("")))
+}
+
+fn respond(mut stream: impl Read + Write, gauges: &Gauges) -> io::Result<()> {
+    let mut request = [0_u8; MAX_REQUEST_LEN];
+    let (method, path) = read_request(&mut stream, &mut request)?;
+
+    let mut body = [0_u8; MAX_RESPONSE_LEN];
+    let (status, body_len) = match (method, path) {
+        ("GET" | "HEAD", "/metrics") => {
+            let mut cursor = &mut body[..];
+            match render(&mut cursor, gauges) {
+                Ok(()) => ("200 OK", MAX_RESPONSE_LEN - cursor.len()),
+                Err(_) => ("500 Internal Server Error", 0),
+            }
+        }
+        ("GET" | "HEAD", _) => ("404 Not Found", 0),
+        _ => ("405 Method Not Allowed", 0),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        status, body_len
+    )?;
+    if method != "HEAD" {
+        stream.write_all(&body[..body_len])?;
+    }
+
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{respond, Address};
+    use crate::memory::MemoryInfo;
+    use crate::metrics::Gauges;
+
+    fn request(request: &'static str) -> String {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let memory = MemoryInfo {
+            total_ram_mb: 16000,
+            total_swap_mb: 0,
+            available_ram_mb: 4000,
+            available_swap_mb: 0,
+            available_ram_percent: 25,
+            available_swap_percent: 0,
+        };
+
+        let client = thread::spawn(move || {
+            client.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        });
+        let gauges = Gauges {
+            memory: &memory,
+            psi: None,
+            window_psi: None,
+            near_terminal: false,
+            sleep: Duration::from_millis(250),
+        };
+        respond(server, &gauges).unwrap();
+
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn should_serve_metrics() {
+        let response = request("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert!(body.contains("\nbustd_ram_available_megabytes 4000\n"));
+        assert!(body.contains("\nbustd_sleep_seconds 0.25\n"));
+        assert!(!body.contains("bustd_memory_pressure_percent"));
+
+        assert!(request("GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(request("POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+        assert!(request("HEAD /metrics HTTP/1.1\r\n\r\n").ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn should_parse_addresses() {
+        assert_eq!(
+            "127.0.0.1:9700".parse::<Address>().unwrap().to_string(),
+            "127.0.0.1:9700"
+        );
+        assert_eq!(
+            "unix:/run/bustd/metrics.sock".parse::<Address>(),
+            Ok(Address::Unix("/run/bustd/metrics.sock".into()))
+        );
+        assert!("localhost".parse::<Address>().is_err());
+    }
+}
@@ -0,0 +1,1042 @@
+mod requests;
+
+use std::collections::VecDeque;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use libc::{pollfd, POLLIN};
+
+use crate::cli::CommandLineArgs;
+use crate::config::{Config, SignalLadder};
+use crate::control;
+use crate::daemon;
+use crate::error::{Error, Result};
+use crate::hooks::{Hook, KillResult};
+use crate::kill::{self, Victim};
+use crate::log::{self, Event, Target, Timestamp};
+use crate::memory::pressure::{PressureSnapshot, PressureTrigger, PressureWindow};
+use crate::memory::MemoryInfo;
+use crate::metrics;
+use crate::notify::{self, Notification, Recipient};
+use crate::signals;
+use crate::utils;
+
+/// How many kills are kept around to be reported through the control socket
+const KILL_HISTORY_LEN: usize = 16;
+
+/// How many times a victim is chosen before giving up, if each one exits before it's held on to
+const VICTIM_ATTEMPTS: u32 = 3;
+
+/// Longest the kill is held off by the `pre_kill` hook
+const PRE_KILL_WAIT: Duration = Duration::from_millis(250);
+
+enum MemoryStatus {
+    NearTerminal(PressureSnapshot),
+    Okay,
+}
+
+/// Checks if the available RAM is under the configured `near_terminal` threshold,
+/// in which case PSI must be checked
+fn is_near_terminal(memory_info: &MemoryInfo, config: &Config) -> bool {
+    memory_info.available_ram_mb as f64 <= config.near_terminal.in_mb(memory_info.total_ram_mb)
+}
+
+/// Logs an error and counts it in the metrics
+fn report_error(message: &str, err: &Error) {
+    metrics::record_error(err);
+    log::emit(&Event::Error {
+        message,
+        error: err,
+    });
+}
+
+/// Starts serving metrics if enabled, logging why we couldn't otherwise
+fn bind_metrics(config: &Config) -> Option<metrics::Server> {
+    let address = config.metrics_listen.as_ref()?;
+
+    match metrics::Server::bind(address) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            report_error("Could not serve metrics", &err);
+            None
+        }
+    }
+}
+
+/// Starts listening on the control socket if enabled, logging why we couldn't otherwise
+fn bind_control(config: &Config) -> Option<control::Server> {
+    if !config.control {
+        return None;
+    }
+
+    match control::Server::bind(&config.control_socket(), config.control_group.as_deref()) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            report_error("Could not listen on the control socket", &err);
+            None
+        }
+    }
+}
+
+/// Registers a PSI trigger if enabled, logging why we'll fall back to adaptive sleep otherwise
+fn register_trigger(config: &Config) -> Option<PressureTrigger> {
+    if !config.psi_trigger {
+        return None;
+    }
+
+    match PressureTrigger::new(
+        &config.proc_root,
+        config.psi_trigger_stall,
+        config.psi_trigger_window,
+    ) {
+        Ok(trigger) => Some(trigger),
+        Err(err) => {
+            report_error(
+                "Could not register a PSI trigger, falling back to adaptive sleep",
+                &err,
+            );
+            None
+        }
+    }
+}
+
+/// A victim that was killed, or would have been in dry-run mode
+struct KillRecord {
+    timestamp: Timestamp,
+    pid: u32,
+    comm: String,
+    oom_score: i16,
+    rss_kib: i64,
+    /// How the kill went, along with the last signal sent. Unset in dry-run mode.
+    outcome: Option<(KillResult, i32)>,
+}
+
+/// What checking memory led to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    Nothing,
+    /// Memory is low, but bustd was paused
+    Paused,
+    /// Memory is low, but a victim was chosen too recently
+    Cooldown,
+    /// The owner of the would-be victim was warned
+    Warned,
+    /// A victim was killed, or would have been in dry-run mode
+    Kill,
+}
+
+impl Decision {
+    fn as_str(self) -> &'static str {
+        match self {
+            Decision::Nothing => "none",
+            Decision::Paused => "paused",
+            Decision::Cooldown => "cooldown",
+            Decision::Warned => "warned",
+            Decision::Kill => "kill",
+        }
+    }
+}
+
+pub struct Monitor {
+    memory_info: MemoryInfo,
+    proc_buf: [u8; 128],
+    buf: [u8; 256],
+    status: MemoryStatus,
+    config: Config,
+    /// Short-window pressure, sampled on every iteration if `cutoff_psi_window` is set
+    pressure_window: PressureWindow,
+    window_psi: Option<f32>,
+    /// Set if we're waiting on kernel PSI triggers rather than sleeping
+    trigger: Option<PressureTrigger>,
+    /// Set if metrics are to be served
+    metrics: Option<metrics::Server>,
+    /// Set if listening on the control socket
+    control: Option<control::Server>,
+    /// Control clients that haven't finished sending their request
+    clients: Vec<control::Connection>,
+    /// When the monitor started
+    started: Instant,
+    /// Set while paused through the control socket, until the given time
+    paused_until: Option<Instant>,
+    /// The latest kills, oldest first
+    kills: VecDeque<KillRecord>,
+    /// The latest adaptive sleep time
+    sleep_time: Duration,
+    /// Kept around so that command-line overrides survive configuration reloads
+    args: CommandLineArgs,
+    /// When the last victim was killed, or would have been killed in dry-run mode
+    last_kill: Option<Instant>,
+    /// When the owner of the would-be victim was last warned about it
+    last_warning: Option<Instant>,
+    /// Hooks that are still running, in the background
+    hooks: Vec<Hook>,
+}
+
+impl Monitor {
+    /// Determines how much oomf should sleep
+    /// This function is essentially a copy of how earlyoom calculates its sleep time
+    ///
+    /// Credits: https://github.com/rfjakob/earlyoom/blob/dea92ae67997fcb1a0664489c13d49d09d472d40/main.c#L365
+    /// MIT Licensed
+    pub fn sleep_time_ms(&self) -> Duration {
+        let config = &self.config;
+        let memory_info = &self.memory_info;
+
+        // How far away we are from the terminal marks, in KiB
+        let ram_headroom_kib = (memory_info.available_ram_mb as f64
+            - config.ram_terminal.in_mb(memory_info.total_ram_mb))
+            * 1000.0;
+        let swap_headroom_kib = (memory_info.available_swap_mb as f64
+            - config.swap_terminal.in_mb(memory_info.total_swap_mb))
+            * 1000.0;
+
+        let ram_headroom_kib = f64::max(ram_headroom_kib, 0.0);
+        let swap_headroom_kib = f64::max(swap_headroom_kib, 0.0);
+
+        // Fill rates are configured per second, and we want them in KiB per ms
+        let ram_fill_rate = config.ram_fill_rate.kib() / 1000.0;
+        let swap_fill_rate = config.swap_fill_rate.kib() / 1000.0;
+
+        let time_to_sleep = Duration::from_millis(
+            (ram_headroom_kib / ram_fill_rate + swap_headroom_kib / swap_fill_rate) as u64,
+        );
+
+        time_to_sleep.clamp(config.min_sleep, config.max_sleep)
+    }
+
+    pub fn new(
+        proc_buf: [u8; 128],
+        buf: [u8; 256],
+        config: Config,
+        args: CommandLineArgs,
+    ) -> Result<Self> {
+        let memory_info = MemoryInfo::new(config.memory_source, &config.proc_root)?;
+        let status = if is_near_terminal(&memory_info, &config) {
+            log::emit(&Event::NearTerminal {
+                available_ram_mb: memory_info.available_ram_mb,
+                available_ram_percent: memory_info.available_ram_percent,
+            });
+            MemoryStatus::NearTerminal(PressureSnapshot::read(&config.proc_root)?)
+        } else {
+            MemoryStatus::Okay
+        };
+
+        Ok(Self {
+            memory_info,
+            proc_buf,
+            buf,
+            status,
+            pressure_window: PressureWindow::new(),
+            window_psi: None,
+            trigger: register_trigger(&config),
+            metrics: bind_metrics(&config),
+            control: bind_control(&config),
+            clients: Vec::with_capacity(control::MAX_CLIENTS),
+            started: Instant::now(),
+            paused_until: None,
+            kills: VecDeque::with_capacity(KILL_HISTORY_LEN),
+            sleep_time: config.min_sleep,
+            config,
+            args,
+            last_kill: None,
+            last_warning: None,
+            hooks: Vec::new(),
+        })
+    }
+
+    fn memory_is_low(&self) -> bool {
+        let psi = match &self.status {
+            MemoryStatus::NearTerminal(psi) => psi,
+            MemoryStatus::Okay => return false,
+        };
+        // Unset cutoffs are never exceeded
+        let exceeds = |value: Option<f32>, cutoff: Option<f32>| match (value, cutoff) {
+            (Some(value), Some(cutoff)) => value >= cutoff,
+            _ => false,
+        };
+
+        exceeds(Some(psi.some.avg10), Some(self.config.cutoff_psi))
+            || exceeds(Some(psi.full.avg10), self.config.cutoff_psi_full)
+            || exceeds(Some(psi.some.avg60), self.config.cutoff_psi_avg60)
+            || exceeds(self.window_psi, self.config.cutoff_psi_window)
+    }
+
+    /// Chooses a victim and holds on to it, choosing again if it exits in the meantime
+    fn get_victim(&mut self) -> Result<Victim> {
+        let mut attempts = 1;
+        loop {
+            let mut victim = kill::choose_victim(&mut self.proc_buf, &mut self.buf, &self.config)?;
+            match victim.hold(&self.config.proc_root) {
+                Ok(()) => return Ok(victim),
+                Err(Error::ProcessNotFound(_)) if attempts < VICTIM_ATTEMPTS => attempts += 1,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn update_memory_stats(&mut self) -> Result<()> {
+        self.memory_info = MemoryInfo::new(self.config.memory_source, &self.config.proc_root)?;
+        let near_terminal = is_near_terminal(&self.memory_info, &self.config);
+
+        // The pressure window needs samples even when memory isn't near terminal, otherwise its
+        // first reading after crossing the threshold would span a long period. `wait_timeout`
+        // keeps iterations from being further apart than the window is long.
+        let snapshot = if near_terminal || self.config.cutoff_psi_window.is_some() {
+            Some(PressureSnapshot::read(&self.config.proc_root)?)
+        } else {
+            None
+        };
+
+        self.window_psi = match (&snapshot, self.config.cutoff_psi_window) {
+            (Some(snapshot), Some(_)) => self.pressure_window.sample(
+                snapshot.some.total,
+                Instant::now(),
+                self.config.psi_window_length,
+            ),
+            _ => None,
+        };
+
+        let was_near_terminal = matches!(self.status, MemoryStatus::NearTerminal(_));
+        if near_terminal != was_near_terminal {
+            let (available_ram_mb, available_ram_percent) = (
+                self.memory_info.available_ram_mb,
+                self.memory_info.available_ram_percent,
+            );
+            log::emit(&if near_terminal {
+                Event::NearTerminal {
+                    available_ram_mb,
+                    available_ram_percent,
+                }
+            } else {
+                Event::Recovered {
+                    available_ram_mb,
+                    available_ram_percent,
+                }
+            });
+        }
+
+        self.status = match snapshot {
+            Some(snapshot) if near_terminal => MemoryStatus::NearTerminal(snapshot),
+            _ => MemoryStatus::Okay,
+        };
+        Ok(())
+    }
+
+    /// Re-reads the configuration file, keeping the current configuration if the new one
+    /// is invalid. Returns how many keys changed.
+    fn reload_config(&mut self) -> Result<usize> {
+        let new_config = Config::load(&self.args)?;
+
+        let changes = self.config.changes(&new_config);
+        log::emit(&Event::ConfigReloaded {
+            changes: changes.len(),
+        });
+        for (key, old, new) in &changes {
+            log::emit(&Event::ConfigChanged { key, old, new });
+        }
+        if new_config.no_daemon != self.config.no_daemon {
+            log::emit(&Event::RestartRequired { key: "no_daemon" });
+        }
+        log::set_filter(new_config.log_filter());
+        log::set_format(new_config.log_format);
+        if new_config.log_target() != self.config.log_target() {
+            log::set_target(new_config.log_target());
+        }
+        if new_config.log_target() == Target::File {
+            // Reopening the log file on every reload lets it be rotated
+            if let Err(err) = daemon::redirect_output(&new_config.log_file()) {
+                report_error("Could not reopen the log file", &err);
+            }
+        }
+
+        // The trigger is registered on the pressure file of the procfs at `proc_root`
+        let trigger_changed = (
+            new_config.psi_trigger,
+            new_config.psi_trigger_stall,
+            new_config.psi_trigger_window,
+            &new_config.proc_root,
+        ) != (
+            self.config.psi_trigger,
+            self.config.psi_trigger_stall,
+            self.config.psi_trigger_window,
+            &self.config.proc_root,
+        );
+        if trigger_changed {
+            // Drop the current trigger before registering the new one
+            self.trigger = None;
+            self.trigger = register_trigger(&new_config);
+        }
+        if new_config.metrics_listen != self.config.metrics_listen {
+            // Unbind first, since the new address may overlap with the old one
+            self.metrics = None;
+            self.metrics = bind_metrics(&new_config);
+        }
+        let control_changed = (
+            new_config.control,
+            new_config.control_socket(),
+            &new_config.control_group,
+        ) != (
+            self.config.control,
+            self.config.control_socket(),
+            &self.config.control_group,
+        );
+        if control_changed {
+            // Dropping the current socket removes it, so it must go before binding the new one
+            self.control = None;
+            self.clients.clear();
+            self.control = bind_control(&new_config);
+        }
+
+        self.config = new_config;
+        Ok(changes.len())
+    }
+
+    /// Returns true while paused, resuming once the pause is over
+    fn is_paused(&mut self) -> bool {
+        match self.paused_until {
+            Some(until) if Instant::now() >= until => {
+                self.paused_until = None;
+                log::emit(&Event::Resumed);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Keeps the victim around to be reported through the control socket
+    fn record_kill(&mut self, victim: &Victim, outcome: Option<(KillResult, i32)>) {
+        if self.kills.len() == KILL_HISTORY_LEN {
+            self.kills.pop_front();
+        }
+        self.kills.push_back(KillRecord {
+            timestamp: Timestamp::now(),
+            pid: victim.process.pid,
+            comm: victim.comm.clone(),
+            oom_score: victim.process.oom_score,
+            rss_kib: victim.vm_rss_kib,
+            outcome,
+        });
+    }
+
+    /// Returns true if not enough time has passed since the last kill
+    fn in_cooldown(&self) -> bool {
+        match self.last_kill {
+            Some(last_kill) => last_kill.elapsed() < self.config.kill_cooldown,
+            None => false,
+        }
+    }
+
+    /// The kernel's pressure averages the latest decision was based on, as read on the latest
+    /// iteration, or zeroes while memory isn't near terminal. The short-window reading that
+    /// `cutoff_psi_window` is checked against is kept apart, in `window_psi`.
+    fn psi(&self) -> PressureSnapshot {
+        match &self.status {
+            MemoryStatus::NearTerminal(psi) => *psi,
+            MemoryStatus::Okay => PressureSnapshot::default(),
+        }
+    }
+
+    /// Logs the victim along with the pressure values that led to it being chosen
+    fn log_decision(&self, victim: &Victim) {
+        log::emit(&Event::Kill {
+            pid: victim.process.pid,
+            comm: &victim.comm,
+            oom_score: victim.process.oom_score,
+            rss_kib: victim.vm_rss_kib,
+            psi: &self.psi(),
+            window_psi: self.window_psi,
+            dry_run: self.config.dry_run,
+        });
+    }
+
+    /// Finds out whom to notify about the victim, which must still be alive at this point
+    fn prepare_notification(
+        &self,
+        victim: &Victim,
+        notification: fn(&str, u32, i64) -> Notification,
+    ) -> Option<(Recipient, Notification)> {
+        let recipient = match Recipient::of(&victim.process, &self.config.proc_root) {
+            Ok(recipient) => recipient,
+            Err(err) => {
+                report_error("Could not find out whom to notify", &err);
+                return None;
+            }
+        };
+
+        Some((
+            recipient,
+            notification(&victim.comm, victim.process.pid, victim.vm_rss_kib),
+        ))
+    }
+
+    /// Runs the given hook, if configured, returning it if it was started
+    fn spawn_hook(
+        &self,
+        name: &'static str,
+        command: Option<&String>,
+        victim: &Victim,
+        result: Option<KillResult>,
+    ) -> Option<Hook> {
+        let command = command?;
+        match Hook::spawn(name, command, victim, self.psi().some.avg10, result) {
+            Ok(hook) => Some(hook),
+            Err(err) => {
+                let message = if result.is_some() {
+                    "Could not run the post_kill hook"
+                } else {
+                    "Could not run the pre_kill hook"
+                };
+                report_error(message, &err);
+                None
+            }
+        }
+    }
+
+    /// Reaps the background hooks that exited, killing those that ran for too long
+    fn reap_hooks(&mut self) {
+        let timeout = self.config.hook_timeout;
+        self.hooks.retain_mut(|hook| !hook.reap(timeout));
+    }
+
+    fn send_notification(recipient: &Recipient, notification: &Notification) {
+        if let Err(err) = notify::send(recipient, notification) {
+            report_error("Could not send a notification", &err);
+        }
+    }
+
+    /// Returns true if pressure is high enough to warn about the would-be victim
+    fn should_warn(&self) -> bool {
+        let (psi, warn_psi) = match (&self.status, self.config.notify_warn_psi) {
+            (MemoryStatus::NearTerminal(psi), Some(warn_psi)) => (psi, warn_psi),
+            _ => return false,
+        };
+        let warned_recently = match self.last_warning {
+            Some(last_warning) => last_warning.elapsed() < self.config.notify_warn_interval,
+            None => false,
+        };
+
+        self.config.notify && !self.config.dry_run && !warned_recently && psi.some.avg10 >= warn_psi
+    }
+
+    /// Warns the owner of the process that would be killed next
+    fn warn_next_victim(&mut self) -> Result<()> {
+        self.last_warning = Some(Instant::now());
+
+        let victim = self.get_victim()?;
+        if let Some((recipient, notification)) =
+            self.prepare_notification(&victim, Notification::warning)
+        {
+            Self::send_notification(&recipient, &notification);
+        }
+
+        Ok(())
+    }
+
+    /// Kills a victim if memory is still low once it's found.
+    /// Returns true if a victim was killed, or would have been in dry-run mode.
+    fn free_up_memory(&mut self) -> Result<bool> {
+        let victim = self.get_victim()?;
+
+        // TODO: is this necessary?
+        //
+        // Check for memory stats again to see if the
+        // low-memory situation was solved while
+        // we were searching for our victim
+        self.update_memory_stats()?;
+        if !self.memory_is_low() {
+            return Ok(false);
+        }
+
+        self.log_decision(&victim);
+        let mut strategy = kill::Strategy::for_victim(&victim.process, &self.config);
+        let full_avg10 = self.psi().full.avg10;
+        if matches!(self.config.sigkill_psi_full, Some(cutoff) if full_avg10 >= cutoff) {
+            // Grace periods would only let things get worse
+            strategy.ladder = SignalLadder::immediate();
+            log::emit(&Event::LadderSkipped {
+                pid: victim.process.pid,
+                full_avg10,
+            });
+        }
+        if self.config.dry_run {
+            // The cooldown applies to would-be kills too, so that the log mirrors real behaviour
+            self.last_kill = Some(Instant::now());
+            self.record_kill(&victim, None);
+            return Ok(true);
+        }
+
+        let notification = if self.config.notify {
+            self.prepare_notification(&victim, Notification::killed)
+        } else {
+            None
+        };
+        if let Some(mut hook) =
+            self.spawn_hook("pre_kill", self.config.pre_kill.as_ref(), &victim, None)
+        {
+            // Memory is running out, so slower hooks are left running alongside the kill
+            if !hook.wait(PRE_KILL_WAIT, self.config.hook_timeout) {
+                self.hooks.push(hook);
+            }
+        }
+
+        let killed = if strategy.kill_pgroup {
+            kill::kill_process_group(&victim, &strategy, &self.config.proc_root)
+        } else {
+            kill::kill_and_wait(&victim, &strategy, &self.config.proc_root)
+        };
+        // The cooldown starts once the victim is gone
+        self.last_kill = Some(Instant::now());
+
+        let result = match &killed {
+            Ok(outcome) if outcome.exited => KillResult::Killed,
+            Ok(_) => KillResult::Survived,
+            Err(_) => KillResult::Failed,
+        };
+        let signal = killed
+            .as_ref()
+            .map_or(strategy.ladder.first_signal(), |outcome| outcome.signal);
+        metrics::record_kill(signal, result);
+        self.record_kill(&victim, Some((result, signal)));
+        if let Some(hook) = self.spawn_hook(
+            "post_kill",
+            self.config.post_kill.as_ref(),
+            &victim,
+            Some(result),
+        ) {
+            self.hooks.push(hook);
+        }
+        if let (KillResult::Killed, Some((recipient, notification))) = (result, notification) {
+            Self::send_notification(&recipient, &notification);
+        }
+
+        killed.map(|_| true)
+    }
+
+    /// Checks memory, killing a process if it's low and warning about it if it's getting there
+    fn evaluate(&mut self) -> Result<Decision> {
+        self.update_memory_stats()?;
+        let paused = self.is_paused();
+
+        if self.memory_is_low() {
+            if paused {
+                log::emit(&Event::StillPaused);
+                return Ok(Decision::Paused);
+            }
+            if self.in_cooldown() {
+                log::emit(&Event::Cooldown);
+                return Ok(Decision::Cooldown);
+            }
+            return Ok(match self.free_up_memory()? {
+                true => Decision::Kill,
+                false => Decision::Nothing,
+            });
+        }
+        if !paused && self.should_warn() {
+            self.warn_next_victim()?;
+            return Ok(Decision::Warned);
+        }
+
+        Ok(Decision::Nothing)
+    }
+
+    /// How long to wait before memory is checked again.
+    ///
+    /// With a PSI trigger registered, we wait for the kernel to report a stall, but still
+    /// fall back to the adaptive sleep time while memory is near terminal, so that
+    /// PSI averages keep being checked even when stalls are below the trigger threshold.
+    /// While `cutoff_psi_window` is set, we never wait for longer than `psi_window_length`,
+    /// so that the window spans at most twice its length however late the trigger fires.
+    fn wait_timeout(&self) -> Duration {
+        let timeout = match (&self.trigger, &self.status) {
+            (Some(_), MemoryStatus::Okay) => self.config.psi_trigger_timeout,
+            _ => self.sleep_time,
+        };
+
+        match self.config.cutoff_psi_window {
+            Some(_) => timeout.min(self.config.psi_window_length),
+            None => timeout,
+        }
+    }
+
+    /// Waits until memory should be checked again, serving metrics and control requests
+    /// in the meantime
+    fn wait(&mut self) {
+        // Calculating the adaptive sleep time
+        self.sleep_time = self.sleep_time_ms();
+
+        let timeout = self.wait_timeout();
+        log::emit(&match self.trigger {
+            Some(_) => Event::WaitingForTrigger { timeout },
+            None => Event::AdaptiveSleep { duration: timeout },
+        });
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            // poll(2) skips entries with a negative file descriptor
+            let unused = pollfd {
+                fd: -1,
+                events: 0,
+                revents: 0,
+            };
+            let readable = |fd| pollfd {
+                fd,
+                events: POLLIN,
+                revents: 0,
+            };
+            // A fixed-size array, since nothing is allocated in between memory checks
+            let mut fds = [unused; 3 + control::MAX_CLIENTS];
+            fds[0] = self
+                .trigger
+                .as_ref()
+                .map_or(unused, PressureTrigger::pollfd);
+            fds[1] = self
+                .metrics
+                .as_ref()
+                .map_or(unused, |server| readable(server.as_raw_fd()));
+            // New clients are left waiting while we're serving as many as we'll take
+            fds[2] = self
+                .control
+                .as_ref()
+                .filter(|_| self.clients.len() < control::MAX_CLIENTS)
+                .map_or(unused, |server| readable(server.as_raw_fd()));
+            for (fd, client) in fds[3..].iter_mut().zip(&self.clients) {
+                *fd = readable(client.as_raw_fd());
+            }
+            let polled = 3 + self.clients.len();
+
+            // Wake up in time to drop clients that never finish their request
+            let wake_up = self
+                .clients
+                .iter()
+                .map(control::Connection::deadline)
+                .fold(deadline, Instant::min);
+            let remaining = wake_up.saturating_duration_since(Instant::now());
+            match utils::poll(&mut fds[..polled], remaining) {
+                // Some client ran out of time, which is dropped below
+                Ok(0) if wake_up < deadline && Instant::now() >= wake_up => {}
+                // Timed out, or a signal arrived and should be handled
+                Ok(0) => return,
+                Ok(_) => {}
+                Err(err) => {
+                    report_error("Could not wait for events", &err);
+                    std::thread::sleep(remaining);
+                    return;
+                }
+            }
+
+            if self.trigger.is_some() {
+                match PressureTrigger::fired(&fds[0]) {
+                    Ok(true) => {
+                        log::emit(&Event::TriggerFired);
+                        return;
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        report_error("PSI trigger failed, falling back to adaptive sleep", &err);
+                        self.trigger = None;
+                        return;
+                    }
+                }
+            }
+            if fds[1].revents != 0 {
+                self.serve_metrics();
+            }
+            if fds[2..].iter().any(|fd| fd.revents != 0) || !self.clients.is_empty() {
+                self.serve_control();
+            }
+        }
+    }
+
+    /// Reads PSI afresh, since it isn't read while memory is far from terminal
+    fn current_psi(&self) -> Option<PressureSnapshot> {
+        PressureSnapshot::read(&self.config.proc_root).ok()
+    }
+
+    fn serve_metrics(&self) {
+        let server = match &self.metrics {
+            Some(server) => server,
+            None => return,
+        };
+        let gauges = metrics::Gauges {
+            memory: &self.memory_info,
+            psi: self.current_psi(),
+            window_psi: self.window_psi,
+            near_terminal: matches!(self.status, MemoryStatus::NearTerminal(_)),
+            sleep: self.sleep_time,
+        };
+
+        if let Err(err) = server.serve(&gauges) {
+            report_error("Could not serve metrics", &err.into());
+        }
+    }
+
+    // Use the never type here whenever it reaches stable
+    #[allow(unreachable_code)]
+    pub fn poll(&mut self) -> Result<()> {
+        loop {
+            if signals::take_reload_request() {
+                if let Err(err) = self.reload_config() {
+                    report_error(
+                        "Failed to reload configuration, keeping the current one",
+                        &err,
+                    );
+                }
+            }
+            match signals::take_verbosity_request() {
+                0 => {}
+                steps => log::emit(&Event::VerbosityChanged {
+                    level: log::shift_verbosity(steps),
+                }),
+            }
+            self.reap_hooks();
+
+            // A failed iteration, e.g. because the victim exited while being chosen,
+            // shouldn't stop the daemon: memory is checked again after waiting
+            if let Err(err) = self.evaluate() {
+                report_error("Could not evaluate memory", &err);
+            }
+            self.wait();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::process::{Child, Command};
+    use std::time::{Duration, Instant};
+
+    use argh::FromArgs;
+    use libc::SIGTERM;
+
+    use super::{register_trigger, Decision, Monitor};
+    use crate::cli::CommandLineArgs;
+    use crate::config::{ByteSize, Config, Threshold};
+    use crate::control;
+    use crate::error::Error;
+    use crate::testing::ProcFixture;
+
+    /// Starts monitoring with the given configuration
+    fn start(config: Config) -> Monitor {
+        let args = CommandLineArgs::from_args(&["bustd"], &[]).unwrap();
+
+        Monitor::new([0; 128], [0; 256], config, args).unwrap()
+    }
+
+    /// A configuration reading from the fixture, under which memory is never near terminal
+    fn plenty_of_memory(fixture: &ProcFixture) -> Config {
+        Config {
+            near_terminal: Threshold::Size(ByteSize(0)),
+            ..fixture.config()
+        }
+    }
+
+    /// A configuration reading from the fixture, under which memory is always low
+    fn low_memory(fixture: &ProcFixture) -> Config {
+        fixture.write(
+            "pressure/memory",
+            "some avg10=50.00 avg60=0.00 avg300=0.00 total=0\n\
+             full avg10=0.00 avg60=0.00 avg300=0.00 total=0\n",
+        );
+
+        Config {
+            near_terminal: Threshold::Percent(100.0),
+            psi_trigger: false,
+            ..fixture.config()
+        }
+    }
+
+    /// Spawns `sleep` in a process group of its own, as the only process of the fixture
+    fn spawn_victim(fixture: &ProcFixture) -> Child {
+        let child = Command::new("sleep")
+            .arg("10")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        fixture.add_process(child.id(), "sleep", 500, 0, 4096);
+        // Victims are checked not to have been replaced by a process reusing their PID
+        let stat = fs::read_to_string(format!("/proc/{}/stat", child.id())).unwrap();
+        fixture.write(format!("{}/stat", child.id()), &stat);
+
+        child
+    }
+
+    #[test]
+    fn should_fall_back_to_adaptive_sleep_without_psi_triggers() {
+        // As on kernels built without PSI
+        let fixture = ProcFixture::new("no-pressure");
+        assert!(register_trigger(&fixture.config()).is_none());
+        let monitor = start(plenty_of_memory(&fixture));
+        assert!(monitor.trigger.is_none());
+        assert_eq!(monitor.wait_timeout(), monitor.sleep_time);
+
+        fixture.write("pressure/memory", "");
+        let config = Config {
+            psi_trigger: false,
+            ..fixture.config()
+        };
+        assert!(register_trigger(&config).is_none());
+
+        let config = Config {
+            psi_trigger_stall: Duration::from_millis(100),
+            ..plenty_of_memory(&fixture)
+        };
+        let monitor = start(config);
+        assert!(monitor.trigger.is_some());
+        assert_eq!(
+            fs::read_to_string(fixture.root.join("pressure/memory")).unwrap(),
+            "some 100000 1000000\0"
+        );
+        assert_eq!(monitor.wait_timeout(), monitor.config.psi_trigger_timeout);
+    }
+
+    #[test]
+    fn pressure_window_samples_should_not_wait_on_the_trigger() {
+        let fixture = ProcFixture::new("window-cadence");
+        fixture.write("pressure/memory", "");
+        let config = Config {
+            cutoff_psi_window: Some(20.0),
+            psi_window_length: Duration::from_millis(300),
+            ..plenty_of_memory(&fixture)
+        };
+
+        let monitor = start(config);
+        assert!(monitor.trigger.is_some());
+        assert_eq!(monitor.wait_timeout(), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn dry_runs_should_never_kill() {
+        let fixture = ProcFixture::new("dry-run");
+        let mut child = spawn_victim(&fixture);
+
+        for kill_pgroup in [false, true] {
+            let mut monitor = start(Config {
+                dry_run: true,
+                kill_pgroup,
+                ..low_memory(&fixture)
+            });
+            for _ in 0..3 {
+                assert_eq!(monitor.evaluate().unwrap(), Decision::Kill);
+            }
+
+            assert!(child.try_wait().unwrap().is_none());
+            assert_eq!(monitor.kills.len(), 3);
+            assert!(monitor.kills.iter().all(|record| record.outcome.is_none()));
+        }
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn cooldown_should_apply_to_real_and_would_be_kills() {
+        for dry_run in [true, false] {
+            let fixture = ProcFixture::new(&format!("cooldown-{}", dry_run));
+            let mut child = spawn_victim(&fixture);
+            let mut monitor = start(Config {
+                dry_run,
+                kill_cooldown: Duration::from_secs(60),
+                ..low_memory(&fixture)
+            });
+
+            assert_eq!(monitor.evaluate().unwrap(), Decision::Kill);
+            assert_eq!(monitor.evaluate().unwrap(), Decision::Cooldown);
+            assert_eq!(monitor.kills.len(), 1);
+
+            if dry_run {
+                child.kill().unwrap();
+            }
+            let signal = child.wait().unwrap().signal();
+            assert_eq!(signal == Some(SIGTERM), !dry_run);
+        }
+    }
+
+    #[test]
+    fn control_clients_should_never_hold_up_the_monitor() {
+        let fixture = ProcFixture::new("control-clients");
+        let socket = fixture.root.join("control.sock");
+        let mut monitor = start(Config {
+            control: true,
+            control_socket: Some(socket.clone()),
+            ..plenty_of_memory(&fixture)
+        });
+
+        // Clients that never send anything are only taken up to the limit
+        let silent: Vec<_> = (0..control::MAX_CLIENTS + 2)
+            .map(|_| UnixStream::connect(&socket).unwrap())
+            .collect();
+        let started = Instant::now();
+        monitor.serve_control();
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert_eq!(monitor.clients.len(), control::MAX_CLIENTS);
+
+        // and dropped once their time runs out, making room for the others
+        std::thread::sleep(Duration::from_millis(600));
+        monitor.serve_control();
+        assert_eq!(monitor.clients.len(), 2);
+        drop(silent);
+
+        let mut client = UnixStream::connect(&socket).unwrap();
+        client.write_all(b"{\"command\":\"resume\"}\n").unwrap();
+        monitor.serve_control();
+        let mut response = String::new();
+        BufReader::new(client).read_line(&mut response).unwrap();
+        assert_eq!(response, "{\"ok\":true,\"was_paused\":false}\n");
+    }
+
+    #[test]
+    fn victims_that_vanish_should_only_fail_the_iteration() {
+        let fixture = ProcFixture::new("vanished");
+        let mut child = spawn_victim(&fixture);
+        // Outranks the real process, but has already exited by the time it's held on to
+        let vanished = 4_194_305;
+        fixture.add_process(vanished, "ghost", 900, 0, 4096);
+        let stat = fs::read_to_string(fixture.root.join(format!("{}/stat", child.id()))).unwrap();
+        fixture.write(format!("{}/stat", vanished), &stat);
+        let mut monitor = start(low_memory(&fixture));
+
+        assert!(matches!(monitor.evaluate(), Err(Error::ProcessNotFound(_))));
+        assert!(child.try_wait().unwrap().is_none());
+
+        fs::remove_dir_all(fixture.root.join(vanished.to_string())).unwrap();
+        assert_eq!(monitor.evaluate().unwrap(), Decision::Kill);
+        assert_eq!(child.wait().unwrap().signal(), Some(SIGTERM));
+    }
+
+    #[test]
+    fn triggers_should_follow_proc_root_on_reload() {
+        let (old, new) = (
+            ProcFixture::new("reload-old-proc"),
+            ProcFixture::new("reload-new-proc"),
+        );
+        for fixture in [&old, &new] {
+            fixture.write("pressure/memory", "");
+        }
+        let mut monitor = start(plenty_of_memory(&old));
+        assert!(monitor.trigger.is_some());
+
+        let config_file = new.root.join("bustd.toml");
+        fs::write(
+            &config_file,
+            // Logging to a file would redirect the output of the tests
+            format!("proc_root = \"{}\"\nno_daemon = true\n", new.root.display()),
+        )
+        .unwrap();
+        monitor.args =
+            CommandLineArgs::from_args(&["bustd"], &["--config", config_file.to_str().unwrap()])
+                .unwrap();
+        monitor.reload_config().unwrap();
+
+        assert!(monitor.trigger.is_some());
+        assert_eq!(
+            fs::read_to_string(new.root.join("pressure/memory")).unwrap(),
+            "some 150000 1000000\0"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Answering requests made through the control socket
+
+use std::fmt;
+use std::time::Instant;
+
+use super::{report_error, MemoryStatus, Monitor};
+use crate::control::{self, Request};
+use crate::json::JsonObject;
+use crate::log::{self, Event};
+use crate::memory::pressure::PressureLine;
+
+fn write_pressure_line(object: &mut JsonObject<String>, line: &PressureLine) -> fmt::Result {
+    object.float("avg10", Some(line.avg10))?;
+    object.float("avg60", Some(line.avg60))?;
+    object.float("avg300", Some(line.avg300))?;
+    object.number("total", line.total)
+}
+
+impl Monitor {
+    /// Accepts pending clients, up to `control::MAX_CLIENTS` at a time, and answers those
+    /// whose request has arrived. Never blocks: clients that haven't finished sending their
+    /// request are kept around until their time runs out.
+    pub(super) fn serve_control(&mut self) {
+        // Making room for new clients first
+        self.clients.retain(|connection| !connection.timed_out());
+        if let Some(server) = &self.control {
+            while self.clients.len() < control::MAX_CLIENTS {
+                match server.accept() {
+                    Ok(Some(connection)) => self.clients.push(connection),
+                    Ok(None) => break,
+                    Err(err) => {
+                        report_error("Could not accept a control connection", &err.into());
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut i = 0;
+        while i < self.clients.len() {
+            // A client that goes away or never sends anything isn't worth reporting
+            let request = match self.clients[i].read_request() {
+                Ok(None) => {
+                    i += 1;
+                    continue;
+                }
+                Ok(Some(request)) => Some(request),
+                Err(_) => None,
+            };
+            let connection = self.clients.swap_remove(i);
+
+            let response = match request {
+                Some(Ok(request)) => self.execute(request),
+                Some(Err(reason)) => control::error_response(&reason),
+                None => continue,
+            };
+            let _ = connection.respond(&response);
+        }
+    }
+
+    fn execute(&mut self, request: Request) -> String {
+        match request {
+            Request::Status => control::ok_response(|object| self.write_status(object)),
+            Request::Pause(duration) => {
+                let until = match Instant::now().checked_add(duration) {
+                    Some(until) => until,
+                    None => return control::error_response("the pause is too long"),
+                };
+                self.paused_until = Some(until);
+                log::emit(&Event::Paused { duration });
+
+                control::ok_response(|object| {
+                    object.number("paused_ms", duration.as_millis() as i128)
+                })
+            }
+            Request::Resume => {
+                let was_paused = self.paused_until.take().is_some();
+                if was_paused {
+                    log::emit(&Event::Resumed);
+                }
+
+                control::ok_response(|object| object.boolean("was_paused", was_paused))
+            }
+            Request::Evaluate => match self.evaluate() {
+                Ok(decision) => control::ok_response(|object| {
+                    object.string("decision", decision.as_str())?;
+                    object.boolean("dry_run", self.config.dry_run)
+                }),
+                Err(err) => {
+                    report_error("Could not evaluate memory on request", &err);
+                    control::error_response(&format!("{:?}", err))
+                }
+            },
+            Request::Reload => match self.reload_config() {
+                Ok(changes) => {
+                    control::ok_response(|object| object.number("changes", changes as i128))
+                }
+                Err(err) => {
+                    report_error(
+                        "Failed to reload configuration, keeping the current one",
+                        &err,
+                    );
+                    control::error_response(&format!("{:?}", err))
+                }
+            },
+        }
+    }
+
+    fn write_status(&self, object: &mut JsonObject<String>) -> fmt::Result {
+        let now = Instant::now();
+        let paused_until = self.paused_until.filter(|until| *until > now);
+
+        object.number("pid", std::process::id())?;
+        object.number("uptime_ms", self.started.elapsed().as_millis() as i128)?;
+        object.boolean("paused", paused_until.is_some())?;
+        if let Some(until) = paused_until {
+            object.number("resumes_in_ms", (until - now).as_millis() as i128)?;
+        }
+        object.boolean("in_cooldown", self.in_cooldown())?;
+        object.boolean("dry_run", self.config.dry_run)?;
+
+        let memory_info = &self.memory_info;
+        let mut memory = object.object("memory")?;
+        memory.number("total_ram_mb", memory_info.total_ram_mb)?;
+        memory.number("available_ram_mb", memory_info.available_ram_mb)?;
+        memory.number("available_ram_percent", memory_info.available_ram_percent)?;
+        memory.number("total_swap_mb", memory_info.total_swap_mb)?;
+        memory.number("available_swap_mb", memory_info.available_swap_mb)?;
+        memory.number("available_swap_percent", memory_info.available_swap_percent)?;
+        memory.finish()?;
+
+        object.string(
+            "status",
+            match self.status {
+                MemoryStatus::NearTerminal(_) => "near_terminal",
+                MemoryStatus::Okay => "okay",
+            },
+        )?;
+        if let Some(psi) = self.current_psi() {
+            let mut pressure = object.object("psi")?;
+            let mut some = pressure.object("some")?;
+            write_pressure_line(&mut some, &psi.some)?;
+            some.finish()?;
+            let mut full = pressure.object("full")?;
+            write_pressure_line(&mut full, &psi.full)?;
+            full.finish()?;
+            pressure.finish()?;
+        }
+        object.float("window_psi", self.window_psi)?;
+        object.number("sleep_ms", self.sleep_time.as_millis() as i128)?;
+
+        // Values are written as they'd appear in the configuration file, unset ones being left out
+        let mut config = object.object("config")?;
+        for (key, value) in self.config.entries() {
+            if let Some(value) = value {
+                config.string(key, value)?;
+            }
+        }
+        config.finish()?;
+
+        let mut kills = object.array("kills")?;
+        for record in &self.kills {
+            let mut kill = kills.object()?;
+            kill.string("timestamp", record.timestamp)?;
+            kill.number("pid", record.pid)?;
+            kill.string("comm", &record.comm)?;
+            kill.number("oom_score", record.oom_score)?;
+            kill.number("rss_kib", record.rss_kib)?;
+            match record.outcome {
+                Some((result, signal)) => {
+                    kill.string("result", result.as_str())?;
+                    kill.number("signal", signal)?;
+                }
+                None => kill.boolean("dry_run", true)?,
+            }
+            kill.finish()?;
+        }
+        kills.finish()
+    }
+}
@@ -0,0 +1,201 @@
+//! Desktop notifications, sent through `org.freedesktop.Notifications` on the session bus
+//! of whoever owns the process being killed.
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::ptr;
+use std::time::Duration;
+
+use crate::dbus::{Address, Arg, Connection, MethodCall};
+use crate::error::{Error, Result};
+use crate::log::{self, Event};
+use crate::process::Process;
+
+/// How long to wait on an unresponsive session bus
+const BUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The user to notify and where their session bus is
+#[derive(Debug, PartialEq)]
+pub struct Recipient {
+    pub uid: u32,
+    pub gid: u32,
+    pub address: Address,
+}
+
+impl Recipient {
+    /// Finds out who owns the given process and the session bus it was started with.
+    /// Must be called before the process is killed, since it reads its environment.
+    pub fn of(process: &Process, proc_root: &Path) -> Result<Self> {
+        let process_dir = proc_root.join(process.pid.to_string());
+        let metadata = fs::metadata(&process_dir)?;
+        let (uid, gid) = (metadata.uid(), metadata.gid());
+
+        // Processes started outside of the user's session, such as cron jobs,
+        // don't know about the bus, but the user's session might still have one
+        let address = fs::read(process_dir.join("environ"))
+            .ok()
+            .and_then(|environ| session_bus_address(&environ))
+            .unwrap_or_else(|| Address::user_bus(uid));
+
+        Ok(Self { uid, gid, address })
+    }
+}
+
+/// Finds `DBUS_SESSION_BUS_ADDRESS` in the NUL-separated contents of `/proc/<pid>/environ`
+fn session_bus_address(environ: &[u8]) -> Option<Address> {
+    environ
+        .split(|byte| *byte == 0)
+        .find_map(|variable| variable.strip_prefix(b"DBUS_SESSION_BUS_ADDRESS="))
+        .and_then(|address| std::str::from_utf8(address).ok())
+        .and_then(Address::parse)
+}
+
+/// See https://specifications.freedesktop.org/notification-spec/latest/urgency-levels.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Urgency {
+    Normal = 1,
+    Critical = 2,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    pub urgency: Urgency,
+}
+
+impl Notification {
+    pub fn killed(comm: &str, pid: u32, vm_rss_kib: i64) -> Self {
+        Self {
+            summary: format!("{} was killed", comm),
+            body: format!(
+                "The system was running out of memory, so bustd killed {} (PID {}), freeing about {} MiB.",
+                comm,
+                pid,
+                vm_rss_kib / 1024
+            ),
+            urgency: Urgency::Critical,
+        }
+    }
+
+    pub fn warning(comm: &str, pid: u32, vm_rss_kib: i64) -> Self {
+        Self {
+            summary: "Memory is running low".into(),
+            body: format!(
+                "{} (PID {}) is using {} MiB and will be killed first if memory pressure keeps rising.",
+                comm,
+                pid,
+                vm_rss_kib / 1024
+            ),
+            urgency: Urgency::Normal,
+        }
+    }
+
+    /// Shows the notification on the given bus, as the calling process' user
+    fn show(&self, address: &Address) -> Result<()> {
+        let mut connection = Connection::open(address, BUS_TIMEOUT)?;
+        connection.call(&MethodCall {
+            destination: "org.freedesktop.Notifications",
+            path: "/org/freedesktop/Notifications",
+            interface: "org.freedesktop.Notifications",
+            member: "Notify",
+            args: &[
+                // Application name
+                Arg::Str("bustd"),
+                // ID of the notification this one replaces, none
+                Arg::Uint32(0),
+                Arg::Str("dialog-warning"),
+                Arg::Str(&self.summary),
+                Arg::Str(&self.body),
+                // Actions
+                Arg::StrArray(&[]),
+                // Hints
+                Arg::ByteDict(&[("urgency", self.urgency as u8)]),
+                // Let the notification server decide when it expires
+                Arg::Int32(-1),
+            ],
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Drops to the recipient's user, as session buses only accept connections from their owner
+fn drop_privileges(recipient: &Recipient) -> bool {
+    // Safety: these calls only change the credentials of the calling process
+    unsafe {
+        libc::setgroups(0, ptr::null()) == 0
+            && libc::setgid(recipient.gid) == 0
+            && libc::setuid(recipient.uid) == 0
+    }
+}
+
+/// Sends the notification from a short-lived grandchild process, so that a slow session bus
+/// never blocks the daemon and so that the daemon itself never gives up its privileges.
+pub fn send(recipient: &Recipient, notification: &Notification) -> Result<()> {
+    // Safety: the daemon is single-threaded, so the child is in a consistent state after forking
+    match unsafe { libc::fork() } {
+        -1 => Err(Error::UnknownFork),
+        0 => {
+            // Fork again so that the grandchild is reparented to init and reaped by it
+            // Safety: as above
+            if unsafe { libc::fork() } == 0 {
+                let status = if !drop_privileges(recipient) {
+                    log::emit(&Event::Error {
+                        message: "Could not switch to the recipient's user to send a notification",
+                        error: &recipient.uid,
+                    });
+                    1
+                } else if let Err(err) = notification.show(&recipient.address) {
+                    log::emit(&Event::Error {
+                        message: "Could not send a notification",
+                        error: &err,
+                    });
+                    1
+                } else {
+                    0
+                };
+                // Safety: _exit skips the daemon's destructors and atexit handlers
+                unsafe { libc::_exit(status) };
+            }
+            // Safety: as above
+            unsafe { libc::_exit(0) };
+        }
+        child => {
+            // Safety: we're waiting on our own child, which exits right after forking
+            unsafe { libc::waitpid(child, ptr::null_mut(), 0) };
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{session_bus_address, Notification, Urgency};
+    use crate::dbus::Address;
+
+    #[test]
+    fn should_find_the_session_bus_in_the_environment() {
+        let environ =
+            b"HOME=/home/user\0DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/1000/bus\0LANG=C\0";
+        assert_eq!(
+            session_bus_address(environ),
+            Some(Address::Path(PathBuf::from("/run/user/1000/bus")))
+        );
+        assert_eq!(session_bus_address(b"HOME=/root\0"), None);
+    }
+
+    #[test]
+    fn kill_notifications_should_report_freed_memory() {
+        let notification = Notification::killed("firefox", 1234, 2_097_152);
+
+        assert_eq!(notification.summary, "firefox was killed");
+        assert!(notification
+            .body
+            .contains("(PID 1234), freeing about 2048 MiB"));
+        assert_eq!(notification.urgency, Urgency::Critical);
+    }
+}
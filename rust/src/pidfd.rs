@@ -0,0 +1,124 @@
+//! Process file descriptors, which keep referring to the same process even once its PID
+//! has been reused. Available since Linux 5.3.
+
+use std::fs;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::ptr;
+
+use libc::{
+    c_int, c_uint, pid_t, siginfo_t, SYS_pidfd_open, SYS_pidfd_send_signal, SYS_process_mrelease,
+};
+
+#[derive(Debug)]
+pub struct PidFd(OwnedFd);
+
+impl AsRawFd for PidFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl PidFd {
+    /// Opens a pidfd referring to the process with the given PID.
+    /// Fails with ENOSYS on kernels without pidfds, and with ESRCH if there's no such process.
+    pub fn open(pid: u32) -> io::Result<Self> {
+        // Safety: pidfd_open takes no pointers
+        let fd = unsafe { libc::syscall(SYS_pidfd_open, pid as pid_t, 0 as c_uint) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Safety: the file descriptor was just opened, and nothing else owns it
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd as RawFd) }))
+    }
+
+    /// The PID of the process in the PID namespace of the procfs mounted at `proc_root`, as
+    /// the `Pid:` line of its fdinfo shows it: 0 if it isn't visible from that namespace,
+    /// and -1 once it has exited. Unset if the fdinfo can't be read, as on kernels older than 5.4.
+    pub fn pid_in(&self, proc_root: &Path) -> Option<i64> {
+        let fdinfo = proc_root.join(format!("self/fdinfo/{}", self.as_raw_fd()));
+        let contents = fs::read_to_string(fdinfo).ok()?;
+
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix("Pid:"))
+            .and_then(|pid| pid.trim().parse().ok())
+    }
+
+    /// Sends the signal to the process, which fails with ESRCH once it has exited
+    pub fn send_signal(&self, signal: i32) -> io::Result<()> {
+        // Safety: a null siginfo makes the kernel fill it in as kill() would
+        let res = unsafe {
+            libc::syscall(
+                SYS_pidfd_send_signal,
+                self.0.as_raw_fd(),
+                signal as c_int,
+                ptr::null::<siginfo_t>(),
+                0 as c_uint,
+            )
+        };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Reaps the memory of the process, which must have been sent SIGKILL, without waiting
+    /// for it to tear down its address space by itself.
+    /// Fails with ENOSYS on kernels older than 5.15.
+    pub fn release_memory(&self) -> io::Result<()> {
+        // Safety: process_mrelease takes no pointers
+        let res = unsafe { libc::syscall(SYS_process_mrelease, self.0.as_raw_fd(), 0 as c_uint) };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+
+    use libc::{ENOSYS, EPERM, SIGKILL};
+
+    use super::PidFd;
+    use std::path::Path;
+
+    #[test]
+    fn should_signal_processes_through_pidfds() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+
+        let pidfd = match PidFd::open(child.id()) {
+            Ok(pidfd) => pidfd,
+            // Kernels older than 5.3
+            Err(err) if err.raw_os_error() == Some(ENOSYS) => {
+                child.kill().unwrap();
+                child.wait().unwrap();
+                return;
+            }
+            Err(err) => panic!("{}", err),
+        };
+        if let Some(pid) = pidfd.pid_in(Path::new("/proc")) {
+            assert_eq!(pid, child.id() as i64);
+        }
+        pidfd.send_signal(SIGKILL).unwrap();
+        // Kernels older than 5.15 lack process_mrelease, which may also be reserved to root
+        if let Err(err) = pidfd.release_memory() {
+            assert!(
+                matches!(err.raw_os_error(), Some(ENOSYS | EPERM)),
+                "{}",
+                err
+            );
+        }
+
+        assert_eq!(child.wait().unwrap().signal(), Some(SIGKILL));
+        // The pidfd still refers to the reaped process, rather than to whichever reuses its PID
+        assert!(pidfd.send_signal(SIGKILL).is_err());
+    }
+}
@@ -1,8 +1,14 @@
+use std::cell::OnceCell;
+use std::fs;
 use std::io::Read;
 use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
 
 use libc::getpgid;
 
+#[cfg(feature = "glob-ignore")]
+use crate::log::{self, Event};
 use crate::{
     error::{Error, Result},
     utils::{self, str_from_bytes},
@@ -15,9 +21,10 @@ pub struct Process {
 }
 
 impl Process {
-    pub fn from_pid(pid: u32, buf: &mut [u8]) -> Result<Self> {
-        let oom_score =
-            Self::oom_score_from_pid(pid, buf).or(Err(Error::ProcessNotFound("from_pid")))?;
+    /// Reads the process with the given PID from the procfs mounted at `proc_root`
+    pub fn from_pid(pid: u32, proc_root: &Path, buf: &mut [u8]) -> Result<Self> {
+        let oom_score = Self::oom_score_from_pid(pid, proc_root, buf)
+            .or(Err(Error::ProcessNotFound("from_pid")))?;
         Ok(Self { pid, oom_score })
     }
 
@@ -27,12 +34,12 @@ impl Process {
     pub fn this(buf: &mut [u8]) -> Result<Self> {
         let pid = unsafe { libc::getpid() } as u32;
 
-        Self::from_pid(pid, buf)
+        Self::from_pid(pid, Path::new("/proc"), buf)
     }
 
     /// Return true if the process is alive
-    /// Could still return true if the process has exited but hasn't yet been reaped.
-    /// TODO: would it be better to check for /proc/<PID>/ in here?
+    /// Could still return true if the process has exited but hasn't yet been reaped,
+    /// which `state` tells apart.
     pub fn is_alive_from_pid(pid: u32) -> bool {
         // Safety: `getpgid` is memory safe
         let group_id = unsafe { getpgid(pid as i32) };
@@ -40,12 +47,8 @@ impl Process {
         group_id > 0
     }
 
-    pub fn is_alive(&self) -> bool {
-        Self::is_alive_from_pid(self.pid)
-    }
-
-    pub fn comm<'a>(&self, buf: &'a mut [u8]) -> Result<&'a str> {
-        write!(&mut *buf, "/proc/{}/comm\0", self.pid)?;
+    pub fn comm<'a>(&self, proc_root: &Path, buf: &'a mut [u8]) -> Result<&'a str> {
+        write!(&mut *buf, "{}/{}/comm\0", proc_root.display(), self.pid)?;
         {
             let mut file = utils::file_from_buffer(buf)?;
             buf.fill(0);
@@ -55,8 +58,8 @@ impl Process {
         str_from_bytes(buf)
     }
 
-    pub fn oom_score_from_pid(pid: u32, buf: &mut [u8]) -> Result<i16> {
-        write!(&mut *buf, "/proc/{}/oom_score\0", pid)?;
+    pub fn oom_score_from_pid(pid: u32, proc_root: &Path, buf: &mut [u8]) -> Result<i16> {
+        write!(&mut *buf, "{}/{}/oom_score\0", proc_root.display(), pid)?;
         let contents = {
             let mut file = utils::file_from_buffer(buf)?;
             buf.fill(0);
@@ -72,8 +75,8 @@ impl Process {
     /// In order to match the VmRSS value in /proc/<PID>/status, we'll
     /// multiply the number of pages in `statm` by the page size of our system and then convert
     /// that value to KiB
-    pub fn vm_rss_kib(&self, buf: &mut [u8]) -> Result<i64> {
-        write!(&mut *buf, "/proc/{}/statm\0", self.pid)?;
+    pub fn vm_rss_kib(&self, proc_root: &Path, buf: &mut [u8]) -> Result<i64> {
+        write!(&mut *buf, "{}/{}/statm\0", proc_root.display(), self.pid)?;
         let mut columns = {
             let mut file = utils::file_from_buffer(buf)?;
             buf.fill(0);
@@ -90,16 +93,103 @@ impl Process {
         Ok(vm_rss_kib)
     }
 
+    /// Reads VmSwap from /proc/<PID>/status, which kernel threads don't have
+    pub fn vm_swap_kib(&self, proc_root: &Path) -> Result<i64> {
+        let status = fs::read_to_string(proc_root.join(self.pid.to_string()).join("status"))?;
+        let vm_swap = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmSwap:"))
+            .and_then(|value| value.trim().strip_suffix("kB"));
+
+        match vm_swap {
+            Some(vm_swap) => Ok(vm_swap.trim().parse()?),
+            None => Ok(0),
+        }
+    }
+
+    /// Reads the path of the process' executable, which kernel threads don't have
+    pub fn exe(&self, proc_root: &Path) -> Result<String> {
+        let exe = fs::read_link(proc_root.join(self.pid.to_string()).join("exe"))?;
+
+        Ok(exe.to_string_lossy().into_owned())
+    }
+
+    /// Reads the process' command line, its arguments separated by spaces
+    pub fn cmdline(&self, proc_root: &Path) -> Result<String> {
+        let cmdline = fs::read(proc_root.join(self.pid.to_string()).join("cmdline"))?;
+        let cmdline = String::from_utf8_lossy(&cmdline).replace('\0', " ");
+
+        Ok(cmdline.trim_end().into())
+    }
+
+    /// Reads the ID of the user owning the process
+    pub fn uid(&self, proc_root: &Path) -> Result<u32> {
+        let metadata = fs::metadata(proc_root.join(self.pid.to_string()))?;
+
+        Ok(metadata.uid())
+    }
+
+    /// Reads the field of /proc/<PID>/stat at the given index, counting from the one after comm
+    fn stat_field(&self, proc_root: &Path, index: usize) -> Result<String> {
+        let stat = fs::read_to_string(proc_root.join(self.pid.to_string()).join("stat"))?;
+        // comm may contain spaces and parentheses, so fields are counted from its end
+        let fields = &stat[stat.rfind(')').ok_or(Error::MalformedStat)? + 1..];
+        let field = fields
+            .split_ascii_whitespace()
+            .nth(index)
+            .ok_or(Error::MalformedStat)?;
+
+        Ok(field.into())
+    }
+
+    /// Reads the process' state from /proc/<PID>/stat, e.g. `R` for running or `Z` for zombies
+    pub fn state(&self, proc_root: &Path) -> Result<char> {
+        self.stat_field(proc_root, 0)?
+            .chars()
+            .next()
+            .ok_or(Error::MalformedStat)
+    }
+
+    /// Reads the ID of the process' group from /proc/<PID>/stat
+    pub fn process_group(&self, proc_root: &Path) -> Result<i32> {
+        Ok(self.stat_field(proc_root, 2)?.parse()?)
+    }
+
+    /// Reads the PID of the process' parent from /proc/<PID>/stat
+    pub fn parent_pid(&self, proc_root: &Path) -> Result<u32> {
+        Ok(self.stat_field(proc_root, 1)?.parse()?)
+    }
+
+    /// Reads when the process started, in clock ticks since boot, from /proc/<PID>/stat.
+    /// Along with the PID, this tells a process apart from any that later reuses its PID.
+    pub fn start_time(&self, proc_root: &Path) -> Result<u64> {
+        Ok(self.stat_field(proc_root, 19)?.parse()?)
+    }
+
+    /// Reads the path of the process' cgroup, within the unified hierarchy if there is one
+    pub fn cgroup(&self, proc_root: &Path) -> Result<String> {
+        let cgroups = fs::read_to_string(proc_root.join(self.pid.to_string()).join("cgroup"))?;
+        // Lines are `hierarchy-ID:controllers:path`, the unified hierarchy being `0::path`
+        let path = cgroups
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .or_else(|| cgroups.lines().next()?.splitn(3, ':').nth(2));
+
+        Ok(path.unwrap_or("/").into())
+    }
+
     #[cfg(feature = "glob-ignore")]
     /// Checks if the process' name matches any of the given glob patterns
-    pub fn is_unkillable(&self, buf: &mut [u8], patterns: &[glob::Pattern]) -> Result<bool> {
-        let comm = self.comm(buf)?.trim();
+    pub fn is_unkillable(
+        &self,
+        proc_root: &Path,
+        buf: &mut [u8],
+        patterns: &[glob::Pattern],
+    ) -> Result<bool> {
+        let comm = self.comm(proc_root, buf)?.trim();
         for pattern in patterns {
             if pattern.matches(comm) {
-                println!(
-                    "Skipping \"{}\" since it matches an unkillable pattern",
-                    comm
-                );
+                log::emit(&Event::Unkillable { comm });
                 return Ok(true);
             }
         }
@@ -107,8 +197,13 @@ impl Process {
         Ok(false)
     }
 
-    pub fn oom_score_adj(&self, buf: &mut [u8]) -> Result<i16> {
-        write!(&mut *buf, "/proc/{}/oom_score_adj\0", self.pid)?;
+    pub fn oom_score_adj(&self, proc_root: &Path, buf: &mut [u8]) -> Result<i16> {
+        write!(
+            &mut *buf,
+            "{}/{}/oom_score_adj\0",
+            proc_root.display(),
+            self.pid
+        )?;
         let contents = {
             let mut file = utils::file_from_buffer(buf)?;
             buf.fill(0);
@@ -121,6 +216,80 @@ impl Process {
     }
 }
 
+/// What rules and patterns match a process against, each read from procfs
+/// the first time it's needed. Unreadable values are `None`.
+pub struct Facts<'a> {
+    process: &'a Process,
+    proc_root: &'a Path,
+    comm: OnceCell<Option<String>>,
+    exe: OnceCell<Option<String>>,
+    cmdline: OnceCell<Option<String>>,
+    uid: OnceCell<Option<u32>>,
+    parent_comm: OnceCell<Option<String>>,
+    cgroup: OnceCell<Option<String>>,
+}
+
+impl<'a> Facts<'a> {
+    pub fn new(process: &'a Process, proc_root: &'a Path) -> Self {
+        Self {
+            process,
+            proc_root,
+            comm: OnceCell::new(),
+            exe: OnceCell::new(),
+            cmdline: OnceCell::new(),
+            uid: OnceCell::new(),
+            parent_comm: OnceCell::new(),
+            cgroup: OnceCell::new(),
+        }
+    }
+
+    pub fn comm(&self) -> Option<&str> {
+        self.comm
+            .get_or_init(|| read_comm(self.process.pid, self.proc_root))
+            .as_deref()
+    }
+
+    pub fn exe(&self) -> Option<&str> {
+        self.exe
+            .get_or_init(|| self.process.exe(self.proc_root).ok())
+            .as_deref()
+    }
+
+    pub fn cmdline(&self) -> Option<&str> {
+        self.cmdline
+            .get_or_init(|| self.process.cmdline(self.proc_root).ok())
+            .as_deref()
+    }
+
+    pub fn uid(&self) -> Option<u32> {
+        *self
+            .uid
+            .get_or_init(|| self.process.uid(self.proc_root).ok())
+    }
+
+    pub fn parent_comm(&self) -> Option<&str> {
+        self.parent_comm
+            .get_or_init(|| {
+                let parent_pid = self.process.parent_pid(self.proc_root).ok()?;
+                read_comm(parent_pid, self.proc_root)
+            })
+            .as_deref()
+    }
+
+    pub fn cgroup(&self) -> Option<&str> {
+        self.cgroup
+            .get_or_init(|| self.process.cgroup(self.proc_root).ok())
+            .as_deref()
+    }
+}
+
+/// Reads the name of the process with the given PID, or `None` if it's gone
+pub fn read_comm(pid: u32, proc_root: &Path) -> Option<String> {
+    let comm = fs::read_to_string(proc_root.join(pid.to_string()).join("comm")).ok()?;
+
+    Some(comm.trim().into())
+}
+
 #[cfg(test)]
 mod tests {
     // We'll use the Process struct from procfs
@@ -128,7 +297,8 @@ mod tests {
     //
     // The reason we don't use `procfs` directly is
     // because our implementation is considerably leaner.
-    use procfs;
+
+    use std::path::Path;
 
     // Returns the Process representing the
     // process of the caller test
@@ -140,7 +310,7 @@ mod tests {
     #[test]
     fn comm() {
         let (mut buf, this) = this();
-        let comm = this.comm(&mut buf).unwrap();
+        let comm = this.comm(Path::new("/proc"), &mut buf).unwrap();
 
         // We'll now represent the current process using
         // the external `procfs` crate as well
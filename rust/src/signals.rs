@@ -0,0 +1,92 @@
+use std::{
+    mem,
+    sync::atomic::{AtomicBool, AtomicI8, Ordering},
+};
+
+use libc::{c_int, sigaction, SIGHUP, SIGUSR1, SIGUSR2};
+
+use crate::errno::errno;
+use crate::error::{Error, Result};
+
+/// Set by the SIGHUP handler, cleared once the configuration is re-read
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sighup(_: c_int) {
+    // Only async-signal-safe operations are allowed in here
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Incremented by SIGUSR1 and decremented by SIGUSR2, reset once applied
+static VERBOSITY_REQUESTS: AtomicI8 = AtomicI8::new(0);
+
+/// Saturates rather than wrapping around, so that a flood of SIGUSR1 can't make logging quieter.
+/// `fetch_update` is a lock-free compare-and-swap loop, so it's async-signal-safe.
+fn request_verbosity(step: i8) {
+    let _ = VERBOSITY_REQUESTS.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |requests| {
+        Some(requests.saturating_add(step))
+    });
+}
+
+extern "C" fn on_sigusr1(_: c_int) {
+    request_verbosity(1);
+}
+
+extern "C" fn on_sigusr2(_: c_int) {
+    request_verbosity(-1);
+}
+
+fn set_handler(signal: c_int, handler: extern "C" fn(c_int)) -> Result<()> {
+    // Safety: the all-zero byte pattern is a valid sigaction struct,
+    //         with an empty signal mask and no flags
+    let mut action: sigaction = unsafe { mem::zeroed() };
+    action.sa_sigaction = handler as libc::sighandler_t;
+
+    // Safety: `action` is a valid sigaction struct and the handler only touches an atomic
+    let ret_val = unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) };
+    if ret_val == -1 {
+        return Err(match errno() {
+            libc::EINVAL => Error::InvalidSignal,
+            _ => Error::UnknownSigaction,
+        });
+    }
+
+    Ok(())
+}
+
+/// Installs the handlers for the signals the daemon reacts to.
+/// SIGHUP requests the configuration to be reloaded,
+/// while SIGUSR1 and SIGUSR2 request more or less verbose logging.
+pub fn install_handlers() -> Result<()> {
+    set_handler(SIGHUP, on_sighup)?;
+    set_handler(SIGUSR1, on_sigusr1)?;
+    set_handler(SIGUSR2, on_sigusr2)
+}
+
+/// Returns true if a reload was requested since the last time this function was called
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+/// Returns how many levels more verbose logging was requested to be since the last time
+/// this function was called, which is negative if less verbose logging was requested
+pub fn take_verbosity_request() -> i8 {
+    VERBOSITY_REQUESTS.swap(0, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{on_sigusr1, on_sigusr2, take_verbosity_request};
+
+    #[test]
+    fn verbosity_requests_should_saturate() {
+        for _ in 0..200 {
+            on_sigusr1(0);
+        }
+        assert_eq!(take_verbosity_request(), i8::MAX);
+
+        for _ in 0..200 {
+            on_sigusr2(0);
+        }
+        assert_eq!(take_verbosity_request(), i8::MIN);
+    }
+}
@@ -0,0 +1,63 @@
+//! Helpers shared by the tests of several modules
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::utils;
+
+/// A fake procfs tree, removed when dropped
+pub struct ProcFixture {
+    pub root: PathBuf,
+}
+
+impl ProcFixture {
+    pub fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("bustd-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        Self { root }
+    }
+
+    /// Writes a file at `path`, relative to the root, creating its parent directories
+    pub fn write(&self, path: impl AsRef<Path>, contents: &str) {
+        let path = self.root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn add_process(
+        &self,
+        pid: u32,
+        comm: &str,
+        oom_score: i16,
+        oom_score_adj: i16,
+        rss_kib: i64,
+    ) {
+        let dir = Path::new(&pid.to_string()).to_owned();
+        let rss_pages = rss_kib * 1024 / utils::page_size().unwrap();
+
+        self.write(dir.join("comm"), &format!("{}\n", comm));
+        self.write(dir.join("cmdline"), &format!("/usr/bin/{}\0", comm));
+        self.write(dir.join("oom_score"), &format!("{}\n", oom_score));
+        self.write(dir.join("oom_score_adj"), &format!("{}\n", oom_score_adj));
+        self.write(
+            dir.join("statm"),
+            &format!("{} {} 0 0 0 0 0\n", rss_pages * 2, rss_pages),
+        );
+    }
+
+    pub fn config(&self) -> Config {
+        Config {
+            proc_root: self.root.clone(),
+            ..Config::default()
+        }
+    }
+}
+
+impl Drop for ProcFixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
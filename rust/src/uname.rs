@@ -3,6 +3,7 @@ use std::mem;
 
 use crate::error::{Error, Result};
 use crate::linux_version::LinuxVersion;
+use crate::log::{self, Event};
 use libc::{uname, utsname};
 
 pub struct Uname {
@@ -38,10 +39,12 @@ impl Uname {
         let release = release.to_str()?;
         let arch = arch.to_str()?;
 
-        println!("OS:           {}", sysname);
-        println!("Hostname:     {}", hostname);
-        println!("Version:      {}", release);
-        println!("Architecture: {}", arch);
+        log::emit(&Event::Startup {
+            sysname,
+            hostname,
+            release,
+            arch,
+        });
 
         Ok(())
     }
@@ -1,12 +1,16 @@
-use std::ffi::OsStr;
-use std::fs::File;
+use std::ffi::{CString, OsStr};
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
 use std::os::unix::prelude::OsStrExt;
 use std::path::Path;
+use std::time::Duration;
 use std::{ffi::CStr, mem, ptr, str};
 
 use libc::_SC_PAGESIZE;
-use libc::{getpgid, sysconf, EINVAL, EPERM, ESRCH};
-use libc::{getpwuid_r, passwd};
+use libc::{getgrnam_r, getpwnam_r, getpwuid_r, group, passwd};
+use libc::{getpgid, pollfd, sysconf, EINTR, EINVAL, EPERM, ESRCH};
 use memchr::memchr;
 
 use crate::errno::errno;
@@ -35,6 +39,27 @@ pub fn get_process_group(pid: i32) -> Result<i32> {
     Ok(pgid)
 }
 
+/// Waits for up to `timeout` for any of the given file descriptors to become ready,
+/// with entries with a negative file descriptor being ignored.
+/// Returns how many are ready, which is zero if the timeout elapsed or a signal arrived first.
+pub fn poll(fds: &mut [pollfd], timeout: Duration) -> Result<usize> {
+    // Rounding up, so that we don't spin while less than a millisecond is left
+    let timeout_ms = (timeout.as_micros() + 999).min(libc::c_int::MAX as u128 * 1000) / 1000;
+
+    // Safety: `fds` is a valid slice of pollfd structs, whose length we pass along
+    let ret_val = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout_ms as _) };
+    if ret_val == -1 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            // Interrupted by a signal, such as SIGHUP
+            Some(EINTR) => Ok(0),
+            _ => Err(err.into()),
+        };
+    }
+
+    Ok(ret_val as usize)
+}
+
 /// Checks if the program is running with sudo permissions.
 pub fn running_as_sudo() -> bool {
     effective_user_id() == 0
@@ -57,12 +82,15 @@ pub fn page_size() -> Result<i64> {
 
 /// Attempt to get the user's username from the system's password bank
 pub fn get_username() -> Option<String> {
+    get_user_name(effective_user_id())
+}
+
+/// Attempt to get the name of the user with the given ID from the system's password bank
+pub fn get_user_name(uid: u32) -> Option<String> {
     let mut buf = [0; 2048];
     let mut result = ptr::null_mut();
     let mut passwd: passwd = unsafe { mem::zeroed() };
 
-    let uid = effective_user_id();
-
     let getpwuid_r_code =
         unsafe { getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
 
@@ -77,6 +105,75 @@ pub fn get_username() -> Option<String> {
     None
 }
 
+/// Looks up the ID of the user with the given name
+pub fn get_user_id(name: &str) -> Result<u32> {
+    let unknown_user = || Error::Config {
+        reason: format!("unknown user `{}`", name),
+    };
+    let name = CString::new(name).map_err(|_| unknown_user())?;
+
+    let mut buf = [0; 2048];
+    let mut result = ptr::null_mut();
+    // Safety: the all-zero byte pattern is a valid passwd struct, which getpwnam_r fills in
+    let mut passwd: passwd = unsafe { mem::zeroed() };
+
+    // Safety: every pointer is valid, and `buf.len()` is the actual length of the buffer
+    let getpwnam_r_code = unsafe {
+        getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if getpwnam_r_code != 0 || result.is_null() {
+        return Err(unknown_user());
+    }
+
+    Ok(passwd.pw_uid)
+}
+
+/// Looks up the ID of the group with the given name
+pub fn get_group_id(name: &str) -> Result<u32> {
+    let unknown_group = || Error::Config {
+        reason: format!("unknown group `{}`", name),
+    };
+    let name = CString::new(name).map_err(|_| unknown_group())?;
+
+    let mut buf = [0; 2048];
+    let mut result = ptr::null_mut();
+    // Safety: the all-zero byte pattern is a valid group struct, which getgrnam_r fills in
+    let mut group: group = unsafe { mem::zeroed() };
+
+    // Safety: every pointer is valid, and `buf.len()` is the actual length of the buffer
+    let getgrnam_r_code = unsafe {
+        getgrnam_r(
+            name.as_ptr(),
+            &mut group,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if getgrnam_r_code != 0 || result.is_null() {
+        return Err(unknown_group());
+    }
+
+    Ok(group.gr_gid)
+}
+
+/// Binds a non-blocking Unix socket, replacing the one a previous run may have left behind
+pub fn bind_unix_socket(path: &Path) -> io::Result<UnixListener> {
+    if matches!(fs::symlink_metadata(path), Ok(metadata) if metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+
+    Ok(listener)
+}
+
 fn bytes_until_first_nil(buf: &[u8]) -> &[u8] {
     let first_nul_idx = memchr(0, buf).unwrap_or(buf.len());
 